@@ -0,0 +1,312 @@
+use poem::http::{header, StatusCode};
+use poem::web::{Data, Path};
+use poem::{delete, get, handler, Response, Route};
+use serde_json::Value;
+
+use crate::auth::error::missing_admin;
+use crate::db::metrics::DbStats;
+use crate::db::migrations::{MigrationPlan, MIGRATIONS};
+use crate::db::{DbHandle, TableInfo};
+use crate::error::{AppError, Context};
+use crate::response::GenericResponse;
+
+
+#[poem_grants::protect("ADMIN", error = missing_admin)]
+#[handler]
+async fn list_tables(db: Data<&DbHandle>) -> Result<GenericResponse<Vec<TableInfo>>, AppError> {
+    let db_ref = db.read().await;
+
+    Ok(GenericResponse::<Vec<TableInfo>>{
+        message: None,
+        status_code_u16: StatusCode::OK.as_u16(),
+        data: Some(db_ref.list_tables())
+    })
+}
+
+/// Drops the table with its records for good, see [`crate::db::Db::drop_table`].
+#[poem_grants::protect("ADMIN", error = missing_admin)]
+#[handler]
+async fn drop_table(Path(name): Path<String>, db: Data<&DbHandle>) -> Result<GenericResponse<Value>, AppError> {
+    let mut db_ref = db.write().await;
+    let dropped = db_ref
+        .drop_table(name)
+        .await
+        .context("Dropping table")?;
+
+    if !dropped {
+        return Err(AppError::NotFound)
+    }
+
+    Ok(GenericResponse::<Value>{
+        message: Some("Table dropped successfully".to_string()),
+        status_code_u16: StatusCode::OK.as_u16(),
+        data: None
+    })
+}
+
+/// Exports the table's live records as CSV, see [`crate::db::Db::export_csv`].
+#[poem_grants::protect("ADMIN", error = missing_admin)]
+#[handler]
+async fn export_table(Path(name): Path<String>, db: Data<&DbHandle>) -> Result<Response, AppError> {
+    let db_ref = db.read().await;
+    let mut body = vec![];
+
+    db_ref
+        .export_csv(name, &mut body)
+        .context("Exporting table")?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/csv")
+        .body(body))
+}
+
+/// Migrations still pending for this process' tables and what they would
+/// change, see [`crate::db::Db::plan_migrations`].
+#[poem_grants::protect("ADMIN", error = missing_admin)]
+#[handler]
+async fn plan_migrations(db: Data<&DbHandle>) -> Result<GenericResponse<MigrationPlan>, AppError> {
+    let db_ref = db.read().await;
+
+    Ok(GenericResponse::<MigrationPlan>{
+        message: None,
+        status_code_u16: StatusCode::OK.as_u16(),
+        data: Some(db_ref.plan_migrations(MIGRATIONS, 3))
+    })
+}
+
+/// Row counts, storage and operation counters, see [`crate::db::Db::stats`].
+#[poem_grants::protect("ADMIN", error = missing_admin)]
+#[handler]
+async fn stats(db: Data<&DbHandle>) -> Result<GenericResponse<DbStats>, AppError> {
+    let db_ref = db.read().await;
+
+    Ok(GenericResponse::<DbStats>{
+        message: None,
+        status_code_u16: StatusCode::OK.as_u16(),
+        data: Some(db_ref.stats().await)
+    })
+}
+
+pub fn admin_routes() -> Route {
+    Route::new()
+        .at("/tables", get(list_tables))
+        .at("/tables/:name", delete(drop_table))
+        .at("/tables/:name/export.csv", get(export_table))
+        .at("/migrations/plan", get(plan_migrations))
+        .at("/stats", get(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::Method;
+    use poem::Endpoint;
+    use serde_json::json;
+
+    use crate::test::{async_run_with_file_create_teardown, ApiTestClient, PermissionCase, TEST_PERMISSION};
+
+    use super::*;
+
+    async fn init_client(file_name: String) -> ApiTestClient<impl Endpoint> {
+        let routes = Route::new().nest("/admin", admin_routes());
+
+        ApiTestClient::init(routes, file_name.as_str()).await
+    }
+
+    #[tokio::test]
+    async fn test_list_tables() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    db.add_table("item".to_string(), true).await.unwrap();
+                    db.insert_or_update("item".to_string(), 1, json!({"id": 1})).await.unwrap();
+                }
+
+                let permission_sets: &[Option<&[&str]>] = &[None, Some(&[TEST_PERMISSION]), Some(&["ADMIN"])];
+                let cases = [
+                    PermissionCase {
+                        method: Method::GET,
+                        uri: "/admin/tables",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    }
+                ];
+                test_client.assert_permission_matrix(permission_sets, &cases).await;
+
+                let jwt_data = test_client.jwt_manager.create_token_data("admin".to_string(), vec!["ADMIN".to_string()]);
+                let token = test_client.jwt_manager.encode(jwt_data).unwrap();
+                let response = test_client.client.get("/admin/tables")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .send()
+                    .await;
+
+                response.assert_status_is_ok();
+                let json = response.json().await;
+                let tables = json.value().object().get("data").array();
+                let item = tables.iter().find(|table| table.object().get("name").string() == "item").unwrap();
+                item.object().get("rows").assert_i64(1);
+                item.object().get("next_id").assert_i64(1);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_drop_table() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    db.add_table("item".to_string(), true).await.unwrap();
+                    db.insert_or_update("item".to_string(), 1, json!({"id": 1})).await.unwrap();
+                }
+
+                let permission_sets: &[Option<&[&str]>] = &[None, Some(&[TEST_PERMISSION]), Some(&["ADMIN"])];
+                let cases = [
+                    PermissionCase {
+                        method: Method::DELETE,
+                        uri: "/admin/tables/item",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    },
+                    PermissionCase {
+                        method: Method::DELETE,
+                        uri: "/admin/tables/item",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::NOT_FOUND]
+                    }
+                ];
+                test_client.assert_permission_matrix(permission_sets, &cases).await;
+
+                assert!(test_client.db.read().await.count("item".to_string()).is_none());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_export_table() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    db.add_table("item".to_string(), true).await.unwrap();
+                    db.insert_or_update("item".to_string(), 1, json!({"id": 1, "name": "a"})).await.unwrap();
+                }
+
+                let permission_sets: &[Option<&[&str]>] = &[None, Some(&[TEST_PERMISSION]), Some(&["ADMIN"])];
+                let cases = [
+                    PermissionCase {
+                        method: Method::GET,
+                        uri: "/admin/tables/item/export.csv",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    },
+                    PermissionCase {
+                        method: Method::GET,
+                        uri: "/admin/tables/missing/export.csv",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::NOT_FOUND]
+                    }
+                ];
+                test_client.assert_permission_matrix(permission_sets, &cases).await;
+
+                let jwt_data = test_client.jwt_manager.create_token_data("admin".to_string(), vec!["ADMIN".to_string()]);
+                let token = test_client.jwt_manager.encode(jwt_data).unwrap();
+                let response = test_client.client
+                    .get("/admin/tables/item/export.csv")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .send()
+                    .await;
+
+                response.assert_status_is_ok();
+                response.assert_content_type("text/csv");
+                let body = response.0.into_body().into_string().await.unwrap();
+                assert!(body.starts_with("id,"), "{}", body);
+                assert_eq!(body.lines().count(), 2);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_plan_migrations() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+
+                let permission_sets: &[Option<&[&str]>] = &[None, Some(&[TEST_PERMISSION]), Some(&["ADMIN"])];
+                let cases = [
+                    PermissionCase {
+                        method: Method::GET,
+                        uri: "/admin/migrations/plan",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    }
+                ];
+                test_client.assert_permission_matrix(permission_sets, &cases).await;
+
+                let jwt_data = test_client.jwt_manager.create_token_data("admin".to_string(), vec!["ADMIN".to_string()]);
+                let token = test_client.jwt_manager.encode(jwt_data).unwrap();
+                let response = test_client.client
+                    .get("/admin/migrations/plan")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .send()
+                    .await;
+
+                response.assert_status_is_ok();
+                let json = response.json().await;
+                let data = json.value().object().get("data").object();
+                data.get("tables").array().assert_is_empty();
+                data.get("estimated_micros").i64();
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    db.add_table("item".to_string(), true).await.unwrap();
+                    db.insert_or_update("item".to_string(), 1, json!({"id": 1})).await.unwrap();
+                }
+
+                let permission_sets: &[Option<&[&str]>] = &[None, Some(&[TEST_PERMISSION]), Some(&["ADMIN"])];
+                let cases = [
+                    PermissionCase {
+                        method: Method::GET,
+                        uri: "/admin/stats",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    }
+                ];
+                test_client.assert_permission_matrix(permission_sets, &cases).await;
+
+                let jwt_data = test_client.jwt_manager.create_token_data("admin".to_string(), vec!["ADMIN".to_string()]);
+                let token = test_client.jwt_manager.encode(jwt_data).unwrap();
+                let response = test_client.client
+                    .get("/admin/stats")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .send()
+                    .await;
+
+                response.assert_status_is_ok();
+                let json = response.json().await;
+                let data = json.value().object().get("data").object();
+                let item = data.get("tables").object().get("item").object();
+                item.get("rows").assert_i64(1);
+                item.get("soft_deleted").assert_i64(0);
+                data.get("operations").object().get("upserts").assert_i64(1);
+            }
+        }).await;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use poem::Request;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+
+/// Header clients answer a challenge with.
+pub const CHALLENGE_RESPONSE_HEADER: &str = "X-Challenge-Response";
+
+/// Unanswered nonces [`ProofOfWork`] keeps at most before dropping the oldest.
+pub const DEFAULT_MAX_ISSUED: usize = 10_000;
+
+/// When clients start getting challenged, set under `challenge` in the
+/// config. `difficulty` only applies to [`ProofOfWork`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ChallengeConfig {
+    pub max_failures: u32,
+    pub window_secs: u64,
+    pub difficulty: u32
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            window_secs: 900,
+            difficulty: 16
+        }
+    }
+}
+
+/// A check an address has to pass after too many failed logins or
+/// registrations. A captcha provider such as hCaptcha or Turnstile would
+/// return its site key from `issue` and post the answer to its siteverify
+/// endpoint in `verify`.
+pub trait Challenge: Send + Sync {
+    /// What the client needs to solve the challenge.
+    fn issue(&self) -> Value;
+
+    /// Checks the answer sent in [`CHALLENGE_RESPONSE_HEADER`].
+    fn verify<'a>(&'a self, answer: &'a str, remote_addr: &'a str) -> BoxFuture<'a, bool>;
+}
+
+/// Asks for a suffix that gives `sha256("{nonce}:{suffix}")` at least
+/// `difficulty` leading zero bits. Each nonce can be answered once, within
+/// `ttl` of being issued, and while it is among the `max_issued` most recent.
+pub struct ProofOfWork {
+    difficulty: u32,
+    ttl: Duration,
+    max_issued: usize,
+    issued: Mutex<HashMap<String, Instant>>
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: u32) -> Self {
+        Self {
+            difficulty,
+            ttl: Duration::from_secs(300),
+            max_issued: DEFAULT_MAX_ISSUED,
+            issued: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub fn with_max_issued(mut self, max_issued: usize) -> Self {
+        self.max_issued = max_issued;
+        self
+    }
+}
+
+/// Number of leading zero bits of `sha256(answer)`.
+pub fn leading_zero_bits(answer: &str) -> u32 {
+    let mut bits = 0;
+
+    for byte in Sha256::digest(answer.as_bytes()) {
+        bits += byte.leading_zeros();
+
+        if byte != 0 {
+            break
+        }
+    }
+
+    bits
+}
+
+impl Challenge for ProofOfWork {
+    fn issue(&self) -> Value {
+        let nonce = Uuid::new_v4().simple().to_string();
+
+        let mut issued = self.issued.lock().unwrap();
+        issued.retain(|_, at| at.elapsed() < self.ttl);
+
+        // Clients can ask for challenges without ever answering them
+        if issued.len() >= self.max_issued {
+            let oldest = issued.iter().min_by_key(|(_, at)| **at).map(|(nonce, _)| nonce.clone());
+
+            if let Some(oldest) = oldest {
+                issued.remove(&oldest);
+            }
+        }
+
+        issued.insert(nonce.clone(), Instant::now());
+
+        json!({ "type": "proof_of_work", "nonce": nonce, "difficulty": self.difficulty })
+    }
+
+    fn verify<'a>(&'a self, answer: &'a str, _: &'a str) -> BoxFuture<'a, bool> {
+        async move {
+            let Some((nonce, _)) = answer.split_once(':') else {
+                return false
+            };
+
+            let Some(issued_at) = self.issued.lock().unwrap().remove(nonce) else {
+                return false
+            };
+
+            issued_at.elapsed() < self.ttl && leading_zero_bits(answer) >= self.difficulty
+        }.boxed()
+    }
+}
+
+/// Counts failed attempts per client ip and challenges the ones over the
+/// limit. A passed challenge only buys a single further attempt.
+#[derive(Clone)]
+pub struct ChallengeGuard {
+    challenge: Arc<dyn Challenge>,
+    max_failures: u32,
+    window: Duration,
+    failures: Arc<Mutex<HashMap<String, (u32, Instant)>>>
+}
+
+impl ChallengeGuard {
+    pub fn new<C: Challenge + 'static>(challenge: C, config: &ChallengeConfig) -> Self {
+        Self {
+            challenge: Arc::new(challenge),
+            max_failures: config.max_failures,
+            window: Duration::from_secs(config.window_secs),
+            failures: Arc::new(Mutex::new(HashMap::new()))
+        }
+    }
+
+    fn client_key(req: &Request) -> String {
+        match req.remote_addr().as_socket_addr() {
+            Some(addr) => addr.ip().to_string(),
+            None => req.remote_addr().to_string()
+        }
+    }
+
+    fn is_over_limit(&self, key: &str) -> bool {
+        self.failures
+            .lock()
+            .unwrap()
+            .get(key)
+            .is_some_and(|(count, since)| *count >= self.max_failures && since.elapsed() < self.window)
+    }
+
+    /// Fails with [`AppError::ChallengeRequired`] when the client is over the
+    /// limit and did not answer a challenge correctly.
+    pub async fn check(&self, req: &Request) -> Result<(), AppError> {
+        let key = Self::client_key(req);
+
+        if !self.is_over_limit(&key) {
+            return Ok(())
+        }
+
+        if let Some(answer) = req.header(CHALLENGE_RESPONSE_HEADER) {
+            if self.challenge.verify(answer, &key).await {
+                return Ok(())
+            }
+        }
+
+        Err(AppError::ChallengeRequired(self.challenge.issue()))
+    }
+
+    /// Counts a failure for the client, starting a new window if its last one
+    /// is over. Windows that are over are dropped for every other client too,
+    /// so clients that stop failing are not kept around.
+    pub fn record_failure(&self, req: &Request) {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, (_, since)| since.elapsed() < self.window);
+        failures.entry(Self::client_key(req)).or_insert((0, Instant::now())).0 += 1;
+    }
+
+    pub fn reset(&self, req: &Request) {
+        self.failures.lock().unwrap().remove(&Self::client_key(req));
+    }
+}
+
+impl Default for ChallengeGuard {
+    fn default() -> Self {
+        let config = ChallengeConfig::default();
+
+        Self::new(ProofOfWork::new(config.difficulty), &config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::solve_proof_of_work;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_proof_of_work() {
+        let challenge = ProofOfWork::new(8);
+        let answer = solve_proof_of_work(&challenge.issue());
+
+        assert!(!challenge.verify("no-separator", "").await);
+        assert!(!challenge.verify(&format!("unknown:{}", answer), "").await);
+        assert!(challenge.verify(&answer, "").await);
+        assert!(!challenge.verify(&answer, "").await, "nonces are single use");
+    }
+
+    #[tokio::test]
+    async fn test_issued_nonces_are_capped() {
+        let challenge = ProofOfWork::new(4).with_max_issued(2);
+        let answers: Vec<String> = (0..3).map(|_| solve_proof_of_work(&challenge.issue())).collect();
+
+        assert_eq!(challenge.issued.lock().unwrap().len(), 2);
+        assert!(!challenge.verify(&answers[0], "").await, "the oldest nonce is evicted");
+        assert!(challenge.verify(&answers[1], "").await);
+        assert!(challenge.verify(&answers[2], "").await);
+    }
+
+    #[test]
+    fn test_record_failure_prunes_expired_windows() {
+        let config = ChallengeConfig { window_secs: 0, ..ChallengeConfig::default() };
+        let guard = ChallengeGuard::new(ProofOfWork::new(config.difficulty), &config);
+        guard.failures.lock().unwrap().insert("10.0.0.1".to_string(), (3, Instant::now()));
+
+        let req = Request::default();
+        guard.record_failure(&req);
+
+        let failures = guard.failures.lock().unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures.get(&ChallengeGuard::client_key(&req)).map(|(count, _)| *count), Some(1));
+    }
+}
\ No newline at end of file
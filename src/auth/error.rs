@@ -0,0 +1,106 @@
+use poem::error::ResponseError;
+use poem::http::{header, StatusCode};
+use poem::{IntoResponse, Response};
+use poem_grants::error::AccessError;
+use serde_json::{json, Value};
+
+use super::jwt::TokenError;
+use crate::response::GenericResponse;
+
+
+/// Why a request was refused. Missing or unusable tokens are 401s, tokens
+/// lacking a permission are 403s, and each carries a bearer challenge.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    MissingToken,
+    InvalidToken(TokenError),
+    InsufficientScope(Vec<String>)
+}
+
+impl AuthError {
+    /// Recovers the auth failure behind an error, including the ones raised by
+    /// poem-grants before a handler runs.
+    pub fn from_error(err: &poem::Error) -> Option<Self> {
+        if let Some(auth_error) = err.downcast_ref::<AuthError>() {
+            return Some(auth_error.clone())
+        }
+
+        match err.downcast_ref::<AccessError>()? {
+            AccessError::UnauthorizedRequest => Some(Self::MissingToken),
+            AccessError::ForbiddenRequest => Some(Self::InsufficientScope(vec![]))
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MissingToken => "missing_token",
+            Self::InvalidToken(err) => err.code(),
+            Self::InsufficientScope(_) => "insufficient_scope"
+        }
+    }
+
+    /// The `WWW-Authenticate` value, following RFC 6750.
+    pub fn challenge(&self) -> String {
+        match self {
+            Self::MissingToken => "Bearer".to_string(),
+            Self::InvalidToken(err) => format!("Bearer error=\"invalid_token\", error_description=\"{}\"", err),
+            Self::InsufficientScope(required) => format!("Bearer error=\"insufficient_scope\", scope=\"{}\"", required.join(" "))
+        }
+    }
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingToken => write!(f, "A bearer token is required"),
+            Self::InvalidToken(err) => write!(f, "Invalid token: {}", err),
+            Self::InsufficientScope(required) => write!(f, "Missing required permissions: {}", required.join(", "))
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl ResponseError for AuthError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::MissingToken | Self::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            Self::InsufficientScope(_) => StatusCode::FORBIDDEN
+        }
+    }
+
+    fn as_response(&self) -> Response {
+        let mut data = json!({ "code": self.code() });
+
+        if let Self::InsufficientScope(required) = self {
+            data["required_permissions"] = json!(required);
+        }
+
+        let mut response = GenericResponse::<Value>{
+            message: Some(self.to_string()),
+            status_code_u16: self.status().as_u16(),
+            data: Some(data)
+        }.into_response();
+
+        if let Ok(value) = self.challenge().parse() {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, value);
+        }
+
+        response
+    }
+}
+
+/// Error for handlers protected with `#[protect("MUTATE", error = ...)]`.
+pub fn missing_mutate() -> poem::Error {
+    AuthError::InsufficientScope(vec!["MUTATE".to_string()]).into()
+}
+
+/// Error for handlers protected with `#[protect("ADMIN", error = ...)]`.
+pub fn missing_admin() -> poem::Error {
+    AuthError::InsufficientScope(vec!["ADMIN".to_string()]).into()
+}
+
+/// Error for handlers protected with `#[protect("PROVISION", error = ...)]`.
+pub fn missing_provision() -> poem::Error {
+    AuthError::InsufficientScope(vec!["PROVISION".to_string()]).into()
+}
\ No newline at end of file
@@ -1,27 +1,86 @@
 use chrono::{Duration, Utc};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
-use poem::http::StatusCode;
 use serde::{Deserialize, Serialize};
 
 
+/// Seconds a token may be past `exp` or before `nbf` and still be accepted,
+/// covering clock skew between the hosts minting and checking tokens.
+pub const DEFAULT_LEEWAY_SECS: u64 = 60;
+
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, PartialEq, Eq)]
 pub struct JwtData {
     pub username: String,
     pub permissions: Vec<String>,
-    exp: i64
+    exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nbf: Option<i64>
 }
 
 impl JwtData {
     pub fn new(username: String, permissions: Vec<String>, token_duration: Duration) -> Self {
+        let now = Utc::now();
+
         Self {
             username,
             permissions,
-            exp: (Utc::now() + token_duration).timestamp()
+            exp: (now + token_duration).timestamp(),
+            nbf: Some(now.timestamp())
         }
     }
+}
 
-    pub fn is_expired(&self) -> bool {
-        self.exp <= Utc::now().timestamp()
+/// Why a bearer token was rejected, reported to clients as the `code` of the
+/// error body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenError {
+    Expired,
+    NotYetValid,
+    Malformed,
+    BadSignature,
+    WrongAudience,
+    Invalid
+}
+
+impl TokenError {
+    fn from_jwt_error(err: &jsonwebtoken::errors::Error) -> Self {
+        use jsonwebtoken::errors::ErrorKind;
+
+        match err.kind() {
+            ErrorKind::ExpiredSignature => Self::Expired,
+            ErrorKind::ImmatureSignature => Self::NotYetValid,
+            ErrorKind::InvalidSignature => Self::BadSignature,
+            ErrorKind::InvalidAudience => Self::WrongAudience,
+            ErrorKind::InvalidToken
+                | ErrorKind::Base64(_)
+                | ErrorKind::Json(_)
+                | ErrorKind::Utf8(_)
+                | ErrorKind::MissingRequiredClaim(_) => Self::Malformed,
+            _ => Self::Invalid
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Expired => "token_expired",
+            Self::NotYetValid => "token_not_yet_valid",
+            Self::Malformed => "token_malformed",
+            Self::BadSignature => "token_bad_signature",
+            Self::WrongAudience => "token_wrong_audience",
+            Self::Invalid => "invalid_token"
+        }
+    }
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Expired => write!(f, "The token has expired"),
+            Self::NotYetValid => write!(f, "The token is not valid yet"),
+            Self::Malformed => write!(f, "The token is malformed"),
+            Self::BadSignature => write!(f, "The token signature does not match"),
+            Self::WrongAudience => write!(f, "The token is meant for another audience"),
+            Self::Invalid => write!(f, "The token could not be verified")
+        }
     }
 }
 
@@ -29,7 +88,8 @@ impl JwtData {
 pub struct Manager {
     encoding_key: EncodingKey,
     decoding_key: DecodingKey,
-    expiration: Duration
+    expiration: Duration,
+    leeway_secs: u64
 }
 
 impl Manager {
@@ -41,37 +101,68 @@ impl Manager {
         Self {
             encoding_key,
             decoding_key,
-            expiration
+            expiration,
+            leeway_secs: DEFAULT_LEEWAY_SECS
         }
     }
 
+    pub fn with_leeway(mut self, leeway_secs: u64) -> Self {
+        self.leeway_secs = leeway_secs;
+
+        self
+    }
+
     pub fn create_token_data(&self, username: String, permissions: Vec<String>) -> JwtData {
         JwtData::new(username, permissions, self.expiration)
     }
 
-    pub fn encode(&self, data: JwtData) -> poem::Result<String> {
+    pub fn encode(&self, data: JwtData) -> jsonwebtoken::errors::Result<String> {
         jsonwebtoken::encode(&Header::default(), &data, &self.encoding_key)
-            .map_err(|_| 
-                poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
-            )
     }
 
-    pub fn decode(&self, token: &str) -> poem::Result<JwtData> {
-        let result = jsonwebtoken::decode::<JwtData>(token, &self.decoding_key, &Validation::default())
+    pub fn decode(&self, token: &str) -> Result<JwtData, TokenError> {
+        let mut validation = Validation::default();
+        validation.leeway = self.leeway_secs;
+        validation.validate_nbf = true;
+
+        jsonwebtoken::decode::<JwtData>(token, &self.decoding_key, &validation)
             .map(|x| x.claims)
-            .map_err(|_| poem::Error::from_status(StatusCode::UNAUTHORIZED));
+            .map_err(|err| TokenError::from_jwt_error(&err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_error_kinds() {
+        let manager = Manager::init("secret".to_string(), 24);
+        let token = manager.encode(manager.create_token_data("user".to_string(), vec![])).unwrap();
+        assert!(manager.decode(&token).is_ok());
 
-        
-        if let Ok(data) = result {
-            if data.exp <= Utc::now().timestamp() {
-                return Err(poem::Error::from_status(StatusCode::UNAUTHORIZED))
-            }
+        let expired = JwtData::new("user".to_string(), vec![], Duration::try_hours(-1).unwrap());
+        let expired_token = manager.encode(expired).unwrap();
+        assert_eq!(manager.decode(&expired_token), Err(TokenError::Expired));
 
-            return Ok(data)
-        } 
+        let other = Manager::init("other".to_string(), 24);
+        assert_eq!(other.decode(&token), Err(TokenError::BadSignature));
 
-        result
+        assert_eq!(manager.decode("not-a-token"), Err(TokenError::Malformed));
     }
-}
 
+    #[test]
+    fn test_decode_leeway_and_nbf() {
+        let manager = Manager::init("secret".to_string(), 24).with_leeway(30);
+        let now = Utc::now().timestamp();
+        let token = |exp: i64, nbf: Option<i64>| {
+            let data = JwtData { username: "user".to_string(), permissions: vec![], exp, nbf };
+            manager.encode(data).unwrap()
+        };
 
+        assert!(manager.decode(&token(now - 10, None)).is_ok());
+        assert_eq!(manager.decode(&token(now - 300, None)), Err(TokenError::Expired));
+        assert!(manager.decode(&token(now + 3600, Some(now + 10))).is_ok());
+        assert_eq!(manager.decode(&token(now + 3600, Some(now + 300))), Err(TokenError::NotYetValid));
+    }
+}
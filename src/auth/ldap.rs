@@ -0,0 +1,221 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use ldap3::{dn_escape, LdapConnAsync, LdapError, Scope, SearchEntry};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::model::User;
+use crate::auth::provider::AuthProvider;
+use crate::auth::route::USER_TABLE_NAME;
+use crate::db::DbHandle;
+use crate::error::{AppError, Context};
+
+
+/// Result code of a bind with a wrong dn or password.
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Grants `permissions` to members of `group`, matched case-insensitively
+/// against the values of the user's group attribute.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupRule {
+    pub group: String,
+    pub permissions: Vec<String>
+}
+
+/// Where and how [`LdapProvider`] binds, set under `auth_provider` in the
+/// config with `"type": "ldap"`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LdapConfig {
+    pub url: String,
+    /// Dn users bind as, with `{username}` replaced by the escaped username.
+    pub user_dn: String,
+    /// Attribute of the user's entry listing their groups.
+    pub group_attribute: String,
+    pub group_rules: Vec<GroupRule>,
+    /// Granted to every user on top of their group permissions.
+    pub default_permissions: Vec<String>
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        Self {
+            url: "ldap://localhost:389".to_string(),
+            user_dn: "uid={username},ou=people,dc=example,dc=com".to_string(),
+            group_attribute: "memberOf".to_string(),
+            group_rules: vec![],
+            default_permissions: vec![]
+        }
+    }
+}
+
+impl LdapConfig {
+    pub fn user_dn(&self, username: &str) -> String {
+        self.user_dn.replace("{username}", &dn_escape(username))
+    }
+
+    /// Permissions of a member of `groups`, without duplicates.
+    pub fn permissions(&self, groups: &[String]) -> Vec<String> {
+        let granted = self.group_rules
+            .iter()
+            .filter(|rule| groups.iter().any(|group| group.eq_ignore_ascii_case(&rule.group)))
+            .flat_map(|rule| &rule.permissions);
+
+        let mut permissions: Vec<String> = vec![];
+
+        for permission in self.default_permissions.iter().chain(granted) {
+            if !permissions.contains(permission) {
+                permissions.push(permission.clone());
+            }
+        }
+
+        permissions
+    }
+}
+
+/// Checks logins by binding to an LDAP or Active Directory server as the
+/// user. A user's first login provisions a local [`User`] with a random
+/// password, so the account cannot log in through [`super::provider::LocalProvider`].
+pub struct LdapProvider {
+    config: LdapConfig
+}
+
+impl LdapProvider {
+    pub fn new(config: LdapConfig) -> Self {
+        Self { config }
+    }
+
+    /// The user's groups, or `None` if the server rejects the credentials.
+    async fn bind(&self, username: &str, password: &str) -> Result<Option<Vec<String>>, LdapError> {
+        let (conn, mut ldap) = LdapConnAsync::new(&self.config.url).await?;
+
+        tokio::spawn(async move {
+            if let Err(err) = conn.drive().await {
+                println!("Ldap connection error: {}", err);
+            }
+        });
+
+        let dn = self.config.user_dn(username);
+        let bind = ldap.simple_bind(&dn, password).await?;
+
+        if bind.rc == INVALID_CREDENTIALS {
+            return Ok(None)
+        }
+
+        bind.success()?;
+
+        let attribute = self.config.group_attribute.as_str();
+        let (entries, _) = ldap
+            .search(&dn, Scope::Base, "(objectClass=*)", vec![attribute])
+            .await?
+            .success()?;
+
+        let groups = entries
+            .into_iter()
+            .map(SearchEntry::construct)
+            .flat_map(|entry| entry.attrs.get(attribute).cloned().unwrap_or_default())
+            .collect();
+
+        ldap.unbind().await?;
+
+        Ok(Some(groups))
+    }
+}
+
+/// The local record of `username` with `permissions`, created if missing.
+async fn provision(db: &DbHandle, username: &str, permissions: Vec<String>) -> Result<User, AppError> {
+    let mut db_ref = db.write().await;
+
+    let existing = db_ref
+        .table::<User>(USER_TABLE_NAME)
+        .find_by_value("username", username.to_string())
+        .and_then(|x| x.first().cloned());
+
+    if let Some(user) = existing {
+        return Ok(User { permissions, ..user })
+    }
+
+    db_ref
+        .transaction(|tx| {
+            let id = tx.get_increment_last_id(USER_TABLE_NAME.to_string())?;
+            let to_insert = User::new(id, username.to_string(), Uuid::new_v4().to_string(), permissions);
+            tx.insert_or_update(USER_TABLE_NAME.to_string(), id, to_insert)
+        })
+        .await
+        .context("Provisioning ldap user")
+}
+
+impl AuthProvider for LdapProvider {
+    fn authenticate<'a>(&'a self, db: &'a DbHandle, username: &'a str, password: &'a str) -> BoxFuture<'a, Result<Option<User>, AppError>> {
+        async move {
+            // An empty password makes an anonymous bind, which servers accept
+            if password.is_empty() {
+                return Ok(None)
+            }
+
+            let groups = self.bind(username, password)
+                .await
+                .map_err(|err| AppError::Internal(vec!["Binding to ldap".to_string(), err.to_string()]))?;
+
+            match groups {
+                Some(groups) => Ok(Some(provision(db, username, self.config.permissions(&groups)).await?)),
+                None => Ok(None)
+            }
+        }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Db;
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    #[test]
+    fn test_user_dn_and_permissions() {
+        let config = LdapConfig {
+            group_rules: vec![
+                GroupRule { group: "cn=editors,ou=groups,dc=example,dc=com".to_string(), permissions: vec!["MUTATE".to_string()] },
+                GroupRule { group: "cn=admins,ou=groups,dc=example,dc=com".to_string(), permissions: vec!["ADMIN".to_string(), "MUTATE".to_string()] }
+            ],
+            default_permissions: vec!["READ".to_string()],
+            ..LdapConfig::default()
+        };
+
+        assert_eq!(config.user_dn("jane,doe"), "uid=jane\\2cdoe,ou=people,dc=example,dc=com");
+        assert_eq!(config.permissions(&[]), vec!["READ".to_string()]);
+        assert_eq!(
+            config.permissions(&["CN=Admins,OU=Groups,DC=example,DC=com".to_string(), "cn=editors,ou=groups,dc=example,dc=com".to_string()]),
+            vec!["READ".to_string(), "MUTATE".to_string(), "ADMIN".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_provision_once() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = Db::init(file_name).await.unwrap();
+                db.add_table(USER_TABLE_NAME.to_string(), false).await.unwrap();
+                let db = DbHandle::new(db);
+
+                let first = provision(&db, "jane", vec!["MUTATE".to_string()]).await.unwrap();
+                let second = provision(&db, "jane", vec!["ADMIN".to_string()]).await.unwrap();
+
+                assert_eq!(first.id, second.id);
+                assert_eq!(second.permissions, vec!["ADMIN".to_string()]);
+                assert!(!first.password.is_empty());
+                assert_eq!(db.read().await.find_all::<User>(USER_TABLE_NAME.to_string()).unwrap().len(), 1);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_rejects_empty_password() {
+        let db = DbHandle::new(Db::in_memory());
+        let provider = LdapProvider::new(LdapConfig::default());
+
+        assert!(provider.authenticate(&db, "jane", "").await.unwrap().is_none());
+    }
+}
\ No newline at end of file
@@ -1,6 +1,7 @@
-use poem::{http::{self, StatusCode}, Endpoint, Error, Middleware, Request, Result};
+use poem::{http, Endpoint, Middleware, Request, Result};
 use poem_grants::authorities::AttachAuthorities;
 
+use super::error::AuthError;
 use super::jwt;
 
 #[derive(Clone)]
@@ -32,11 +33,12 @@ impl<E: Endpoint> Endpoint for JwtMiddlewareImpl<E> {
             .filter(|value| value.starts_with("Bearer "))
             .map(|value| &value[7..])
         {
-            let jwt_data = self.manager.decode(value)?;
-
-            if jwt_data.is_expired() {
-                return Err(Error::from_status(StatusCode::UNAUTHORIZED))
-            }
+            let jwt_data = self.manager
+                .decode(value)
+                .map_err(|err| {
+                    println!("Rejected token for {} {}: {}", req.method(), req.uri(), err);
+                    AuthError::InvalidToken(err)
+                })?;
 
             req.attach(jwt_data.permissions);
         }
@@ -1,4 +1,9 @@
+pub mod challenge;
+pub mod error;
 pub mod jwt;
+pub mod ldap;
 pub mod middleware;
+pub mod policy;
+pub mod provider;
 pub mod route;
 pub mod model;
\ No newline at end of file
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+
+/// Rules usernames and passwords must follow, set under `credential_policy`
+/// in the config. Usernames may always contain ascii letters and digits.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CredentialPolicy {
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    pub username_allowed_symbols: String,
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    pub banned_passwords: Vec<String>
+}
+
+impl Default for CredentialPolicy {
+    fn default() -> Self {
+        Self {
+            username_min_length: 3,
+            username_max_length: 32,
+            username_allowed_symbols: "_.-".to_string(),
+            password_min_length: 8,
+            password_max_length: 128,
+            banned_passwords: ["password", "12345678", "123456789", "qwertyuiop", "iloveyou", "letmein1"]
+                .map(String::from)
+                .to_vec()
+        }
+    }
+}
+
+/// A single rule a credential broke, returned to the client as is.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub field: String,
+    pub rule: String,
+    pub message: String
+}
+
+impl Violation {
+    fn new(field: &str, rule: &str, message: String) -> Self {
+        Self { field: field.to_string(), rule: rule.to_string(), message }
+    }
+}
+
+fn check_length(field: &str, value: &str, min: usize, max: usize, violations: &mut Vec<Violation>) {
+    let length = value.chars().count();
+
+    if length < min {
+        violations.push(Violation::new(field, "min_length", format!("Must be at least {} characters", min)));
+    }
+
+    if length > max {
+        violations.push(Violation::new(field, "max_length", format!("Must be at most {} characters", max)));
+    }
+}
+
+impl CredentialPolicy {
+    /// Every rule the credentials break, empty if they are acceptable.
+    pub fn check(&self, username: &str, password: &str) -> Vec<Violation> {
+        let mut violations = vec![];
+
+        check_length("username", username, self.username_min_length, self.username_max_length, &mut violations);
+
+        let is_allowed = |c: char| c.is_ascii_alphanumeric() || self.username_allowed_symbols.contains(c);
+
+        if !username.chars().all(is_allowed) {
+            violations.push(Violation::new(
+                "username",
+                "allowed_characters",
+                format!("May only contain letters, digits and any of \"{}\"", self.username_allowed_symbols)
+            ));
+        }
+
+        check_length("password", password, self.password_min_length, self.password_max_length, &mut violations);
+
+        if self.banned_passwords.iter().any(|banned| banned.eq_ignore_ascii_case(password)) {
+            violations.push(Violation::new("password", "banned", "Is too common".to_string()));
+        }
+
+        if password.eq_ignore_ascii_case(username) {
+            violations.push(Violation::new("password", "matches_username", "Must differ from the username".to_string()));
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(violations: Vec<Violation>) -> Vec<String> {
+        violations.into_iter().map(|x| format!("{}.{}", x.field, x.rule)).collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let policy = CredentialPolicy::default();
+
+        assert!(policy.check("user.name", "correct horse").is_empty());
+        assert_eq!(rules(policy.check("ab", "Password")), vec!["username.min_length", "password.banned"]);
+        assert_eq!(rules(policy.check("bad name!", "short")), vec!["username.allowed_characters", "password.min_length"]);
+        assert_eq!(rules(policy.check("samename", "SameName")), vec!["password.matches_username"]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ldap::{LdapConfig, LdapProvider};
+use crate::auth::model::User;
+use crate::auth::route::USER_TABLE_NAME;
+use crate::db::DbHandle;
+use crate::error::AppError;
+
+
+/// Checks the credentials sent to `/login`. A directory such as LDAP or an
+/// OIDC introspection endpoint would answer with the user it knows under
+/// `username`, whose permissions then go into the issued JWT as usual.
+pub trait AuthProvider: Send + Sync {
+    /// The user the credentials belong to, or `None` if they are wrong.
+    fn authenticate<'a>(&'a self, db: &'a DbHandle, username: &'a str, password: &'a str) -> BoxFuture<'a, Result<Option<User>, AppError>>;
+}
+
+/// Checks the credentials against the `user` table.
+pub struct LocalProvider;
+
+impl AuthProvider for LocalProvider {
+    fn authenticate<'a>(&'a self, db: &'a DbHandle, username: &'a str, password: &'a str) -> BoxFuture<'a, Result<Option<User>, AppError>> {
+        async move {
+            let db_ref = db.read().await;
+            let user = db_ref
+                .table::<User>(USER_TABLE_NAME)
+                .find_by_value("username", username.to_string())
+                .and_then(|x| x.first().cloned())
+                .filter(|user| user.password == password);
+
+            Ok(user)
+        }.boxed()
+    }
+}
+
+/// Which [`AuthProvider`] checks logins, set under `auth_provider` in the config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthProviderConfig {
+    #[default]
+    Local,
+    Ldap(LdapConfig)
+}
+
+impl AuthProviderConfig {
+    pub fn build(&self) -> Authenticator {
+        match self {
+            AuthProviderConfig::Local => Authenticator::new(LocalProvider),
+            AuthProviderConfig::Ldap(config) => Authenticator::new(LdapProvider::new(config.clone()))
+        }
+    }
+}
+
+/// The configured [`AuthProvider`], shared with the handlers as request data.
+#[derive(Clone)]
+pub struct Authenticator {
+    provider: Arc<dyn AuthProvider>
+}
+
+impl Authenticator {
+    pub fn new<P: AuthProvider + 'static>(provider: P) -> Self {
+        Self { provider: Arc::new(provider) }
+    }
+
+    pub async fn authenticate(&self, db: &DbHandle, username: &str, password: &str) -> Result<Option<User>, AppError> {
+        self.provider.authenticate(db, username, password).await
+    }
+}
+
+impl Default for Authenticator {
+    fn default() -> Self {
+        AuthProviderConfig::default().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::Db;
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_provider() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = Db::init(file_name).await.unwrap();
+                db.add_table(USER_TABLE_NAME.to_string(), false).await.unwrap();
+                let user = User::new(1, "user".to_string(), "password1".to_string(), vec!["MUTATE".to_string()]);
+                db.insert_or_update(USER_TABLE_NAME.to_string(), 1, user).await.unwrap();
+                let db = DbHandle::new(db);
+
+                let authenticator = Authenticator::default();
+
+                let user = authenticator.authenticate(&db, "user", "password1").await.unwrap().unwrap();
+                assert_eq!(user.permissions, vec!["MUTATE".to_string()]);
+                assert!(authenticator.authenticate(&db, "user", "wrong").await.unwrap().is_none());
+                assert!(authenticator.authenticate(&db, "nobody", "password1").await.unwrap().is_none());
+            }
+        }).await;
+    }
+}
\ No newline at end of file
@@ -1,33 +1,31 @@
-use std::sync::{Arc, Mutex};
-
-use poem::{handler, http::StatusCode, post, web::Data, Error, Result, Route};
+use poem::{handler, http::StatusCode, post, web::Data, Request, Route};
 use serde_json::Value;
 
-use crate::{auth::model::{UserFormBody, LoginResponse, User}, db::Db, response::GenericResponse};
+use crate::{auth::model::{UserFormBody, LoginResponse, User}, db::{DbError, DbHandle}, error::{AppError, Context}, response::GenericResponse};
 
+use super::challenge::ChallengeGuard;
 use super::jwt;
+use super::policy::CredentialPolicy;
+use super::provider::Authenticator;
 
 pub const USER_TABLE_NAME: &str = "user";
 
 #[handler]
-pub fn login(payload: UserFormBody, db: Data<&Arc<Mutex<Db>>>, manager: Data<&jwt::Manager>) -> Result<GenericResponse<LoginResponse>> {
-    let db_ref = db
-        .lock()
-        .map_err(|_| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))
-        .expect("Getting db lock");
-    let user = db_ref.find_by_value::<User>(USER_TABLE_NAME.to_string(), "username".to_string(), payload.username)
-        .map(|x| x.first().cloned().unwrap())
-        .ok_or(
-            Error::from_status(StatusCode::UNAUTHORIZED)
-        )?;
-    
-    if user.password != payload.password {
-        return Err(Error::from_status(StatusCode::UNAUTHORIZED))
-    }
+pub async fn login(req: &Request, payload: UserFormBody, db: Data<&DbHandle>, manager: Data<&jwt::Manager>, guard: Data<&ChallengeGuard>, auth: Data<&Authenticator>) -> Result<GenericResponse<LoginResponse>, AppError> {
+    guard.check(req).await?;
+
+    let user = auth.authenticate(&db, &payload.username, &payload.password).await?;
+
+    let Some(user) = user else {
+        guard.record_failure(req);
+        return Err(AppError::InvalidCredentials)
+    };
+
+    guard.reset(req);
 
     let token_data = manager.create_token_data(user.username, user.permissions);
     let token = manager.encode(token_data)
-        .expect("Encoding jwt");
+        .context("Issuing login token")?;
 
     Ok(GenericResponse{
         status_code_u16: StatusCode::OK.as_u16(),
@@ -37,31 +35,35 @@ pub fn login(payload: UserFormBody, db: Data<&Arc<Mutex<Db>>>, manager: Data<&jw
 }
 
 #[handler]
-pub fn register(payload: UserFormBody, db: Data<&Arc<Mutex<Db>>>) -> Result<GenericResponse<Value>> {
-    let mut db_ref = db
-        .lock()
-        .map_err(|_| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))
-        .expect("Getting db lock");
-    let users = db_ref
-        .find_by_value::<User>(USER_TABLE_NAME.to_string(), "username".to_string(), payload.username.clone())
-        .ok_or(
-            Error::from_status(StatusCode::UNAUTHORIZED)
-        )?;
-    
-    if !users.is_empty() {
-        return Ok(GenericResponse{
-            status_code_u16: StatusCode::BAD_REQUEST.as_u16(),
-            message: Some("User already exists!".to_string()),
-            data: None
+pub async fn register(req: &Request, payload: UserFormBody, db: Data<&DbHandle>, policy: Data<&CredentialPolicy>, guard: Data<&ChallengeGuard>) -> Result<GenericResponse<Value>, AppError> {
+    guard.check(req).await?;
+
+    let violations = policy.check(&payload.username, &payload.password);
+
+    if !violations.is_empty() {
+        guard.record_failure(req);
+        return Err(AppError::PolicyViolation(violations))
+    }
+
+    let mut db_ref = db.write().await;
+    let result = db_ref
+        .transaction(|tx| {
+            let id = tx.get_increment_last_id(USER_TABLE_NAME.to_string())?;
+            // Skipping hashing of password
+            let to_insert = User::new(id, payload.username, payload.password, vec!["MUTATE".to_string()]);
+            tx.insert_or_update(USER_TABLE_NAME.to_string(), id, to_insert)
         })
+        .await;
+
+    if let Err(err) = result {
+        let err = AppError::from(err).with_context("Registering user");
+
+        if let AppError::Db(DbError::UniqueViolation { .. }) = err {
+            guard.record_failure(req);
+        }
+
+        return Err(err)
     }
-    let id = db_ref.get_increment_last_id(USER_TABLE_NAME.to_string()).unwrap().unwrap();
-    // Skipping hashing of password
-    let to_insert = User::new(id, payload.username, payload.password, vec!["MUTATE".to_string()]);
-    db_ref
-        .insert_or_update(USER_TABLE_NAME.to_string(), id, to_insert)
-        .unwrap()
-        .unwrap();
 
     Ok(GenericResponse::<Value>{
         message: Some("User registered successfully.".to_string()),
@@ -81,26 +83,29 @@ pub fn auth_routes() -> Route {
 mod tests {
     use poem::Endpoint;
 
-    use crate::test::{async_run_with_file_create_teardown, ApiTestClient, TEST_PASSWORD, TEST_USERNAME};
+    use crate::db::Db;
+    use crate::auth::challenge::CHALLENGE_RESPONSE_HEADER;
+    use crate::test::{async_run_with_file_create_teardown, solve_proof_of_work, ApiTestClient, TEST_PASSWORD, TEST_USERNAME};
 
     use super::*;
 
-    fn init_client(file_name: String) -> ApiTestClient<impl Endpoint> {
+    async fn init_client(file_name: String) -> ApiTestClient<impl Endpoint> {
         let routes = Route::new().nest(
             "/", auth_routes()
         );
-        let test_client = ApiTestClient::init(routes, file_name.as_str());
+        let test_client = ApiTestClient::init(routes, file_name.as_str()).await;
         {
-            let mut db = test_client.db.lock().unwrap();
-            db.add_table(USER_TABLE_NAME.to_string(), false).unwrap();
-            db.delete_all(USER_TABLE_NAME.to_string()).unwrap();
+            let mut db = test_client.db.write().await;
+            db.add_table(USER_TABLE_NAME.to_string(), false).await.unwrap();
+            db.delete_all(USER_TABLE_NAME.to_string()).await.unwrap();
+            db.add_unique_constraint(USER_TABLE_NAME.to_string(), "username".to_string()).unwrap();
         }
 
-        return test_client
+        test_client
     }
 
-    fn insert_user(db: &mut Db, username: &str, password: &str) {
-        let id = db.get_increment_last_id(USER_TABLE_NAME.to_string()).unwrap().unwrap();
+    async fn insert_user(db: &mut Db, username: &str, password: &str) {
+        let id = db.get_increment_last_id(USER_TABLE_NAME.to_string()).await.unwrap().unwrap();
         let to_insert = User::new(
             id, 
             username.to_string(), 
@@ -109,6 +114,7 @@ mod tests {
         );
         db
             .insert_or_update(USER_TABLE_NAME.to_string(), id, to_insert)
+            .await
             .unwrap()
             .unwrap();
     }
@@ -118,10 +124,10 @@ mod tests {
         async_run_with_file_create_teardown(|file_name| {
             let file_name = file_name.to_string();
             async {
-                let test_client = init_client(file_name);
+                let test_client = init_client(file_name).await;
                 {
-                    let mut db = test_client.db.lock().unwrap();
-                    insert_user(&mut db, TEST_USERNAME, TEST_PASSWORD);
+                    let mut db = test_client.db.write().await;
+                    insert_user(&mut db, TEST_USERNAME, TEST_PASSWORD).await;
                 }
 
                 let response = test_client.client.post("/login")
@@ -142,7 +148,7 @@ mod tests {
         async_run_with_file_create_teardown(|file_name| {
             let file_name = file_name.to_string();
             async {
-                let test_client = init_client(file_name);
+                let test_client = init_client(file_name).await;
 
                 let response = test_client.client.post("/register")
                     .body_json(&UserFormBody{ 
@@ -156,4 +162,105 @@ mod tests {
             }
         }).await;
     }
+
+    #[tokio::test]
+    async fn test_register_existing_user() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    insert_user(&mut db, TEST_USERNAME, TEST_PASSWORD).await;
+                }
+
+                let response = test_client.client.post("/register")
+                    .body_json(&UserFormBody{ 
+                        username: TEST_USERNAME.to_string(),
+                        password: TEST_PASSWORD.to_string()
+                    })
+                    .send()
+                    .await;
+
+                response.assert_status(StatusCode::CONFLICT);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_register_policy_violations() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+
+                let response = test_client.client.post("/register")
+                    .body_json(&UserFormBody{ 
+                        username: TEST_USERNAME.to_string(),
+                        password: "letmein".to_string()
+                    })
+                    .send()
+                    .await;
+
+                response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+                response.assert_json(serde_json::json!({
+                    "data": {
+                        "code": "policy_violation",
+                        "violations": [
+                            {
+                                "field": "password",
+                                "rule": "min_length",
+                                "message": "Must be at least 8 characters"
+                            }
+                        ]
+                    },
+                    "message": "Credentials do not meet the policy"
+                })).await;
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_login_challenge_after_failures() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    insert_user(&mut db, TEST_USERNAME, TEST_PASSWORD).await;
+                }
+
+                let wrong = UserFormBody{
+                    username: TEST_USERNAME.to_string(),
+                    password: "wrong-password".to_string()
+                };
+
+                for _ in 0..5 {
+                    let response = test_client.client.post("/login").body_json(&wrong).send().await;
+                    response.assert_status(StatusCode::UNAUTHORIZED);
+                }
+
+                let correct = UserFormBody{
+                    username: TEST_USERNAME.to_string(),
+                    password: TEST_PASSWORD.to_string()
+                };
+
+                let response = test_client.client.post("/login").body_json(&correct).send().await;
+                response.assert_status(StatusCode::PRECONDITION_REQUIRED);
+                let body = response.json().await;
+                let challenge = body.value().object().get("data").object().get("challenge").deserialize::<Value>();
+
+                let response = test_client.client.post("/login")
+                    .header(CHALLENGE_RESPONSE_HEADER, solve_proof_of_work(&challenge))
+                    .body_json(&correct)
+                    .send()
+                    .await;
+                response.assert_status_is_ok();
+
+                let response = test_client.client.post("/login").body_json(&correct).send().await;
+                response.assert_status_is_ok();
+            }
+        }).await;
+    }
 }
\ No newline at end of file
@@ -0,0 +1,367 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use moka::sync::Cache;
+use poem::http::{header, HeaderMap, Method, StatusCode};
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use poem_grants::authorities::AuthDetails;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+
+use crate::db::metrics::CacheMetrics;
+use crate::db::watch::ChangeEvent;
+
+
+/// Caching policy applied to a class of routes through the `Cache-Control` header.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CachePolicy {
+    Public { max_age: u64 },
+    Private { max_age: u64 },
+    NoStore
+}
+
+impl CachePolicy {
+    pub fn header_value(&self) -> String {
+        match self {
+            CachePolicy::Public { max_age } => format!("public, max-age={}", max_age),
+            CachePolicy::Private { max_age } => format!("private, max-age={}", max_age),
+            CachePolicy::NoStore => "no-store".to_string()
+        }
+    }
+
+    /// `no-store` applies to every response, while cacheable policies only
+    /// apply to successful reads.
+    fn applies_to(&self, method: &Method, response: &Response) -> bool {
+        match self {
+            CachePolicy::NoStore => true,
+            _ => (method == Method::GET || method == Method::HEAD) && response.status().is_success()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CacheControlMiddleware {
+    pub policy: CachePolicy
+}
+
+impl<E: Endpoint> Middleware<E> for CacheControlMiddleware {
+    type Output = CacheControlMiddlewareImpl<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CacheControlMiddlewareImpl { ep, policy: self.policy.clone() }
+    }
+}
+
+pub struct CacheControlMiddlewareImpl<E> {
+    ep: E,
+    policy: CachePolicy
+}
+
+impl<E: Endpoint> Endpoint for CacheControlMiddlewareImpl<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let method = req.method().clone();
+        let mut response = self.ep.call(req).await?.into_response();
+
+        if self.policy.applies_to(&method, &response) && !response.headers().contains_key(header::CACHE_CONTROL) {
+            response.headers_mut().insert(
+                header::CACHE_CONTROL,
+                self.policy.header_value().parse().unwrap()
+            );
+        }
+
+        Ok(response)
+    }
+}
+
+/// Header set by [`ResponseCacheMiddleware`] to `HIT` or `MISS` on every GET
+/// it sees, so cache effectiveness can be read off the access logs.
+pub const CACHE_STATUS_HEADER: &str = "x-cache";
+
+/// Bounds of the in-process response cache. Disabled unless `enabled` is set.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ResponseCacheConfig {
+    pub enabled: bool,
+    pub max_entries: u64,
+    pub ttl_secs: u64
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: 1000,
+            ttl_secs: 60
+        }
+    }
+}
+
+#[derive(Hash, PartialEq, Eq)]
+struct CacheKey {
+    /// Writes seen before the request, so a response read while a write was
+    /// in flight is stored under a generation nobody looks up again.
+    generation: u64,
+    method: Method,
+    uri: String,
+    /// Sorted permissions of the token the request was sent with, so responses
+    /// are only shared between clients authorized the same way.
+    scope: Option<Vec<String>>
+}
+
+#[derive(Clone)]
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>
+}
+
+impl CachedResponse {
+    fn to_response(&self, cache_status: &'static str) -> Response {
+        let mut response = Response::builder()
+            .status(self.status)
+            .body(self.body.clone());
+        response.headers_mut().extend(self.headers.clone());
+        response.headers_mut().insert(CACHE_STATUS_HEADER, cache_status.parse().unwrap());
+
+        response
+    }
+}
+
+/// The cached responses along with the generation they are looked up under.
+#[derive(Clone)]
+struct Entries {
+    cache: Cache<CacheKey, CachedResponse>,
+    generation: Arc<AtomicU64>
+}
+
+impl Entries {
+    fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.cache.invalidate_all();
+    }
+}
+
+/// Serves repeated successful GETs from memory, keyed on the method, URI and
+/// auth scope, counting hits and misses into the db's [`CacheMetrics`]. The
+/// cache is emptied by every change [`ResponseCacheMiddleware::invalidate_on`]
+/// is given, and right away by successful writes through the same routes so
+/// clients never read back records from before their own writes.
+#[derive(Clone)]
+pub struct ResponseCacheMiddleware {
+    entries: Entries,
+    metrics: CacheMetrics
+}
+
+impl ResponseCacheMiddleware {
+    pub fn new(config: &ResponseCacheConfig, metrics: CacheMetrics) -> Self {
+        let cache = Cache::builder()
+            .max_capacity(config.max_entries)
+            .time_to_live(Duration::from_secs(config.ttl_secs))
+            .build();
+
+        Self { entries: Entries { cache, generation: Arc::new(AtomicU64::new(0)) }, metrics }
+    }
+
+    /// Empties the cache on every change received from `changes`, as returned
+    /// by [`crate::db::Db::watch`], until the db is dropped.
+    pub fn invalidate_on(&self, mut changes: broadcast::Receiver<ChangeEvent>) -> JoinHandle<()> {
+        let entries = self.entries.clone();
+
+        tokio::spawn(async move {
+            while let Ok(_) | Err(RecvError::Lagged(_)) = changes.recv().await {
+                entries.invalidate();
+            }
+        })
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for ResponseCacheMiddleware {
+    type Output = ResponseCacheMiddlewareImpl<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ResponseCacheMiddlewareImpl { ep, entries: self.entries.clone(), metrics: self.metrics.clone() }
+    }
+}
+
+pub struct ResponseCacheMiddlewareImpl<E> {
+    ep: E,
+    entries: Entries,
+    metrics: CacheMetrics
+}
+
+impl<E: Endpoint> Endpoint for ResponseCacheMiddlewareImpl<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let method = req.method().clone();
+
+        if method != Method::GET {
+            let response = self.ep.call(req).await?.into_response();
+
+            if !method.is_safe() && response.status().is_success() {
+                self.entries.invalidate();
+            }
+
+            return Ok(response)
+        }
+
+        let scope = req.extensions().get::<AuthDetails>().map(|details| {
+            let mut permissions: Vec<String> = details.authorities.iter().cloned().collect();
+            permissions.sort();
+            permissions
+        });
+
+        let key = CacheKey {
+            generation: self.entries.generation.load(Ordering::SeqCst),
+            method,
+            uri: req.uri().to_string(),
+            scope
+        };
+
+        if let Some(cached) = self.entries.cache.get(&key) {
+            self.metrics.record_hit();
+            return Ok(cached.to_response("HIT"))
+        }
+
+        self.metrics.record_miss();
+        let mut response = self.ep.call(req).await?.into_response();
+
+        if !response.status().is_success() {
+            return Ok(response)
+        }
+
+        let cached = CachedResponse {
+            status: response.status(),
+            body: response.take_body().into_vec().await?,
+            headers: response.headers().clone()
+        };
+        let response = cached.to_response("MISS");
+        self.entries.cache.insert(key, cached);
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::{get, handler, test::TestClient, web::Data, EndpointExt, Route};
+    use serde_json::json;
+
+    use super::*;
+    use crate::db::Db;
+
+    #[handler]
+    fn index() -> &'static str {
+        "ok"
+    }
+
+    #[handler]
+    fn count(calls: Data<&Arc<AtomicU64>>) -> String {
+        calls.fetch_add(1, Ordering::SeqCst).to_string()
+    }
+
+    fn client(policy: CachePolicy) -> TestClient<impl Endpoint> {
+        TestClient::new(
+            Route::new()
+                .at("/", get(index).post(index))
+                .with(CacheControlMiddleware{ policy })
+        )
+    }
+
+    #[tokio::test]
+    async fn test_cacheable_policy_on_get() {
+        let response = client(CachePolicy::Public { max_age: 60 }).get("/").send().await;
+
+        response.assert_status_is_ok();
+        response.assert_header(header::CACHE_CONTROL, "public, max-age=60");
+    }
+
+    #[tokio::test]
+    async fn test_cacheable_policy_skips_mutations() {
+        let response = client(CachePolicy::Private { max_age: 60 }).post("/").send().await;
+
+        response.assert_status_is_ok();
+        response.assert_header_is_not_exist(header::CACHE_CONTROL);
+    }
+
+    #[tokio::test]
+    async fn test_no_store_on_every_method() {
+        let response = client(CachePolicy::NoStore).post("/").send().await;
+
+        response.assert_header(header::CACHE_CONTROL, "no-store");
+    }
+
+    #[tokio::test]
+    async fn test_response_cache() {
+        let config = ResponseCacheConfig { enabled: true, ..ResponseCacheConfig::default() };
+        let metrics = CacheMetrics::default();
+        let client = TestClient::new(
+            Route::new()
+                .at("/", get(count).post(index))
+                .with(ResponseCacheMiddleware::new(&config, metrics.clone()))
+                .data(Arc::new(AtomicU64::new(0)))
+        );
+        let scope = |permissions: &[&str]| AuthDetails::new(permissions.iter().map(|p| p.to_string()));
+
+        let requests = [
+            (client.get("/"), "MISS", "0"),
+            (client.get("/"), "HIT", "0"),
+            (client.get("/").data(scope(&["READ", "MUTATE"])), "MISS", "1"),
+            (client.get("/").data(scope(&["MUTATE", "READ"])), "HIT", "1"),
+            (client.get("/").data(scope(&["READ"])), "MISS", "2"),
+            (client.get("/?q=a"), "MISS", "3"),
+            (client.get("/"), "HIT", "0")
+        ];
+
+        for (request, cache_status, body) in requests {
+            let response = request.send().await;
+            response.assert_status_is_ok();
+            response.assert_header(CACHE_STATUS_HEADER, cache_status);
+            response.assert_text(body).await;
+        }
+
+        client.post("/").send().await.assert_status_is_ok();
+
+        let response = client.get("/").send().await;
+        response.assert_header(CACHE_STATUS_HEADER, "MISS");
+        response.assert_text("4").await;
+
+        let stats = metrics.snapshot();
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 5);
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_invalidated_by_db_changes() {
+        let config = ResponseCacheConfig { enabled: true, ..ResponseCacheConfig::default() };
+        let mut db = Db::in_memory();
+        db.add_table("sample".to_string(), false).await.unwrap();
+
+        let response_cache = ResponseCacheMiddleware::new(&config, db.cache_metrics());
+        let watcher = response_cache.invalidate_on(db.watch("sample".to_string()));
+        let client = TestClient::new(
+            Route::new()
+                .at("/", get(count))
+                .with(response_cache)
+                .data(Arc::new(AtomicU64::new(0)))
+        );
+
+        client.get("/").send().await.assert_header(CACHE_STATUS_HEADER, "MISS");
+        client.get("/").send().await.assert_header(CACHE_STATUS_HEADER, "HIT");
+
+        db.insert_or_update("sample".to_string(), 1, json!({ "name": "a" })).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while client.get("/").send().await.0.headers()[CACHE_STATUS_HEADER] == "HIT" {
+                tokio::task::yield_now().await;
+            }
+        }).await.expect("the insert should empty the cache");
+
+        drop(db);
+        watcher.await.unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use poem::middleware::Cors;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::challenge::ChallengeConfig;
+use crate::auth::jwt::DEFAULT_LEEWAY_SECS;
+use crate::auth::policy::CredentialPolicy;
+use crate::auth::provider::AuthProviderConfig;
+use crate::cache::{CachePolicy, ResponseCacheConfig};
+use crate::db::storage::codec::CodecKind;
+use crate::db::storage::file::Compression;
+use crate::db::{merge_patch, DEFAULT_CHECKPOINT_INTERVAL};
+use crate::db::storage::StorageBackend;
+use crate::naming::FieldNaming;
+use crate::replay::ReplayConfig;
+
+
+pub const DEFAULT_CONFIG_FILE: &str = "./config.json";
+
+const DEFAULT_JWT_SECRET: &str = "secret";
+
+/// Shortest `jwt_secret` accepted with `strict_secrets`.
+pub const MIN_STRICT_SECRET_LENGTH: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub items: CachePolicy,
+    pub auth: CachePolicy,
+    /// In-process cache of item reads, see [`crate::cache::ResponseCacheMiddleware`].
+    pub responses: ResponseCacheConfig
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            items: CachePolicy::Public { max_age: 0 },
+            auth: CachePolicy::NoStore,
+            responses: ResponseCacheConfig::default()
+        }
+    }
+}
+
+/// Periodic backups of the db, written by [`crate::db::Db::snapshot`].
+/// Disabled while `interval_secs` is 0.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    pub interval_secs: u64,
+    pub dir: String
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 0,
+            dir: "./snapshots".to_string()
+        }
+    }
+}
+
+/// Origins browsers may call the api from, answered through [`Cors`]. No
+/// cross-origin requests are allowed while `allowed_origins` is empty, and
+/// `"*"` allows every origin.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub max_age_secs: i32
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![],
+            max_age_secs: 86400
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_origins.is_empty()
+    }
+
+    pub fn build(&self) -> Cors {
+        let cors = Cors::new().max_age(self.max_age_secs);
+
+        match self.allowed_origins.iter().any(|origin| origin == "*") {
+            true => cors,
+            false => cors.allow_origins(self.allowed_origins.iter().map(String::as_str))
+        }
+    }
+}
+
+/// When logged mutations are checkpointed into the db file: once
+/// `max_pending_entries` accumulate, and every `interval_secs` while any are
+/// pending. The timer is disabled while `interval_secs` is 0.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FlushConfig {
+    pub interval_secs: u64,
+    pub max_pending_entries: usize
+}
+
+impl Default for FlushConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            max_pending_entries: DEFAULT_CHECKPOINT_INTERVAL
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub db_file: String,
+    pub storage: StorageBackend,
+    pub compression: Compression,
+    /// Codecs for tables that should not be stored as json, by table name.
+    pub table_codecs: HashMap<String, CodecKind>,
+    pub flush: FlushConfig,
+    pub purge_interval_secs: u64,
+    pub snapshot: SnapshotConfig,
+    pub jwt_secret: String,
+    pub jwt_expiration_hours: i64,
+    pub jwt_leeway_secs: u64,
+    pub credential_policy: CredentialPolicy,
+    pub auth_provider: AuthProviderConfig,
+    pub challenge: ChallengeConfig,
+    pub cache: CacheConfig,
+    pub cors: CorsConfig,
+    pub replay: ReplayConfig,
+    /// Case of the field names in json responses.
+    pub field_naming: FieldNaming,
+    /// Keeps the db in memory only, like the `--ephemeral` flag.
+    pub ephemeral: bool,
+    /// Refuses to start with the default or a short `jwt_secret`.
+    pub strict_secrets: bool
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "0.0.0.0:3000".to_string(),
+            db_file: "./data.json".to_string(),
+            storage: StorageBackend::Json,
+            compression: Compression::None,
+            table_codecs: HashMap::new(),
+            flush: FlushConfig::default(),
+            purge_interval_secs: 60,
+            snapshot: SnapshotConfig::default(),
+            jwt_secret: DEFAULT_JWT_SECRET.to_string(),
+            jwt_expiration_hours: 24,
+            jwt_leeway_secs: DEFAULT_LEEWAY_SECS,
+            credential_policy: CredentialPolicy::default(),
+            auth_provider: AuthProviderConfig::default(),
+            challenge: ChallengeConfig::default(),
+            cache: CacheConfig::default(),
+            cors: CorsConfig::default(),
+            replay: ReplayConfig::default(),
+            field_naming: FieldNaming::default(),
+            ephemeral: false,
+            strict_secrets: false
+        }
+    }
+}
+
+/// Preset bundle of settings picked with `--profile`. Anything set in the
+/// config file still overrides it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// In-memory db, easy challenges, uncached responses and any origin.
+    Dev,
+    /// The defaults with hourly snapshots.
+    Staging,
+    /// Hourly snapshots and a required strong `jwt_secret`.
+    Prod
+}
+
+impl Profile {
+    pub fn preset(&self) -> ServerConfig {
+        let defaults = ServerConfig::default();
+
+        match self {
+            Profile::Dev => ServerConfig {
+                ephemeral: true,
+                challenge: ChallengeConfig { max_failures: 50, difficulty: 4, ..defaults.challenge },
+                cache: CacheConfig { items: CachePolicy::NoStore, auth: CachePolicy::NoStore, ..defaults.cache },
+                cors: CorsConfig { allowed_origins: vec!["*".to_string()], ..defaults.cors },
+                ..defaults
+            },
+            Profile::Staging => ServerConfig {
+                snapshot: SnapshotConfig { interval_secs: 3600, ..defaults.snapshot },
+                ..defaults
+            },
+            Profile::Prod => ServerConfig {
+                snapshot: SnapshotConfig { interval_secs: 3600, ..defaults.snapshot },
+                strict_secrets: true,
+                ..defaults
+            }
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "dev" => Ok(Profile::Dev),
+            "staging" => Ok(Profile::Staging),
+            "prod" => Ok(Profile::Prod),
+            other => Err(format!("Unknown profile {}, expected dev, staging or prod", other))
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the config from a json file, falling back to the defaults for the
+    /// whole config when the file is missing and for any omitted fields.
+    pub fn load(file_name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if !Path::new(file_name).exists() {
+            return Ok(Self::default())
+        }
+
+        let contents = std::fs::read_to_string(file_name)?;
+
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Like [`ServerConfig::load`] but starting from the preset of `profile`,
+    /// then checked with [`ServerConfig::validate`].
+    pub fn load_with_profile(file_name: &str, profile: Option<Profile>) -> Result<Self, Box<dyn std::error::Error>> {
+        let Some(profile) = profile else {
+            let config = Self::load(file_name)?;
+            config.validate()?;
+
+            return Ok(config)
+        };
+
+        let mut config = serde_json::to_value(profile.preset())?;
+
+        if Path::new(file_name).exists() {
+            let overrides: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(file_name)?)?;
+            merge_patch(&mut config, &overrides);
+        }
+
+        let config: Self = serde_json::from_value(config)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.strict_secrets && (self.jwt_secret == DEFAULT_JWT_SECRET || self.jwt_secret.len() < MIN_STRICT_SECRET_LENGTH) {
+            return Err(format!("jwt_secret must be set to at least {} characters with strict_secrets", MIN_STRICT_SECRET_LENGTH))
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::{header, StatusCode};
+    use poem::test::TestClient;
+    use poem::{endpoint::make_sync, EndpointExt};
+
+    use crate::test::run_with_file_create_teardown;
+
+    use super::*;
+
+    #[test]
+    fn test_load_missing_file() {
+        let config = ServerConfig::load("./does-not-exist-config.json").unwrap();
+
+        assert_eq!(config, ServerConfig::default());
+    }
+
+    #[test]
+    fn test_load_partial_file() {
+        run_with_file_create_teardown(|file_name| {
+            std::fs::write(
+                file_name,
+                r#"{"bind_address": "127.0.0.1:8080", "cache": {"items": {"type": "private", "max_age": 30}}}"#
+            ).unwrap();
+
+            let config = ServerConfig::load(file_name).unwrap();
+
+            assert_eq!(config.bind_address, "127.0.0.1:8080");
+            assert_eq!(config.db_file, ServerConfig::default().db_file);
+            assert_eq!(config.cache.items, CachePolicy::Private { max_age: 30 });
+            assert_eq!(config.cache.auth, CachePolicy::NoStore);
+        });
+    }
+
+    #[test]
+    fn test_load_with_profile() {
+        run_with_file_create_teardown(|file_name| {
+            std::fs::write(file_name, r#"{"ephemeral": false, "challenge": {"difficulty": 8}}"#).unwrap();
+
+            let config = ServerConfig::load_with_profile(file_name, Some(Profile::Dev)).unwrap();
+
+            assert!(!config.ephemeral);
+            assert_eq!(config.challenge.difficulty, 8);
+            assert_eq!(config.challenge.max_failures, 50);
+            assert_eq!(config.cache.items, CachePolicy::NoStore);
+            assert!(config.cors.is_enabled());
+
+            assert!(ServerConfig::load_with_profile(file_name, Some(Profile::Prod)).is_err());
+
+            std::fs::write(file_name, format!(r#"{{"jwt_secret": "{}"}}"#, "s".repeat(MIN_STRICT_SECRET_LENGTH))).unwrap();
+            let config = ServerConfig::load_with_profile(file_name, Some(Profile::Prod)).unwrap();
+            assert!(config.strict_secrets);
+            assert_eq!(config.snapshot.interval_secs, 3600);
+            assert!(!config.cors.is_enabled());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_cors() {
+        let cors = CorsConfig { allowed_origins: vec!["https://app.example".to_string()], ..CorsConfig::default() };
+        let client = TestClient::new(make_sync(|_| "ok").with(cors.build()));
+
+        let response = client.get("/").header(header::ORIGIN, "https://app.example").send().await;
+        response.assert_status_is_ok();
+        response.assert_header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "https://app.example");
+
+        let response = client.get("/").header(header::ORIGIN, "https://other.example").send().await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let any = Profile::Dev.preset().cors.build();
+        let client = TestClient::new(make_sync(|_| "ok").with(any));
+        let response = client.get("/").header(header::ORIGIN, "https://other.example").send().await;
+        response.assert_header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "https://other.example");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+use super::query::compare_numbers;
+
+
+/// Aggregation over the numeric values of a column. Records where the column
+/// is missing or not a number are left out, `Count` included.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Agg {
+    #[default]
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg
+}
+
+impl Agg {
+    /// Folds the values of `column`. `Min`, `Max` and `Avg` of no values are
+    /// null, and sums stay integers unless a value is fractional or the sum
+    /// overflows.
+    pub fn apply<'v>(&self, records: impl Iterator<Item = &'v Value>, column: &str) -> Value {
+        let numbers: Vec<&Number> = records
+            .filter_map(|record| record.get(column))
+            .filter_map(Value::as_number)
+            .collect();
+
+        match self {
+            Agg::Count => Value::from(numbers.len()),
+            Agg::Sum => sum(&numbers),
+            Agg::Min => numbers
+                .iter()
+                .min_by(|a, b| compare_numbers(a, b).unwrap_or(std::cmp::Ordering::Equal))
+                .map_or(Value::Null, |x| Value::Number((*x).clone())),
+            Agg::Max => numbers
+                .iter()
+                .max_by(|a, b| compare_numbers(a, b).unwrap_or(std::cmp::Ordering::Equal))
+                .map_or(Value::Null, |x| Value::Number((*x).clone())),
+            Agg::Avg if numbers.is_empty() => Value::Null,
+            Agg::Avg => Value::from(float_sum(&numbers) / numbers.len() as f64)
+        }
+    }
+
+    /// Like [`Agg::apply`] per value of `group_column`, keyed by that value,
+    /// strings as they are and other values as json. Records without the
+    /// group column are left out.
+    pub fn apply_grouped<'v>(&self, records: impl Iterator<Item = &'v Value>, group_column: &str, column: &str) -> BTreeMap<String, Value> {
+        let mut groups: BTreeMap<String, Vec<&Value>> = BTreeMap::new();
+
+        for record in records {
+            let key = match record.get(group_column) {
+                Some(Value::String(key)) => key.clone(),
+                Some(key) => key.to_string(),
+                None => continue
+            };
+
+            groups.entry(key).or_default().push(record);
+        }
+
+        groups
+            .into_iter()
+            .map(|(key, records)| (key, self.apply(records.into_iter(), column)))
+            .collect()
+    }
+}
+
+fn float_sum(numbers: &[&Number]) -> f64 {
+    numbers.iter().filter_map(|x| x.as_f64()).sum()
+}
+
+fn sum(numbers: &[&Number]) -> Value {
+    let integer = numbers
+        .iter()
+        .try_fold(0i64, |total, x| total.checked_add(x.as_i64()?));
+
+    match integer {
+        Some(total) => Value::from(total),
+        None => Value::from(float_sum(numbers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let records = [json!({"n": 3}), json!({"n": -1}), json!({"n": "4"}), json!({}), json!({"n": 10})];
+
+        assert_eq!(Agg::Count.apply(records.iter(), "n"), json!(3));
+        assert_eq!(Agg::Sum.apply(records.iter(), "n"), json!(12));
+        assert_eq!(Agg::Min.apply(records.iter(), "n"), json!(-1));
+        assert_eq!(Agg::Max.apply(records.iter(), "n"), json!(10));
+        assert_eq!(Agg::Avg.apply(records.iter(), "n"), json!(4.0));
+
+        assert_eq!(Agg::Sum.apply([json!({"n": 1}), json!({"n": 0.5})].iter(), "n"), json!(1.5));
+        assert_eq!(Agg::Min.apply(records.iter(), "missing"), Value::Null);
+        assert_eq!(Agg::Avg.apply(records.iter(), "missing"), Value::Null);
+    }
+
+    #[test]
+    fn test_apply_grouped() {
+        let records = [
+            json!({"tag": "a", "n": 1}),
+            json!({"tag": "b", "n": 2}),
+            json!({"tag": "a", "n": 3}),
+            json!({"tag": 7, "n": 4}),
+            json!({"n": 5})
+        ];
+
+        let sums = Agg::Sum.apply_grouped(records.iter(), "tag", "n");
+
+        assert_eq!(sums, BTreeMap::from([
+            ("7".to_string(), json!(4)),
+            ("a".to_string(), json!(4)),
+            ("b".to_string(), json!(2))
+        ]));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,52 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+
+/// Encoded records of one table by id.
+type EncodedTable = BTreeMap<u32, Arc<[u8]>>;
+
+/// Json encodings of records, kept so list responses can be assembled from
+/// them instead of serializing every record again. Entries are filled on the
+/// first read and dropped whenever the record is written.
+#[derive(Default)]
+pub(crate) struct EncodedCache {
+    tables: Mutex<HashMap<String, EncodedTable>>
+}
+
+impl Clone for EncodedCache {
+    fn clone(&self) -> Self {
+        Self { tables: Mutex::new(self.tables.lock().unwrap().clone()) }
+    }
+}
+
+impl EncodedCache {
+    /// Returns the encoding of `record` and whether it had to be made.
+    pub fn get_or_encode(&self, table_name: &str, id: u32, record: &Value) -> (Arc<[u8]>, bool) {
+        let mut tables = self.tables.lock().unwrap();
+
+        if let Some(encoded) = tables.get(table_name).and_then(|table| table.get(&id)) {
+            return (encoded.clone(), false)
+        }
+
+        let encoded: Arc<[u8]> = serde_json::to_vec(record).unwrap().into();
+        tables.entry(table_name.to_string()).or_default().insert(id, encoded.clone());
+
+        (encoded, true)
+    }
+
+    pub fn invalidate(&mut self, table_name: &str, id: u32) {
+        if let Some(table) = self.tables.get_mut().unwrap().get_mut(table_name) {
+            table.remove(&id);
+        }
+    }
+
+    pub fn invalidate_table(&mut self, table_name: &str) {
+        self.tables.get_mut().unwrap().remove(table_name);
+    }
+
+    pub fn clear(&mut self) {
+        self.tables.get_mut().unwrap().clear();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,118 @@
+use std::fmt;
+
+use serde_json::Value;
+
+
+/// Everything a db operation can fail with. Failures of the underlying
+/// libraries are kept as their message so the error stays comparable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DbError {
+    /// Reading or writing the storage failed.
+    Io(String),
+    /// A record or stored file could not be encoded or decoded.
+    Serialization(String),
+    TableNotFound(String),
+    /// Table names cannot contain `/`, which separates the parts of storage keys.
+    InvalidTableName(String),
+    /// A lock was poisoned by a thread that panicked while holding it.
+    LockPoisoned(String),
+    UniqueViolation { table: String, column: String, value: Value },
+    Validation { table: String, id: u32, message: String },
+    /// Record `id` is still referenced by `column` of `table`.
+    ForeignKeyViolation { table: String, column: String, id: u32 },
+    /// Record `id` was at version `actual` when `expected` was asked for.
+    Conflict { table: String, id: u32, expected: u64, actual: u64 },
+    MalformedSnapshot { path: String, message: String },
+    /// Another process holds the lock on the storage at `path`.
+    Locked { path: String },
+    /// The file at `path` is damaged and could not be recovered from a backup.
+    Corrupt { path: String, message: String }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Io(message) => write!(f, "Storage io failed: {}", message),
+            DbError::Serialization(message) => write!(f, "Serialization failed: {}", message),
+            DbError::TableNotFound(table) => write!(f, "Table {} does not exist", table),
+            DbError::InvalidTableName(table) => write!(f, "Table name {} must not contain /", table),
+            DbError::LockPoisoned(message) => write!(f, "Lock poisoned: {}", message),
+            DbError::UniqueViolation { table, column, value } =>
+                write!(f, "Duplicate value {} for unique column {}.{}", value, table, column),
+            DbError::Validation { table, id, message } =>
+                write!(f, "Invalid record {} for table {}: {}", id, table, message),
+            DbError::ForeignKeyViolation { table, column, id } =>
+                write!(f, "Record {} is still referenced by {}.{}", id, table, column),
+            DbError::Conflict { table, id, expected, actual } =>
+                write!(f, "Record {} of table {} is at version {}, not {}", id, table, actual, expected),
+            DbError::MalformedSnapshot { path, message } =>
+                write!(f, "Malformed snapshot {}: {}", path, message),
+            DbError::Locked { path } =>
+                write!(f, "Storage {} is in use by another process", path),
+            DbError::Corrupt { path, message } =>
+                write!(f, "Storage {} is corrupt: {}", path, message)
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<std::io::Error> for DbError {
+    fn from(value: std::io::Error) -> Self {
+        DbError::Io(value.to_string())
+    }
+}
+
+impl From<sled::Error> for DbError {
+    fn from(value: sled::Error) -> Self {
+        DbError::Io(value.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for DbError {
+    fn from(value: tokio::task::JoinError) -> Self {
+        DbError::Io(format!("Storage task failed: {}", value))
+    }
+}
+
+impl<T> From<std::sync::PoisonError<T>> for DbError {
+    fn from(value: std::sync::PoisonError<T>) -> Self {
+        DbError::LockPoisoned(value.to_string())
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(value: serde_json::Error) -> Self {
+        DbError::Serialization(value.to_string())
+    }
+}
+
+impl From<rmp_serde::encode::Error> for DbError {
+    fn from(value: rmp_serde::encode::Error) -> Self {
+        DbError::Serialization(value.to_string())
+    }
+}
+
+impl From<rmp_serde::decode::Error> for DbError {
+    fn from(value: rmp_serde::decode::Error) -> Self {
+        DbError::Serialization(value.to_string())
+    }
+}
+
+impl From<rmp::encode::ValueWriteError> for DbError {
+    fn from(value: rmp::encode::ValueWriteError) -> Self {
+        DbError::Serialization(value.to_string())
+    }
+}
+
+impl From<csv::Error> for DbError {
+    fn from(value: csv::Error) -> Self {
+        DbError::Serialization(value.to_string())
+    }
+}
+
+impl From<std::num::ParseIntError> for DbError {
+    fn from(value: std::num::ParseIntError) -> Self {
+        DbError::Serialization(value.to_string())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+use serde_json::Value;
+
+/// Flattens a record into `column -> cell` pairs. Nested objects become
+/// dotted columns such as `owner.name`; arrays are kept as JSON text.
+pub fn flatten(record: &Value) -> BTreeMap<String, String> {
+    let mut row = BTreeMap::new();
+    flatten_into(&mut row, None, record);
+
+    row
+}
+
+fn flatten_into(row: &mut BTreeMap<String, String>, prefix: Option<&str>, value: &Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let column = match prefix {
+                    Some(prefix) => format!("{}.{}", prefix, key),
+                    None => key.clone()
+                };
+                flatten_into(row, Some(&column), value);
+            }
+        },
+        Value::Null => { row.insert(prefix.unwrap_or_default().to_string(), String::new()); },
+        Value::String(value) => { row.insert(prefix.unwrap_or_default().to_string(), value.clone()); },
+        value => { row.insert(prefix.unwrap_or_default().to_string(), value.to_string()); }
+    }
+}
+
+/// Writes the records as CSV under a header of every column any of them has,
+/// leaving the cells of missing columns empty. Returns the number of rows.
+pub fn write_csv<'v, W: Write>(writer: W, records: impl Iterator<Item = &'v Value>) -> csv::Result<usize> {
+    let rows: Vec<_> = records.map(flatten).collect();
+    let columns: BTreeSet<&String> = rows.iter().flat_map(BTreeMap::keys).collect();
+
+    let mut csv = csv::Writer::from_writer(writer);
+
+    // An empty record would be written as `""`, so an empty table has no header
+    if !columns.is_empty() {
+        csv.write_record(&columns)?;
+    }
+
+    for row in &rows {
+        csv.write_record(columns.iter().map(|column| row.get(*column).map(String::as_str).unwrap_or_default()))?;
+    }
+
+    csv.flush()?;
+
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_write_csv() {
+        let records = [
+            json!({"id": 1, "name": "a, b", "owner": {"name": "x"}, "tags": [1, 2]}),
+            json!({"id": 2, "name": null, "active": true})
+        ];
+        let mut out = Vec::new();
+
+        let rows = write_csv(&mut out, records.iter()).unwrap();
+
+        assert_eq!(rows, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "active,id,name,owner.name,tags\n,1,\"a, b\",x,\"[1,2]\"\ntrue,2,,,\n"
+        );
+    }
+}
\ No newline at end of file
@@ -0,0 +1,118 @@
+use std::io::Read;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use super::DbError;
+
+/// Layout of the rows read by [`super::Db::import`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// A header row of columns followed by one record per row, as written by
+    /// [`super::Db::export_csv`]. Dotted columns become nested objects, cells
+    /// holding JSON such as `42`, `true` or `[1,2]` are parsed and empty cells
+    /// are left out.
+    Csv,
+    /// A JSON array of objects.
+    Json
+}
+
+/// A row that was not imported, numbered from 1 after any header.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RowError {
+    pub row: usize,
+    pub message: String
+}
+
+/// Outcome of [`super::Db::import`].
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    /// Ids assigned to the imported rows, in row order.
+    pub ids: Vec<u32>,
+    pub errors: Vec<RowError>
+}
+
+/// Parses every row of `reader`. Fails only if the input as a whole cannot be
+/// read; a malformed row is returned as its error message.
+pub fn read_rows<R: Read>(reader: R, format: ImportFormat) -> Result<Vec<Result<Value, String>>, DbError> {
+    match format {
+        ImportFormat::Json => {
+            let rows: Vec<Value> = serde_json::from_reader(reader)?;
+
+            Ok(rows
+                .into_iter()
+                .map(|row| match row {
+                    Value::Object(_) => Ok(row),
+                    other => Err(format!("Expected an object, found {}", other))
+                })
+                .collect())
+        },
+        ImportFormat::Csv => {
+            let mut csv = csv::Reader::from_reader(reader);
+            let columns = csv.headers()?.clone();
+
+            Ok(csv
+                .records()
+                .map(|record| {
+                    let record = record.map_err(|err| err.to_string())?;
+                    unflatten(columns.iter().zip(record.iter()))
+                })
+                .collect())
+        }
+    }
+}
+
+/// Builds a record from `(column, cell)` pairs, the reverse of [`super::export::flatten`].
+fn unflatten<'a>(cells: impl Iterator<Item = (&'a str, &'a str)>) -> Result<Value, String> {
+    let mut record = Map::new();
+
+    for (column, cell) in cells.filter(|(_, cell)| !cell.is_empty()) {
+        let value = serde_json::from_str(cell).unwrap_or_else(|_| Value::String(cell.to_string()));
+        let mut path: Vec<&str> = column.split('.').collect();
+        let Some(field) = path.pop() else { continue };
+
+        let mut fields = &mut record;
+
+        for key in path {
+            fields = fields
+                .entry(key)
+                .or_insert_with(|| Value::Object(Map::new()))
+                .as_object_mut()
+                .ok_or(format!("Column {} nests under a value", column))?;
+        }
+
+        fields.insert(field.to_string(), value);
+    }
+
+    Ok(Value::Object(record))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_read_csv_rows() {
+        let csv = "id,name,owner.name,tags,owner\n1,\"a, b\",x,\"[1,2]\",\n,plain,,,y\n";
+
+        let rows = read_rows(csv.as_bytes(), ImportFormat::Csv).unwrap();
+
+        assert_eq!(rows, vec![
+            Ok(json!({"id": 1, "name": "a, b", "owner": {"name": "x"}, "tags": [1, 2]})),
+            Ok(json!({"name": "plain", "owner": "y"}))
+        ]);
+
+        let rows = read_rows("owner,owner.name\ny,x\n".as_bytes(), ImportFormat::Csv).unwrap();
+        assert_eq!(rows, vec![Err("Column owner.name nests under a value".to_string())]);
+    }
+
+    #[test]
+    fn test_read_json_rows() {
+        let rows = read_rows(r#"[{"name": "a"}, 3]"#.as_bytes(), ImportFormat::Json).unwrap();
+
+        assert_eq!(rows, vec![Ok(json!({"name": "a"})), Err("Expected an object, found 3".to_string())]);
+        assert!(read_rows("[{".as_bytes(), ImportFormat::Json).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::Value;
+
+
+/// What an [`Index`] maps to record ids.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IndexKind {
+    /// The value of one column.
+    #[default]
+    Exact,
+    /// Every word in any string field of the record, see [`tokenize`].
+    Text
+}
+
+/// Splits text into lowercase words of letters and digits.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Words of every string in `value`, nested ones included.
+pub fn words(value: &Value) -> BTreeSet<String> {
+    let mut words = BTreeSet::new();
+    let mut pending = vec![value];
+
+    while let Some(value) = pending.pop() {
+        match value {
+            Value::String(text) => words.extend(tokenize(text)),
+            Value::Array(values) => pending.extend(values),
+            Value::Object(fields) => pending.extend(fields.values()),
+            _ => {}
+        }
+    }
+
+    words
+}
+
+/// Secondary index over one column of a table, mapping each value to the ids
+/// of the records holding it. A [`IndexKind::Text`] index maps words instead.
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    column: String,
+    kind: IndexKind,
+    entries: BTreeMap<String, BTreeSet<u32>>
+}
+
+impl Index {
+    pub fn new(column: String) -> Self {
+        Self { column, kind: IndexKind::Exact, entries: BTreeMap::new() }
+    }
+
+    /// Full-text index over the string fields of the records.
+    pub fn text() -> Self {
+        Self { column: String::new(), kind: IndexKind::Text, entries: BTreeMap::new() }
+    }
+
+    /// An index of the same column and kind holding no records.
+    pub fn empty_like(&self) -> Self {
+        Self { column: self.column.clone(), kind: self.kind, entries: BTreeMap::new() }
+    }
+
+    pub fn column(&self) -> &str {
+        &self.column
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        self.kind
+    }
+
+    /// Values are keyed by their json encoding so that e.g. the string `"1"`
+    /// and the number `1` stay distinct.
+    fn key(value: &Value) -> String {
+        serde_json::to_string(value).unwrap()
+    }
+
+    fn keys(&self, record: &Value) -> Vec<String> {
+        match self.kind {
+            IndexKind::Exact => record.get(&self.column).map(Self::key).into_iter().collect(),
+            IndexKind::Text => words(record).into_iter().collect()
+        }
+    }
+
+    pub fn insert(&mut self, id: u32, record: &Value) {
+        for key in self.keys(record) {
+            self.entries
+                .entry(key)
+                .or_default()
+                .insert(id);
+        }
+    }
+
+    pub fn remove(&mut self, id: u32, record: &Value) {
+        for key in self.keys(record) {
+            if let Some(ids) = self.entries.get_mut(&key) {
+                ids.remove(&id);
+
+                if ids.is_empty() {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns a value held by more than one record, if any.
+    pub fn find_duplicate(&self) -> Option<Value> {
+        self.entries
+            .iter()
+            .find(|(_, ids)| ids.len() > 1)
+            .map(|(key, _)| serde_json::from_str(key).unwrap())
+    }
+
+    pub fn get(&self, value: &Value) -> Vec<u32> {
+        self.entries
+            .get(&Self::key(value))
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Ids of the records containing every word of `query`, for a
+    /// [`IndexKind::Text`] index. A query without words matches nothing.
+    pub fn search(&self, query: &str) -> Vec<u32> {
+        let mut found: Option<BTreeSet<u32>> = None;
+
+        for word in tokenize(query) {
+            let ids = self.entries.get(&word).cloned().unwrap_or_default();
+
+            found = Some(match found {
+                Some(found) => found.intersection(&ids).copied().collect(),
+                None => ids
+            });
+        }
+
+        found.unwrap_or_default().into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_insert_remove() {
+        let mut index = Index::new("name".to_string());
+        let first = json!({"id": 1, "name": "foo"});
+        let second = json!({"id": 2, "name": "foo"});
+        index.insert(1, &first);
+        index.insert(2, &second);
+        index.insert(3, &json!({"id": 3}));
+
+        assert_eq!(index.get(&json!("foo")), vec![1, 2]);
+
+        assert_eq!(index.find_duplicate(), Some(json!("foo")));
+
+        index.remove(1, &first);
+        assert_eq!(index.get(&json!("foo")), vec![2]);
+        assert!(index.find_duplicate().is_none());
+        assert!(index.get(&json!("bar")).is_empty());
+    }
+
+    #[test]
+    fn test_text_search() {
+        let mut index = Index::text();
+        let first = json!({"id": 1, "name": "Red apple", "tags": ["fruit"]});
+        index.insert(1, &first);
+        index.insert(2, &json!({"id": 2, "name": "Green apple-pie"}));
+
+        assert_eq!(index.search("APPLE"), vec![1, 2]);
+        assert_eq!(index.search("apple fruit"), vec![1]);
+        assert!(index.search("apple banana").is_empty());
+        assert!(index.search(" ").is_empty());
+
+        index.remove(1, &first);
+        assert_eq!(index.search("apple"), vec![2]);
+    }
+
+    #[test]
+    fn test_keys_distinguish_types() {
+        let mut index = Index::new("value".to_string());
+        index.insert(1, &json!({"value": 1}));
+
+        assert!(index.get(&json!("1")).is_empty());
+        assert_eq!(index.get(&json!(1)), vec![1]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,71 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::Value;
+use uuid::Uuid;
+
+
+/// Field holding the key of a record.
+pub const ID_FIELD: &str = "id";
+
+/// How new records of a table are keyed, chosen in [`super::Db::add_table_with_key`].
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum KeyStrategy {
+    /// Ids counting up from 1, which tell how many records were made.
+    #[default]
+    Increment,
+    /// Random v4 uuids, for tables whose ids are exposed to clients.
+    Uuid
+}
+
+/// Key a record is looked up and stored by. Records are kept under a
+/// numeric row id either way, which `Id` addresses directly while `Uuid`
+/// is resolved through the table's index on [`ID_FIELD`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    Id(u32),
+    Uuid(Uuid)
+}
+
+impl From<u32> for Key {
+    fn from(value: u32) -> Self {
+        Key::Id(value)
+    }
+}
+
+impl From<Uuid> for Key {
+    fn from(value: Uuid) -> Self {
+        Key::Uuid(value)
+    }
+}
+
+/// The key as it is stored in [`ID_FIELD`].
+impl From<Key> for Value {
+    fn from(value: Key) -> Self {
+        match value {
+            Key::Id(id) => Value::from(id),
+            Key::Uuid(uuid) => Value::String(uuid.to_string())
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Id(id) => write!(f, "{}", id),
+            Key::Uuid(uuid) => write!(f, "{}", uuid)
+        }
+    }
+}
+
+/// Parses a key from a path segment, as a number if it is one.
+impl FromStr for Key {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u32>() {
+            Ok(id) => Ok(Key::Id(id)),
+            Err(_) => Ok(Key::Uuid(s.parse()?))
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use super::wal::WalEntry;
+
+
+/// Serialization work done for one table since the db was opened.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct SerdeStats {
+    /// Records converted from their typed form to be stored or encoded.
+    pub serialized: u64,
+    /// Records cloned and converted back to their typed form for a read, or
+    /// decoded by the storage.
+    pub deserialized: u64,
+    /// Bytes the storage encoded records into. Conversions between typed
+    /// records and json values produce no bytes and are not counted here.
+    pub bytes_serialized: u64,
+    pub bytes_deserialized: u64
+}
+
+/// Per-table [`SerdeStats`], shared with the storage and updated by reads
+/// through a shared `&Db` as well.
+#[derive(Clone, Default)]
+pub struct SerdeMetrics {
+    stats: Arc<Mutex<HashMap<String, SerdeStats>>>
+}
+
+impl SerdeMetrics {
+    pub fn record_serialize(&self, table_name: &str, records: usize, bytes: usize) {
+        self.update(table_name, |stats| {
+            stats.serialized += records as u64;
+            stats.bytes_serialized += bytes as u64;
+        });
+    }
+
+    pub fn record_deserialize(&self, table_name: &str, records: usize, bytes: usize) {
+        self.update(table_name, |stats| {
+            stats.deserialized += records as u64;
+            stats.bytes_deserialized += bytes as u64;
+        });
+    }
+
+    fn update<F: FnOnce(&mut SerdeStats)>(&self, table_name: &str, f: F) {
+        let mut stats = self.stats.lock().unwrap();
+
+        match stats.get_mut(table_name) {
+            Some(table) => f(table),
+            None => f(stats.entry(table_name.to_string()).or_default())
+        }
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, SerdeStats> {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// Lookups of the response cache in front of the db's reads, see
+/// [`crate::cache::ResponseCacheMiddleware`].
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64
+}
+
+/// [`CacheStats`] shared with the response cache, which counts into them.
+#[derive(Clone, Default)]
+pub struct CacheMetrics {
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>
+}
+
+impl CacheMetrics {
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed)
+        }
+    }
+}
+
+/// Mutations stored since the db was opened, counted once they are logged.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct OperationCounts {
+    pub upserts: u64,
+    pub deletes: u64,
+    pub clears: u64,
+    pub checkpoints: u64
+}
+
+impl OperationCounts {
+    pub fn record(&mut self, entries: &[WalEntry]) {
+        for entry in entries {
+            match entry {
+                WalEntry::Upsert { .. } => self.upserts += 1,
+                WalEntry::Delete { .. } => self.deletes += 1,
+                WalEntry::Clear { .. } => self.clears += 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct TableStats {
+    /// Records that are not soft deleted.
+    pub rows: usize,
+    pub soft_deleted: usize
+}
+
+/// Runtime statistics returned by [`super::Db::stats`].
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct DbStats {
+    pub tables: BTreeMap<String, TableStats>,
+    pub bytes_on_disk: u64,
+    /// Time the last checkpoint took to write out the tables, if there was one.
+    pub last_flush_micros: Option<u64>,
+    /// Entries logged since the last checkpoint.
+    pub pending_entries: usize,
+    pub operations: OperationCounts,
+    pub response_cache: CacheStats
+}
+
+impl From<DbStats> for serde_json::Value {
+    fn from(value: DbStats) -> Self {
+        serde_json::to_value(value).unwrap()
+    }
+}
+
+/// A table as listed by [`super::Db::list_tables`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TableInfo {
+    pub name: String,
+    /// Records that are not soft deleted.
+    pub rows: usize,
+    pub next_id: u32
+}
+
+impl From<TableInfo> for serde_json::Value {
+    fn from(value: TableInfo) -> Self {
+        serde_json::to_value(value).unwrap()
+    }
+}
\ No newline at end of file
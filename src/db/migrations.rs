@@ -0,0 +1,96 @@
+use serde::Serialize;
+use serde_json::Value;
+
+
+/// Upgrades every record of `table` to `version`. Migrations for a table run
+/// in version order, each only once, tracked by the table's `schema_version`.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub table: &'static str,
+    pub version: u32,
+    pub migrate: fn(&mut Value)
+}
+
+/// Migrations applied by `Db::init`. Append new ones here when a stored model
+/// changes shape, with a version higher than the table's previous migration.
+pub const MIGRATIONS: &[Migration] = &[];
+
+pub fn latest_version(migrations: &[Migration], table: &str) -> u32 {
+    migrations
+        .iter()
+        .filter(|migration| migration.table == table)
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Migrations for `table` newer than `current_version`, in the order they run.
+pub fn pending<'a>(migrations: &'a [Migration], table: &str, current_version: u32) -> Vec<&'a Migration> {
+    let mut pending: Vec<&Migration> = migrations
+        .iter()
+        .filter(|migration| migration.table == table && migration.version > current_version)
+        .collect();
+    pending.sort_by_key(|migration| migration.version);
+
+    pending
+}
+
+/// A record as it is stored and as the pending migrations would leave it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SampleChange {
+    pub id: u32,
+    pub before: Value,
+    pub after: Value
+}
+
+/// What migrating one table would do, see [`super::Db::plan_migrations`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TablePlan {
+    pub table: String,
+    pub from_version: u32,
+    pub to_version: u32,
+    pub records: usize,
+    /// Records the migrations would leave different from how they are stored.
+    pub changed: usize,
+    pub samples: Vec<SampleChange>
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct MigrationPlan {
+    pub tables: Vec<TablePlan>,
+    /// Time the dry run took to migrate every record in memory. Storing the
+    /// records and the checkpoint after come on top of it.
+    pub estimated_micros: u64
+}
+
+impl From<MigrationPlan> for Value {
+    fn from(value: MigrationPlan) -> Self {
+        serde_json::to_value(value).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop(_: &mut Value) {}
+
+    #[test]
+    fn test_pending_in_version_order() {
+        let migrations = [
+            Migration { table: "item", version: 2, migrate: noop },
+            Migration { table: "user", version: 1, migrate: noop },
+            Migration { table: "item", version: 1, migrate: noop }
+        ];
+
+        let versions: Vec<u32> = pending(&migrations, "item", 0)
+            .iter()
+            .map(|migration| migration.version)
+            .collect();
+
+        assert_eq!(versions, vec![1, 2]);
+        assert!(pending(&migrations, "item", 2).is_empty());
+        assert_eq!(latest_version(&migrations, "item"), 2);
+        assert_eq!(latest_version(&migrations, "other"), 0);
+    }
+}
\ No newline at end of file
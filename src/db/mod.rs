@@ -0,0 +1,3389 @@
+pub mod aggregate;
+pub mod encoded;
+pub mod error;
+pub mod export;
+pub mod import;
+pub mod index;
+pub mod key;
+pub mod metrics;
+pub mod migrations;
+pub mod query;
+pub mod relation;
+pub mod storage;
+pub mod table;
+pub mod transaction;
+pub mod wal;
+pub mod watch;
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Deserialize};
+use serde_json::{Number, Value};
+use tokio::sync::{broadcast, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use encoded::EncodedCache;
+pub use aggregate::Agg;
+pub use error::DbError;
+use import::{ImportFormat, ImportReport, RowError};
+use index::{Index, IndexKind};
+pub use key::{Key, KeyStrategy};
+use key::ID_FIELD;
+use metrics::{CacheMetrics, DbStats, OperationCounts, SerdeMetrics, SerdeStats, TableStats};
+pub use metrics::TableInfo;
+use migrations::{Migration, MigrationPlan, SampleChange, TablePlan, MIGRATIONS};
+use query::{compare_by_field, compare_numbers, Direction, Query};
+use relation::{OnDelete, Relation};
+use storage::codec::CodecKind;
+use storage::file::Compression;
+use storage::memory::MemoryStorage;
+use storage::{Storage, StorageBackend};
+use table::Table;
+use transaction::{TableSnapshot, Transaction};
+use wal::WalEntry;
+pub use watch::ChangeEvent;
+use watch::WATCH_CAPACITY;
+
+
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 100;
+
+/// Field set by [`Db::soft_delete_by_id`] to the unix timestamp of the deletion.
+pub const DELETED_AT_FIELD: &str = "deleted_at";
+
+/// Field counting the writes to a record of a table passed to [`Db::add_versioning`].
+pub const VERSION_FIELD: &str = "version";
+
+/// Fields stamped with unix timestamps on tables passed to [`Db::add_timestamps`].
+pub const CREATED_AT_FIELD: &str = "created_at";
+pub const UPDATED_AT_FIELD: &str = "updated_at";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct TableData {
+    next_id: u32,
+    #[serde(default)]
+    schema_version: u32,
+    /// Only used by backends that store records separately.
+    #[serde(default, skip_serializing_if = "CodecKind::is_default")]
+    codec: CodecKind,
+    data: BTreeMap<u32, Value>,
+    /// Unix timestamps after which records inserted with a ttl are purged.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    expires_at: BTreeMap<u32, i64>
+}
+
+/// A slice of a table along with what is needed to request the next one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+    pub next_offset: Option<usize>
+}
+
+impl<T> Page<T> {
+    /// Pages through records that are already loaded, e.g. after sorting them.
+    pub fn from_items(items: Vec<T>, offset: usize, limit: usize) -> Self {
+        let total = items.len();
+        let items: Vec<T> = items.into_iter().skip(offset).take(limit).collect();
+        let end = offset.saturating_add(items.len());
+
+        Self {
+            items,
+            total,
+            next_offset: (end < total).then_some(end)
+        }
+    }
+}
+
+impl<T: Serialize> From<Page<T>> for Value {
+    fn from(value: Page<T>) -> Self {
+        serde_json::to_value(value).unwrap()
+    }
+}
+
+/// The stored record from [`Db::upsert`], telling a new record apart from a
+/// replaced one so routes can answer `201 Created` or `200 OK`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpsertResult<T> {
+    Created(T),
+    Updated(T)
+}
+
+impl<T> UpsertResult<T> {
+    pub fn is_created(&self) -> bool {
+        matches!(self, UpsertResult::Created(_))
+    }
+
+    pub fn into_inner(self) -> T {
+        match self {
+            UpsertResult::Created(x) | UpsertResult::Updated(x) => x
+        }
+    }
+}
+
+/// Whether the record was soft deleted and should be left out of reads.
+pub(crate) fn is_deleted(record: &Value) -> bool {
+    record.get(DELETED_AT_FIELD).is_some_and(|deleted_at| !deleted_at.is_null())
+}
+
+/// Applies an RFC 7386 merge patch: objects are merged recursively, `null`
+/// removes a field and any other value replaces it.
+pub(crate) fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+
+    let target = target.as_object_mut().unwrap();
+
+    for (key, value) in patch {
+        match value {
+            Value::Null => {
+                target.remove(key);
+            },
+            value => merge_patch(target.entry(key.clone()).or_insert(Value::Null), value)
+        }
+    }
+}
+
+/// Checks that a record matches the shape a table expects.
+type Validator = fn(&Value) -> Result<(), String>;
+
+fn validate_as<T: DeserializeOwned>(value: &Value) -> Result<(), String> {
+    serde_json::from_value::<T>(value.clone())
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn check_table_name(table_name: &str) -> DbResult<()> {
+    match table_name.contains('/') {
+        true => Err(DbError::InvalidTableName(table_name.to_string())),
+        false => Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Db {
+    storage: Arc<Mutex<Box<dyn Storage>>>,
+    tables: HashMap<String, TableData>,
+    indexes: HashMap<String, Vec<Index>>,
+    unique_constraints: HashMap<String, Vec<String>>,
+    relations: Vec<Relation>,
+    validators: HashMap<String, Validator>,
+    timestamped_tables: HashSet<String>,
+    versioned_tables: HashSet<String>,
+    key_strategies: HashMap<String, KeyStrategy>,
+    migrations: Vec<Migration>,
+    pending_entries: usize,
+    checkpoint_interval: usize,
+    metrics: SerdeMetrics,
+    cache_metrics: CacheMetrics,
+    operations: OperationCounts,
+    last_flush: Option<Duration>,
+    encoded: EncodedCache,
+    watchers: HashMap<String, broadcast::Sender<ChangeEvent>>,
+    /// Events of an open transaction, sent only once it is stored.
+    held_events: Option<Vec<ChangeEvent>>
+}
+
+pub type DbResult<T> = Result<T, DbError>;
+
+impl Db {
+    
+    pub async fn init(file_name: String) -> DbResult<Self>{
+        Self::open(StorageBackend::Json, Compression::None, file_name).await
+    }
+
+    /// Loads the tables kept by `backend` at `path`, replaying anything logged
+    /// after they were last written out. Checkpoints of the file backends are
+    /// written with `compression`.
+    pub async fn open(backend: StorageBackend, compression: Compression, path: String) -> DbResult<Self> {
+        let mut db = Self::open_unmigrated(backend, compression, path).await?;
+        db.run_migrations(MIGRATIONS).await?;
+
+        Ok(db)
+    }
+
+    /// Like [`Db::open`] but leaves the tables on the schema versions they were
+    /// stored with, e.g. for [`Db::plan_migrations`].
+    pub async fn open_unmigrated(backend: StorageBackend, compression: Compression, path: String) -> DbResult<Self> {
+        let started = Instant::now();
+        let metrics = SerdeMetrics::default();
+        let mut storage = storage::open(backend, compression, path).await?;
+        storage.set_metrics(metrics.clone());
+        let (tables, entries) = storage.scan().await?;
+
+        println!(
+            "Loaded {} tables with {} records and {} logged entries in {:?}",
+            tables.len(),
+            tables.values().map(|table| table.data.len()).sum::<usize>(),
+            entries.len(),
+            started.elapsed()
+        );
+
+        let mut db = Self::with_storage(storage, tables);
+        db.metrics = metrics;
+
+        if !entries.is_empty() {
+            for entry in entries {
+                db.apply(entry);
+            }
+
+            db.checkpoint().await?;
+        }
+
+        Ok(db)
+    }
+
+    /// A db that never touches the disk, for tests and throwaway deployments.
+    /// Everything is lost when it is dropped.
+    pub fn in_memory() -> Self {
+        let mut db = Self::with_storage(Box::new(MemoryStorage), HashMap::new());
+        db.migrations = MIGRATIONS.to_vec();
+
+        db
+    }
+
+    fn with_storage(storage: Box<dyn Storage>, tables: HashMap<String, TableData>) -> Self {
+        Self {
+            storage: Arc::new(Mutex::new(storage)),
+            tables,
+            indexes: HashMap::new(),
+            unique_constraints: HashMap::new(),
+            relations: vec![],
+            validators: HashMap::new(),
+            timestamped_tables: HashSet::new(),
+            versioned_tables: HashSet::new(),
+            key_strategies: HashMap::new(),
+            migrations: vec![],
+            pending_entries: 0,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            metrics: SerdeMetrics::default(),
+            cache_metrics: CacheMetrics::default(),
+            operations: OperationCounts::default(),
+            last_flush: None,
+            encoded: EncodedCache::default(),
+            watchers: HashMap::new(),
+            held_events: None
+        }
+    }
+
+    /// Registers the migrations and upgrades every existing table still on an
+    /// older `schema_version`, checkpointing once if anything changed.
+    pub async fn run_migrations(&mut self, migrations: &[Migration]) -> DbResult<()> {
+        self.migrations.extend_from_slice(migrations);
+
+        let mut entries = vec![];
+
+        for (table_name, table) in &self.tables {
+            let pending = migrations::pending(migrations, table_name, table.schema_version);
+
+            let Some(latest) = pending.last() else {
+                continue
+            };
+
+            for (id, record) in &table.data {
+                let mut record = record.clone();
+
+                for migration in &pending {
+                    (migration.migrate)(&mut record);
+                }
+
+                entries.push(WalEntry::Upsert { table: table_name.clone(), id: *id, data: record });
+            }
+
+            entries.push(WalEntry::SchemaVersion { table: table_name.clone(), schema_version: latest.version });
+        }
+
+        if entries.is_empty() {
+            return Ok(())
+        }
+
+        self.commit_all(entries).await?;
+
+        self.checkpoint().await
+    }
+
+    /// Dry run of [`Db::run_migrations`] reporting the tables and records it
+    /// would change, with up to `samples` changed records per table. Nothing is
+    /// stored or registered.
+    pub fn plan_migrations(&self, migrations: &[Migration], samples: usize) -> MigrationPlan {
+        let started = Instant::now();
+        let mut plan = MigrationPlan::default();
+
+        for (table_name, table) in &self.tables {
+            let pending = migrations::pending(migrations, table_name, table.schema_version);
+
+            let Some(latest) = pending.last() else {
+                continue
+            };
+
+            let mut table_plan = TablePlan {
+                table: table_name.clone(),
+                from_version: table.schema_version,
+                to_version: latest.version,
+                records: table.data.len(),
+                changed: 0,
+                samples: vec![]
+            };
+
+            for (id, record) in &table.data {
+                let mut migrated = record.clone();
+
+                for migration in &pending {
+                    (migration.migrate)(&mut migrated);
+                }
+
+                if migrated == *record {
+                    continue
+                }
+
+                table_plan.changed += 1;
+
+                if table_plan.samples.len() < samples {
+                    table_plan.samples.push(SampleChange { id: *id, before: record.clone(), after: migrated });
+                }
+            }
+
+            plan.tables.push(table_plan);
+        }
+
+        plan.tables.sort_by(|a, b| a.table.cmp(&b.table));
+        plan.estimated_micros = started.elapsed().as_micros() as u64;
+
+        plan
+    }
+
+    /// Writes the current tables to a new timestamped file in `dir`, in the
+    /// same format as the json storage file, and returns its path.
+    pub async fn snapshot(&self, dir: &Path) -> DbResult<PathBuf> {
+        let contents = serde_json::to_vec(&self.tables)?;
+        let path = dir.join(format!("snapshot-{}.json", Utc::now().format("%Y%m%dT%H%M%S%.3fZ")));
+
+        tokio::fs::create_dir_all(dir).await?;
+        storage::file::write_atomic(&path, &contents).await?;
+
+        Ok(path)
+    }
+
+    /// Replaces every table with the contents of a file written by
+    /// [`Db::snapshot`], both in memory and in storage. Tables the snapshot
+    /// has on an older `schema_version` are first upgraded by the migrations
+    /// registered with [`Db::run_migrations`]. The snapshot is checked against
+    /// the table schemas and unique constraints after, and nothing changes if
+    /// it is malformed.
+    pub async fn restore(&mut self, path: &Path) -> DbResult<()> {
+        let malformed = |message: String| DbError::MalformedSnapshot {
+            path: path.to_string_lossy().to_string(),
+            message
+        };
+
+        let contents = tokio::fs::read_to_string(path).await.map_err(|err| malformed(err.to_string()))?;
+        let mut tables: HashMap<String, TableData> = serde_json::from_str(&contents).map_err(|err| malformed(err.to_string()))?;
+
+        for (table_name, table) in &mut tables {
+            let pending = migrations::pending(&self.migrations, table_name, table.schema_version);
+
+            let Some(latest) = pending.last() else {
+                continue
+            };
+
+            for record in table.data.values_mut() {
+                for migration in &pending {
+                    (migration.migrate)(record);
+                }
+            }
+
+            table.schema_version = latest.version;
+        }
+
+        let mut indexes: HashMap<String, Vec<Index>> = HashMap::new();
+
+        for (table_name, table) in &tables {
+            for (id, record) in &table.data {
+                self.validate(table_name, *id, record)?;
+            }
+
+            for existing in self.indexes.get(table_name).into_iter().flatten() {
+                let mut index = existing.empty_like();
+                let column = existing.column();
+
+                for (id, record) in &table.data {
+                    index.insert(*id, record);
+                }
+
+                let is_unique = existing.kind() == IndexKind::Exact
+                    && self.unique_constraints.get(table_name).is_some_and(|columns| columns.iter().any(|x| x == column));
+
+                if let Some(value) = index.find_duplicate().filter(|_| is_unique) {
+                    return Err(DbError::UniqueViolation {
+                        table: table_name.clone(),
+                        column: column.to_string(),
+                        value
+                    })
+                }
+
+                indexes.entry(table_name.clone()).or_default().push(index);
+            }
+        }
+
+        self.storage.lock().await.replace(&tables).await?;
+        let replaced: HashSet<String> = self.tables.keys().chain(tables.keys()).cloned().collect();
+        self.tables = tables;
+
+        for table in replaced {
+            self.notify(&table, || ChangeEvent::Cleared { table: table.clone() });
+        }
+
+        self.indexes = indexes;
+        self.encoded.clear();
+        self.pending_entries = 0;
+
+        Ok(())
+    }
+
+    /// Sets how many logged mutations may accumulate before they are
+    /// checkpointed into the main file.
+    pub fn set_checkpoint_interval(&mut self, interval: usize) {
+        self.checkpoint_interval = interval.max(1);
+    }
+
+    /// Writes the current tables out in full and clears what was logged since
+    /// the last checkpoint.
+    pub async fn checkpoint(&mut self) -> DbResult<()> {
+        let started = Instant::now();
+        self.storage.lock().await.checkpoint(&self.tables).await?;
+        self.last_flush = Some(started.elapsed());
+        self.operations.checkpoints += 1;
+        self.pending_entries = 0;
+
+        Ok(())
+    }
+
+    /// Whether anything was logged since the last checkpoint.
+    pub fn is_dirty(&self) -> bool {
+        self.pending_entries > 0
+    }
+
+    /// Checkpoints if anything is pending, e.g. on a timer or before shutting
+    /// down so the next start has no log to replay.
+    pub async fn flush_now(&mut self) -> DbResult<()> {
+        if self.is_dirty() {
+            self.checkpoint().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Hands the mutation to the storage before applying it to the in-memory
+    /// tables, so it can be replayed if the process dies before the next checkpoint.
+    async fn commit(&mut self, entry: WalEntry) -> DbResult<()> {
+        self.commit_all(vec![entry]).await
+    }
+
+    async fn commit_all(&mut self, entries: Vec<WalEntry>) -> DbResult<()> {
+        self.storage.lock().await.put(&entries).await?;
+        self.operations.record(&entries);
+
+        let count = entries.len();
+
+        for entry in entries {
+            self.apply(entry);
+        }
+
+        self.add_pending_entries(count).await
+    }
+
+    async fn add_pending_entries(&mut self, count: usize) -> DbResult<()> {
+        self.pending_entries += count;
+
+        if self.pending_entries >= self.checkpoint_interval {
+            self.checkpoint().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs `f` against a [`Transaction`] whose mutations are visible to its own
+    /// reads. If `f` succeeds they are logged together with a single flush,
+    /// otherwise every table it touched is rolled back.
+    pub async fn transaction<F, R>(&mut self, f: F) -> DbResult<R> 
+        where F: FnOnce(&mut Transaction<'_>) -> DbResult<R>
+    {
+        self.held_events = Some(vec![]);
+
+        let (value, entries, originals) = {
+            let mut tx = Transaction::new(self);
+            let result = f(&mut tx);
+            let (entries, originals) = tx.into_parts();
+
+            match result {
+                Ok(value) => (value, entries, originals),
+                Err(err) => {
+                    self.held_events = None;
+                    self.restore_tables(originals);
+                    return Err(err)
+                }
+            }
+        };
+
+        let events = self.held_events.take().unwrap_or_default();
+
+        if entries.is_empty() {
+            return Ok(value)
+        }
+
+        let storage = self.storage.clone();
+
+        if let Err(err) = storage.lock().await.put(&entries).await {
+            self.restore_tables(originals);
+            return Err(err)
+        }
+
+        self.operations.record(&entries);
+
+        for event in events {
+            if let Some(sender) = self.watchers.get(event.table()) {
+                let _ = sender.send(event);
+            }
+        }
+
+        self.add_pending_entries(entries.len()).await?;
+
+        Ok(value)
+    }
+
+    fn restore_tables(&mut self, originals: HashMap<String, TableSnapshot>) {
+        for (table_name, (table, indexes)) in originals {
+            self.encoded.invalidate_table(&table_name);
+
+            match table {
+                Some(table) => self.tables.insert(table_name.clone(), table),
+                None => self.tables.remove(&table_name)
+            };
+
+            match indexes {
+                Some(indexes) => self.indexes.insert(table_name, indexes),
+                None => self.indexes.remove(&table_name)
+            };
+        }
+    }
+
+    fn apply(&mut self, entry: WalEntry) {
+        match entry {
+            WalEntry::AddTable { table, schema_version } => {
+                self.indexes_mut(&table).for_each(Index::clear);
+                self.encoded.invalidate_table(&table);
+                self.tables.insert(
+                    table, 
+                    TableData{ 
+                        next_id: 1,
+                        schema_version,
+                        codec: CodecKind::default(),
+                        data: BTreeMap::new(),
+                        expires_at: BTreeMap::new()
+                     });
+            },
+            WalEntry::NextId { table, next_id } => {
+                if let Some(table) = self.tables.get_mut(&table) {
+                    table.next_id = next_id;
+                }
+            },
+            WalEntry::SchemaVersion { table, schema_version } => {
+                if let Some(table) = self.tables.get_mut(&table) {
+                    table.schema_version = schema_version;
+                }
+            },
+            WalEntry::Codec { table, codec } => {
+                if let Some(table) = self.tables.get_mut(&table) {
+                    table.codec = codec;
+                }
+            },
+            WalEntry::Upsert { table, id, data } => {
+                if let Some(table_data) = self.tables.get_mut(&table) {
+                    // A write without a ttl makes the record permanent, an
+                    // `Expire` logged after it sets the ttl again
+                    table_data.expires_at.remove(&id);
+                    let previous = table_data.data.insert(id, data.clone());
+                    self.encoded.invalidate(&table, id);
+
+                    for index in self.indexes_mut(&table) {
+                        if let Some(previous) = &previous {
+                            index.remove(id, previous);
+                        }
+                        index.insert(id, &data);
+                    }
+
+                    self.notify(&table, || match previous {
+                        Some(_) => ChangeEvent::Updated { table: table.clone(), id, data },
+                        None => ChangeEvent::Inserted { table: table.clone(), id, data }
+                    });
+                }
+            },
+            WalEntry::Expire { table, id, expires_at } => {
+                if let Some(table) = self.tables.get_mut(&table) {
+                    table.expires_at.insert(id, expires_at);
+                }
+            },
+            WalEntry::Delete { table, id } => {
+                if let Some(table_data) = self.tables.get_mut(&table) {
+                    table_data.expires_at.remove(&id);
+
+                    if let Some(previous) = table_data.data.remove(&id) {
+                        self.indexes_mut(&table).for_each(|index| index.remove(id, &previous));
+                        self.encoded.invalidate(&table, id);
+                        self.notify(&table, || ChangeEvent::Deleted { table: table.clone(), id });
+                    }
+                }
+            },
+            WalEntry::Clear { table } => {
+                if let Some(table_data) = self.tables.get_mut(&table) {
+                    table_data.data.clear();
+                    table_data.expires_at.clear();
+                    self.indexes_mut(&table).for_each(Index::clear);
+                    self.encoded.invalidate_table(&table);
+                    self.notify(&table, || ChangeEvent::Cleared { table: table.clone() });
+                }
+            },
+            WalEntry::DropTable { table } => {
+                if self.tables.remove(&table).is_some() {
+                    self.indexes_mut(&table).for_each(Index::clear);
+                    self.encoded.invalidate_table(&table);
+                    self.notify(&table, || ChangeEvent::Cleared { table: table.clone() });
+                }
+            },
+            WalEntry::RenameTable { table, to } => {
+                if let Some(data) = self.tables.remove(&table) {
+                    self.encoded.invalidate_table(&table);
+                    self.encoded.invalidate_table(&to);
+                    self.notify(&table, || ChangeEvent::Cleared { table: table.clone() });
+                    self.tables.insert(to.clone(), data);
+
+                    if let Some(indexes) = self.indexes.remove(&table) {
+                        self.indexes.insert(to, indexes);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the event to the table's watchers, building it only if there are
+    /// any, or holds it back until the open transaction is stored.
+    fn notify(&mut self, table_name: &str, event: impl FnOnce() -> ChangeEvent) {
+        let Some(sender) = self.watchers.get(table_name).filter(|sender| sender.receiver_count() > 0) else {
+            return
+        };
+
+        match &mut self.held_events {
+            Some(held) => held.push(event()),
+            None => { let _ = sender.send(event()); }
+        }
+    }
+
+    /// Subscribes to the inserts, updates and deletes applied to the table from
+    /// now on. Writes rolled back in a transaction are never sent; a receiver
+    /// more than [`WATCH_CAPACITY`] events behind gets `RecvError::Lagged`.
+    pub fn watch(&mut self, table_name: String) -> broadcast::Receiver<ChangeEvent> {
+        self.watchers
+            .entry(table_name)
+            .or_insert_with(|| broadcast::channel(WATCH_CAPACITY).0)
+            .subscribe()
+    }
+
+    fn index(&self, table_name: &str, column: &str) -> Option<&Index> {
+        self.indexes
+            .get(table_name)?
+            .iter()
+            .find(|index| index.kind() == IndexKind::Exact && index.column() == column)
+    }
+
+    fn search_index(&self, table_name: &str) -> Option<&Index> {
+        self.indexes
+            .get(table_name)?
+            .iter()
+            .find(|index| index.kind() == IndexKind::Text)
+    }
+
+    fn indexes_mut(&mut self, table_name: &str) -> impl Iterator<Item = &mut Index> {
+        self.indexes
+            .get_mut(table_name)
+            .into_iter()
+            .flatten()
+    }
+
+    /// Indexes `column` of the table so [`Db::find_by_value`] can look records
+    /// up directly instead of scanning. Returns `false` if the table does not exist.
+    pub fn add_index(&mut self, table_name: String, column: String) -> bool {
+        let Some(table) = self.tables.get(&table_name) else {
+            return false
+        };
+
+        let indexes = self.indexes.entry(table_name).or_default();
+
+        if indexes.iter().any(|index| index.kind() == IndexKind::Exact && index.column() == column) {
+            return true
+        }
+
+        let mut index = Index::new(column);
+
+        for (id, record) in &table.data {
+            index.insert(*id, record);
+        }
+
+        indexes.push(index);
+
+        true
+    }
+
+    /// Indexes the words of every string field of the table for
+    /// [`Db::search`]. Returns `false` if the table does not exist.
+    pub fn add_search_index(&mut self, table_name: String) -> bool {
+        let Some(table) = self.tables.get(&table_name) else {
+            return false
+        };
+
+        let indexes = self.indexes.entry(table_name).or_default();
+
+        if indexes.iter().any(|index| index.kind() == IndexKind::Text) {
+            return true
+        }
+
+        let mut index = Index::text();
+
+        for (id, record) in &table.data {
+            index.insert(*id, record);
+        }
+
+        indexes.push(index);
+
+        true
+    }
+
+    /// Makes `insert_or_update` reject records whose `column` value is already
+    /// held by another record. Returns `false` if the table does not exist.
+    pub fn add_unique_constraint(&mut self, table_name: String, column: String) -> DbResult<bool> {
+        if !self.add_index(table_name.clone(), column.clone()) {
+            return Ok(false)
+        }
+
+        if let Some(value) = self.index(&table_name, &column).and_then(Index::find_duplicate) {
+            return Err(DbError::UniqueViolation { table: table_name, column, value })
+        }
+
+        let constraints = self.unique_constraints.entry(table_name).or_default();
+
+        if !constraints.contains(&column) {
+            constraints.push(column);
+        }
+
+        Ok(true)
+    }
+
+    /// Declares that `column` of the table holds ids of records in `references`,
+    /// so [`Db::delete_by_id`] on a referenced record restricts or cascades as
+    /// `on_delete` says. Writes are not checked against the relation. Returns
+    /// `false` if either table does not exist.
+    pub fn add_foreign_key(&mut self, table_name: String, column: String, references: String, on_delete: OnDelete) -> bool {
+        if !self.tables.contains_key(&references) || !self.add_index(table_name.clone(), column.clone()) {
+            return false
+        }
+
+        self.relations.retain(|relation| relation.table != table_name || relation.column != column);
+        self.relations.push(Relation { table: table_name, column, references, on_delete });
+
+        true
+    }
+
+    /// Lists the deletes removing the record and whatever cascades from it,
+    /// failing if a restricting relation still points at any of them.
+    fn plan_delete(&self, table_name: &str, id: u32) -> Result<Vec<WalEntry>, DbError> {
+        self.plan_deletes(vec![(table_name.to_string(), id)])
+    }
+
+    /// Like [`Db::plan_delete`] for several records at once. Records referencing
+    /// one another within the batch do not restrict each other.
+    fn plan_deletes(&self, targets: Vec<(String, u32)>) -> Result<Vec<WalEntry>, DbError> {
+        let batch = targets.iter().cloned().collect();
+
+        self.plan_deletes_within(targets, &batch)
+    }
+
+    /// Plans deleting `targets` as part of a larger `batch` of records being
+    /// deleted, none of which restrict the targets.
+    fn plan_deletes_within(&self, targets: Vec<(String, u32)>, batch: &HashSet<(String, u32)>) -> Result<Vec<WalEntry>, DbError> {
+        let mut entries = vec![];
+        let mut visited = HashSet::new();
+        let mut deleting: HashSet<(String, u32)> = HashSet::new();
+        let mut pending = targets;
+
+        while let Some((table_name, id)) = pending.pop() {
+            if !visited.insert((table_name.clone(), id)) {
+                continue
+            }
+
+            for relation in self.relations.iter().filter(|relation| relation.references == table_name) {
+                let referencing: Vec<u32> = self.index(&relation.table, &relation.column)
+                    .map(|index| index.get(&Value::from(id)))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|other| {
+                        let key = (relation.table.clone(), *other);
+                        !batch.contains(&key) && !deleting.contains(&key)
+                    })
+                    .collect();
+
+                if referencing.is_empty() {
+                    continue
+                }
+
+                match relation.on_delete {
+                    OnDelete::Restrict => return Err(DbError::ForeignKeyViolation {
+                        table: relation.table.clone(),
+                        column: relation.column.clone(),
+                        id
+                    }),
+                    OnDelete::Cascade => {
+                        for other in referencing {
+                            deleting.insert((relation.table.clone(), other));
+                            pending.push((relation.table.clone(), other));
+                        }
+                    }
+                }
+            }
+
+            entries.push(WalEntry::Delete { table: table_name, id });
+        }
+
+        Ok(entries)
+    }
+
+    /// Checks a record about to be stored under `id` against the table's schema.
+    fn validate(&self, table_name: &str, id: u32, record: &Value) -> Result<(), DbError> {
+        match self.validators.get(table_name) {
+            Some(validator) => {
+                self.metrics.record_deserialize(table_name, 1, 0);
+
+                validator(record).map_err(|message| DbError::Validation {
+                    table: table_name.to_string(),
+                    id,
+                    message
+                })
+            },
+            None => Ok(())
+        }
+    }
+
+    /// Checks a record about to be stored under `id` against the table's
+    /// unique constraints.
+    fn check_unique(&self, table_name: &str, id: u32, record: &Value) -> Result<(), DbError> {
+        for column in self.unique_constraints.get(table_name).into_iter().flatten() {
+            let Some(value) = record.get(column).filter(|value| !value.is_null()) else {
+                continue
+            };
+
+            let is_taken = self.index(table_name, column)
+                .map(|index| index.get(value).into_iter().any(|other| other != id))
+                .unwrap_or(false);
+
+            if is_taken {
+                return Err(DbError::UniqueViolation {
+                    table: table_name.to_string(),
+                    column: column.clone(),
+                    value: value.clone()
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes every write to the table set [`UPDATED_AT_FIELD`], and
+    /// [`CREATED_AT_FIELD`] when the record is first inserted.
+    pub fn add_timestamps(&mut self, table_name: String) {
+        self.timestamped_tables.insert(table_name);
+    }
+
+    /// Makes every write to the table bump the record's [`VERSION_FIELD`],
+    /// starting at 1, for [`Db::insert_or_update_versioned`] to check against.
+    pub fn add_versioning(&mut self, table_name: String) {
+        self.versioned_tables.insert(table_name);
+    }
+
+    /// Version of the record stored under `id`, 0 if there is none.
+    fn version(&self, table_name: &str, id: u32) -> u64 {
+        self.tables
+            .get(table_name)
+            .and_then(|table| table.data.get(&id))
+            .and_then(|record| record.get(VERSION_FIELD))
+            .and_then(Value::as_u64)
+            .unwrap_or(0)
+    }
+
+    /// Stamps a record about to be stored under `id` if its table is timestamped,
+    /// carrying `created_at` over from the stored record on updates, and bumps
+    /// its version if the table is versioned.
+    fn stamp(&self, table_name: &str, id: u32, record: &mut Value) {
+        if self.versioned_tables.contains(table_name) {
+            if let Some(fields) = record.as_object_mut() {
+                fields.insert(VERSION_FIELD.to_string(), Value::from(self.version(table_name, id) + 1));
+            }
+        }
+
+        if !self.timestamped_tables.contains(table_name) {
+            return
+        }
+
+        let now = Value::from(Utc::now().timestamp());
+        let created_at = self.tables
+            .get(table_name)
+            .and_then(|table| table.data.get(&id))
+            .and_then(|previous| previous.get(CREATED_AT_FIELD))
+            .cloned()
+            .unwrap_or(now.clone());
+
+        if let Some(fields) = record.as_object_mut() {
+            fields.insert(CREATED_AT_FIELD.to_string(), created_at);
+            fields.insert(UPDATED_AT_FIELD.to_string(), now);
+        }
+    }
+
+    /// Adds the table like [`Db::add_table`] and rejects any record written to
+    /// it that does not deserialize into `T`.
+    pub async fn add_table_with_schema<T>(&mut self, table_name: String, is_recreate: bool) -> DbResult<()> 
+        where T: DeserializeOwned
+    {
+        self.validators.insert(table_name.clone(), validate_as::<T>);
+
+        self.add_table(table_name, is_recreate).await
+    }
+
+    /// Adds the table like [`Db::add_table`] with new records keyed by
+    /// `strategy`, see [`Db::new_key`]. Uuid keys are indexed and kept unique.
+    pub async fn add_table_with_key(&mut self, table_name: String, is_recreate: bool, strategy: KeyStrategy) -> DbResult<()> {
+        self.add_table(table_name.clone(), is_recreate).await?;
+        self.key_strategies.insert(table_name.clone(), strategy);
+
+        if strategy == KeyStrategy::Uuid {
+            self.add_unique_constraint(table_name, ID_FIELD.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    /// A key for a new record of the table, or `None` if it does not exist.
+    pub async fn new_key(&mut self, table_name: String) -> DbResult<Option<Key>> {
+        match self.key_strategies.get(&table_name).copied().unwrap_or_default() {
+            KeyStrategy::Increment => Ok(self.get_increment_last_id(table_name).await?.map(Key::Id)),
+            KeyStrategy::Uuid => Ok(self.tables.contains_key(&table_name).then(|| Key::Uuid(Uuid::new_v4())))
+        }
+    }
+
+    /// Row id of the record stored under `key`, if there is one.
+    fn row_id(&self, table_name: &str, key: Key) -> Option<u32> {
+        match key {
+            Key::Id(id) => Some(id),
+            Key::Uuid(_) => self.index(table_name, ID_FIELD)?.get(&Value::from(key)).first().copied()
+        }
+    }
+
+    pub async fn add_table(&mut self, table_name: String, is_recreate: bool) -> DbResult<()> {
+        check_table_name(&table_name)?;
+
+        if !is_recreate && self.tables.contains_key(&table_name) {
+            println!("Table already exists!");
+            return Ok(())
+        }
+
+        // A new table holds no old records, so it starts at the latest version
+        let schema_version = migrations::latest_version(&self.migrations, &table_name);
+        self.commit(WalEntry::AddTable { table: table_name, schema_version }).await?;
+
+        Ok(())
+    }
+
+    /// Removes the table with all its records, indexes, constraints and the
+    /// foreign keys from or to it, then checkpoints. Returns `false` if there
+    /// is no such table.
+    pub async fn drop_table(&mut self, table_name: String) -> DbResult<bool> {
+        if !self.tables.contains_key(&table_name) {
+            return Ok(false)
+        }
+
+        self.commit(WalEntry::DropTable { table: table_name.clone() }).await?;
+
+        self.indexes.remove(&table_name);
+        self.unique_constraints.remove(&table_name);
+        self.validators.remove(&table_name);
+        self.timestamped_tables.remove(&table_name);
+        self.versioned_tables.remove(&table_name);
+        self.key_strategies.remove(&table_name);
+        self.relations.retain(|relation| relation.table != table_name && relation.references != table_name);
+
+        self.checkpoint().await?;
+
+        Ok(true)
+    }
+
+    /// Renames the table, keeping its records, `next_id`, indexes, constraints
+    /// and foreign keys, then checkpoints. Returns `false` if there is no table
+    /// `from` or there already is one named `to`.
+    pub async fn rename_table(&mut self, from: String, to: String) -> DbResult<bool> {
+        check_table_name(&to)?;
+
+        if !self.tables.contains_key(&from) || self.tables.contains_key(&to) {
+            return Ok(false)
+        }
+
+        self.commit(WalEntry::RenameTable { table: from.clone(), to: to.clone() }).await?;
+
+        fn rename<V>(map: &mut HashMap<String, V>, from: &str, to: &str) {
+            if let Some(value) = map.remove(from) {
+                map.insert(to.to_string(), value);
+            }
+        }
+
+        rename(&mut self.unique_constraints, &from, &to);
+        rename(&mut self.validators, &from, &to);
+        rename(&mut self.key_strategies, &from, &to);
+        rename(&mut self.watchers, &from, &to);
+
+        for tables in [&mut self.timestamped_tables, &mut self.versioned_tables] {
+            if tables.remove(&from) {
+                tables.insert(to.clone());
+            }
+        }
+
+        for relation in &mut self.relations {
+            if relation.table == from {
+                relation.table = to.clone();
+            }
+            if relation.references == from {
+                relation.references = to.clone();
+            }
+        }
+
+        self.checkpoint().await?;
+
+        Ok(true)
+    }
+
+    /// Clones and deserializes records read from the table, counting them in
+    /// its [`SerdeStats`].
+    fn decode<'v, T>(&self, table_name: &str, records: impl Iterator<Item = &'v Value>) -> Vec<T> 
+        where T: DeserializeOwned
+    {
+        let records: Vec<T> = records
+            .cloned()
+            .map(|x| serde_json::from_value::<T>(x).unwrap())
+            .collect();
+        self.metrics.record_deserialize(table_name, records.len(), 0);
+
+        records
+    }
+
+    /// Serialization work done per table since the db was opened.
+    pub fn serde_stats(&self) -> HashMap<String, SerdeStats> {
+        self.metrics.snapshot()
+    }
+
+    /// Every table by name, with its row count and the id the next record gets.
+    pub fn list_tables(&self) -> Vec<TableInfo> {
+        let mut tables: Vec<TableInfo> = self.tables
+            .iter()
+            .map(|(name, table)| TableInfo {
+                name: name.clone(),
+                rows: table.data.values().filter(|x| !is_deleted(x)).count(),
+                next_id: table.next_id
+            })
+            .collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+        tables
+    }
+
+    /// Row counts per table along with storage and operation counters.
+    pub async fn stats(&self) -> DbStats {
+        let tables = self.tables
+            .iter()
+            .map(|(name, table)| {
+                let soft_deleted = table.data.values().filter(|x| is_deleted(x)).count();
+                (name.clone(), TableStats { rows: table.data.len() - soft_deleted, soft_deleted })
+            })
+            .collect();
+
+        DbStats {
+            tables,
+            bytes_on_disk: self.storage.lock().await.size_on_disk(),
+            last_flush_micros: self.last_flush.map(|elapsed| elapsed.as_micros() as u64),
+            pending_entries: self.pending_entries,
+            operations: self.operations,
+            response_cache: self.cache_metrics.snapshot()
+        }
+    }
+
+    /// Counters for a response cache to report its hits and misses into, which
+    /// [`Db::stats`] returns along with the db's own.
+    pub fn cache_metrics(&self) -> CacheMetrics {
+        self.cache_metrics.clone()
+    }
+
+    pub fn find_all<T>(&self, table_name: String) -> Option<Vec<T>> 
+        where T: DeserializeOwned
+    {
+        if let Some(table) = self.tables.get(&table_name) {
+            return Some(self.decode(&table_name, table.data.values().filter(|x| !is_deleted(x))));
+        }
+
+        None
+    }
+
+    /// Like [`Db::find_all`] but including soft deleted records.
+    pub fn find_all_with_deleted<T>(&self, table_name: String) -> Option<Vec<T>> 
+        where T: DeserializeOwned
+    {
+        let table = self.tables.get(&table_name)?;
+
+        Some(self.decode(&table_name, table.data.values()))
+    }
+
+    /// Writes the table's live records to `writer` as CSV in id order, see
+    /// [`export::write_csv`]. Returns the number of rows written, or `None` if
+    /// the table does not exist.
+    pub fn export_csv<W: std::io::Write>(&self, table_name: String, writer: W) -> DbResult<Option<usize>> {
+        let Some(table) = self.tables.get(&table_name) else {
+            return Ok(None)
+        };
+
+        Ok(Some(export::write_csv(writer, table.data.values().filter(|x| !is_deleted(x)))?))
+    }
+
+    /// Inserts every row read from `reader` under a fresh id in one batch,
+    /// stamping and validating each like [`Db::insert_or_update`]. Rows that fail
+    /// are reported and skipped, along with any id they took, while the rest are
+    /// still imported. Returns `None` if the table does not exist.
+    pub async fn import<R: std::io::Read>(&mut self, table_name: String, reader: R, format: ImportFormat) -> DbResult<Option<ImportReport>> {
+        if !self.tables.contains_key(&table_name) {
+            return Ok(None)
+        }
+
+        let rows = import::read_rows(reader, format)?;
+
+        let report = self.transaction(|tx| {
+            let mut report = ImportReport::default();
+
+            for (index, row) in rows.into_iter().enumerate() {
+                let result = row.and_then(|mut record| {
+                    let id = tx.get_increment_last_id(table_name.clone()).map_err(|err| err.to_string())?;
+                    record[ID_FIELD] = Value::from(id);
+
+                    tx.insert_or_update::<Value>(table_name.clone(), id, record)
+                        .map(|_| id)
+                        .map_err(|err| err.to_string())
+                });
+
+                match result {
+                    Ok(id) => report.ids.push(id),
+                    Err(message) => report.errors.push(RowError { row: index + 1, message })
+                }
+            }
+
+            Ok(report)
+        }).await?;
+
+        Ok(Some(report))
+    }
+
+    pub fn table<T>(&self, table_name: &str) -> Table<&Db, T> {
+        Table::new(self, table_name)
+    }
+
+    pub fn table_mut<T>(&mut self, table_name: &str) -> Table<&mut Db, T> {
+        Table::new(self, table_name)
+    }
+
+    pub fn query<T>(&self, table_name: &str) -> Query<'_, T> 
+        where T: DeserializeOwned
+    {
+        Query::new(self.tables.get(table_name))
+    }
+
+    /// Like [`Db::find_all`] but ordered by `field`, comparing numbers by value
+    /// and strings lexicographically. Records missing the field come last.
+    pub fn find_all_sorted<T>(&self, table_name: String, field: String, direction: Direction) -> Option<Vec<T>> 
+        where T: DeserializeOwned
+    {
+        let table = self.tables.get(&table_name)?;
+
+        let mut records: Vec<&Value> = table.data.values().filter(|x| !is_deleted(x)).collect();
+        records.sort_by(|a, b| compare_by_field(a, b, &field, direction));
+
+        Some(self.decode(&table_name, records.into_iter()))
+    }
+
+    pub fn find_page<T>(&self, table_name: String, offset: usize, limit: usize) -> Option<Page<T>> 
+        where T: DeserializeOwned
+    {
+        if let Some(table) = self.tables.get(&table_name) {
+            let total = table.data.values().filter(|x| !is_deleted(x)).count();
+            let items: Vec<T> = self.decode(
+                &table_name,
+                table.data.values().filter(|x| !is_deleted(x)).skip(offset).take(limit)
+            );
+            let end = offset.saturating_add(items.len());
+
+            return Some(Page {
+                items,
+                total,
+                next_offset: (end < total).then_some(end)
+            });
+        }
+
+        None
+    }
+
+    /// Like [`Db::find_page`] but with each record as its json encoding, which
+    /// is kept and handed out again until the record changes. Records come as
+    /// stored rather than through a typed struct.
+    pub fn find_page_encoded(&self, table_name: String, offset: usize, limit: usize) -> Option<Page<Arc<[u8]>>> {
+        let table = self.tables.get(&table_name)?;
+        let total = table.data.values().filter(|x| !is_deleted(x)).count();
+        let (mut misses, mut bytes) = (0, 0);
+
+        let items: Vec<Arc<[u8]>> = table
+            .data
+            .iter()
+            .filter(|(_, x)| !is_deleted(x))
+            .skip(offset)
+            .take(limit)
+            .map(|(id, x)| {
+                let (encoded, is_miss) = self.encoded.get_or_encode(&table_name, *id, x);
+
+                if is_miss {
+                    misses += 1;
+                    bytes += encoded.len();
+                }
+
+                encoded
+            })
+            .collect();
+        self.metrics.record_serialize(&table_name, misses, bytes);
+
+        let end = offset.saturating_add(items.len());
+
+        Some(Page {
+            items,
+            total,
+            next_offset: (end < total).then_some(end)
+        })
+    }
+
+    pub fn find_by_value<T>(&self, table_name: String, column: String, value: String) -> Option<Vec<T>> 
+        where T: DeserializeOwned
+    {
+        let table = self.tables.get(&table_name)?;
+
+        if let Some(index) = self.index(&table_name, &column) {
+            let ids = index.get(&Value::String(value));
+
+            return Some(self.decode(
+                &table_name,
+                ids.into_iter().filter_map(|id| table.data.get(&id)).filter(|x| !is_deleted(x))
+            ));
+        }
+
+        let matching = table
+            .data
+            .values()
+            .filter(|x| !is_deleted(x))
+            .filter(|x| {
+                let result = x.get(column.clone());
+                
+                if let Some(val) = result {
+                    return *val == *value
+                }
+
+                false
+            });
+
+        Some(self.decode(&table_name, matching))
+    }
+
+    /// Records whose `column` is a number from `min` to `max` inclusive, with
+    /// integers compared exactly. Records where it is missing or not a json
+    /// number, such as the string `"5"`, are left out.
+    pub fn find_in_range<T>(&self, table_name: String, column: String, min: Number, max: Number) -> Option<Vec<T>> 
+        where T: DeserializeOwned
+    {
+        let table = self.tables.get(&table_name)?;
+
+        let matching = table
+            .data
+            .values()
+            .filter(|x| !is_deleted(x))
+            .filter(|x| match x.get(&column) {
+                Some(Value::Number(value)) => {
+                    compare_numbers(value, &min).is_some_and(Ordering::is_ge)
+                        && compare_numbers(value, &max).is_some_and(Ordering::is_le)
+                },
+                _ => false
+            });
+
+        Some(self.decode(&table_name, matching))
+    }
+
+    /// Records with every word of `query` in their string fields, ignoring
+    /// case, in id order. Uses the table's search index if it has one and
+    /// scans the records otherwise.
+    pub fn search<T>(&self, table_name: String, query: &str) -> Option<Vec<T>> 
+        where T: DeserializeOwned
+    {
+        let table = self.tables.get(&table_name)?;
+
+        if let Some(index) = self.search_index(&table_name) {
+            let ids = index.search(query);
+
+            return Some(self.decode(
+                &table_name,
+                ids.into_iter().filter_map(|id| table.data.get(&id)).filter(|x| !is_deleted(x))
+            ));
+        }
+
+        let query = index::tokenize(query);
+        let matching = table
+            .data
+            .values()
+            .filter(|x| !is_deleted(x))
+            .filter(|x| {
+                let words = index::words(x);
+                !query.is_empty() && query.iter().all(|word| words.contains(word))
+            });
+
+        Some(self.decode(&table_name, matching))
+    }
+
+    pub fn find_by_id<T>(&self, table_name: String, key: impl Into<Key>) -> Option<T> 
+        where T: DeserializeOwned
+    {
+        let table = self.tables.get(&table_name)?;
+        let id = self.row_id(&table_name, key.into())?;
+
+        self.decode(&table_name, table.data.get(&id).filter(|x| !is_deleted(x)).into_iter()).pop()
+    }
+
+    /// Records matching a typed predicate. Each record is deserialized once, so
+    /// conditions can use any field and comparison unlike [`Db::find_by_value`].
+    pub fn find_where<T, F>(&self, table_name: String, predicate: F) -> Option<Vec<T>>
+        where T: DeserializeOwned,
+            F: Fn(&T) -> bool
+    {
+        let table = self.tables.get(&table_name)?;
+
+        let records: Vec<T> = self.decode(&table_name, table.data.values().filter(|x| !is_deleted(x)));
+
+        Some(records.into_iter().filter(|x| predicate(x)).collect())
+    }
+
+    /// Sets the codec the table's records are stored with, re-encoding the
+    /// stored ones. Only backends storing records separately use it, the file
+    /// backends write every table in the file's format. Returns `false` if the
+    /// table does not exist.
+    pub async fn set_codec(&mut self, table_name: String, codec: CodecKind) -> DbResult<bool> {
+        let Some(table) = self.tables.get(&table_name) else {
+            return Ok(false)
+        };
+
+        if table.codec != codec {
+            self.commit(WalEntry::Codec { table: table_name, codec }).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Whether a record with `id` exists and is not soft deleted, without
+    /// deserializing it.
+    pub fn exists(&self, table_name: String, key: impl Into<Key>) -> bool {
+        let Some(id) = self.row_id(&table_name, key.into()) else {
+            return false
+        };
+
+        self.tables
+            .get(&table_name)
+            .and_then(|table| table.data.get(&id))
+            .is_some_and(|x| !is_deleted(x))
+    }
+
+    /// Number of records in the table, not counting soft deleted ones.
+    pub fn count(&self, table_name: String) -> Option<usize> {
+        let table = self.tables.get(&table_name)?;
+
+        Some(table.data.values().filter(|x| !is_deleted(x)).count())
+    }
+
+    /// Aggregates the numeric values of `column` over the live records, see
+    /// [`Agg::apply`]. Returns `None` if there is no such table.
+    pub fn aggregate(&self, table_name: String, column: String, agg: Agg) -> Option<Value> {
+        let table = self.tables.get(&table_name)?;
+
+        Some(agg.apply(table.data.values().filter(|x| !is_deleted(x)), &column))
+    }
+
+    /// Like [`Db::aggregate`] but separately for each value of `group_column`.
+    pub fn group_by(&self, table_name: String, group_column: String, column: String, agg: Agg) -> Option<BTreeMap<String, Value>> {
+        let table = self.tables.get(&table_name)?;
+
+        Some(agg.apply_grouped(table.data.values().filter(|x| !is_deleted(x)), &group_column, &column))
+    }
+
+    /// Like [`Db::find_by_value`] but only counting the matches, without
+    /// deserializing them.
+    pub fn count_by_value(&self, table_name: String, column: String, value: String) -> Option<usize> {
+        let table = self.tables.get(&table_name)?;
+        let value = Value::String(value);
+
+        if let Some(index) = self.index(&table_name, &column) {
+            return Some(
+                index
+                    .get(&value)
+                    .into_iter()
+                    .filter_map(|id| table.data.get(&id))
+                    .filter(|x| !is_deleted(x))
+                    .count()
+            );
+        }
+
+        Some(
+            table
+                .data
+                .values()
+                .filter(|x| !is_deleted(x) && x.get(&column) == Some(&value))
+                .count()
+        )
+    }
+
+    pub async fn get_increment_last_id(&mut self, table_name: String) -> DbResult<Option<u32>> {
+        if let Some(table) = self.tables.get(&table_name) {
+            let id = table.next_id;
+            self.commit(WalEntry::NextId { table: table_name, next_id: id + 1 }).await?;
+            return Ok(Some(id));
+        }
+
+        println!("Table does not exist! Cannot get next id.");
+        Ok(None)
+    }
+
+    /// Stores the record under `id`, returning it as stored, including any
+    /// timestamps added by [`Db::add_timestamps`].
+    pub async fn insert_or_update<T>(&mut self, table_name: String, key: impl Into<Key>, data: T) -> DbResult<Option<T>> 
+        where T: Serialize + DeserializeOwned
+    {
+        Ok(self.upsert(table_name, key, data).await?.map(UpsertResult::into_inner))
+    }
+
+    /// Replaces the record only if it is still at `expected_version`, failing
+    /// with [`DbError::Conflict`] if another write got there first. Returns
+    /// `None` if there is no record with `key`.
+    pub async fn insert_or_update_versioned<T>(&mut self, table_name: String, key: impl Into<Key>, data: T, expected_version: u64) -> DbResult<Option<T>> 
+        where T: Serialize + DeserializeOwned
+    {
+        let key = key.into();
+
+        let Some(id) = self.row_id(&table_name, key).filter(|id| self.exists(table_name.clone(), *id)) else {
+            return Ok(None)
+        };
+
+        let actual = self.version(&table_name, id);
+
+        if actual != expected_version {
+            return Err(DbError::Conflict { table: table_name, id, expected: expected_version, actual })
+        }
+
+        self.insert_or_update(table_name, key, data).await
+    }
+
+    /// Like [`Db::insert_or_update`] but reports whether a record with `key`
+    /// already existed. Replacing a soft deleted record counts as a creation,
+    /// as reads did not return it before. A uuid key not stored yet gets the
+    /// next row id, and is written to the record's [`ID_FIELD`].
+    pub async fn upsert<T>(&mut self, table_name: String, key: impl Into<Key>, data: T) -> DbResult<Option<UpsertResult<T>>> 
+        where T: Serialize + DeserializeOwned
+    {
+        let key = key.into();
+
+        if let Some(table) = self.tables.get(&table_name) {
+            let mut entries = vec![];
+            let id = match self.row_id(&table_name, key) {
+                Some(id) => id,
+                None => {
+                    entries.push(WalEntry::NextId { table: table_name.clone(), next_id: table.next_id + 1 });
+                    table.next_id
+                }
+            };
+
+            let existed = self.exists(table_name.clone(), id);
+            let mut value = serde_json::to_value(data)?;
+            self.metrics.record_serialize(&table_name, 1, 0);
+
+            if let (Key::Uuid(_), Some(fields)) = (key, value.as_object_mut()) {
+                fields.insert(ID_FIELD.to_string(), Value::from(key));
+            }
+
+            self.stamp(&table_name, id, &mut value);
+            self.validate(&table_name, id, &value)?;
+            self.check_unique(&table_name, id, &value)?;
+            // Counted up front for the conversion back once the record is stored
+            self.metrics.record_deserialize(&table_name, 1, 0);
+            entries.push(WalEntry::Upsert { table: table_name, id, data: value.clone() });
+            self.commit_all(entries).await?;
+
+            let stored = serde_json::from_value(value)?;
+            return Ok(Some(match existed {
+                true => UpsertResult::Updated(stored),
+                false => UpsertResult::Created(stored)
+            }))
+        }
+
+        Ok(None)
+    }
+
+    /// Merges `partial` into the stored record as a merge patch and stores the
+    /// result like [`Db::insert_or_update`], returning `None` if there is no
+    /// record with `id`. The record keeps its id whatever the patch says.
+    pub async fn update_fields(&mut self, table_name: String, id: u32, partial: Value) -> DbResult<Option<Value>> {
+        let Some(mut record) = self.find_by_id::<Value>(table_name.clone(), id) else {
+            return Ok(None)
+        };
+
+        let original_id = record.get(ID_FIELD).cloned();
+        merge_patch(&mut record, &partial);
+
+        if let Some(original_id) = original_id {
+            record[ID_FIELD] = original_id;
+        }
+
+        self.insert_or_update(table_name, id, record).await
+    }
+
+    /// Replaces every live record matching `predicate` with what `update`
+    /// makes of it, in one transaction so a record failing validation or a
+    /// unique constraint leaves all of them as they were. Returns how many were
+    /// updated, or `None` if there is no such table.
+    pub async fn update_where<T, P, U>(&mut self, table_name: String, predicate: P, mut update: U) -> DbResult<Option<usize>>
+        where T: Serialize + DeserializeOwned,
+            P: Fn(&T) -> bool,
+            U: FnMut(T) -> T
+    {
+        let Some(table) = self.tables.get(&table_name) else {
+            return Ok(None)
+        };
+
+        let mut matching: Vec<(u32, T)> = vec![];
+
+        for (id, record) in table.data.iter().filter(|(_, x)| !is_deleted(x)) {
+            let record: T = serde_json::from_value(record.clone())?;
+
+            if predicate(&record) {
+                matching.push((*id, record));
+            }
+        }
+
+        self.metrics.record_deserialize(&table_name, table.data.len(), 0);
+
+        if matching.is_empty() {
+            return Ok(Some(0))
+        }
+
+        let count = matching.len();
+
+        self.transaction(|tx| {
+            for (id, record) in matching {
+                tx.insert_or_update(table_name.clone(), id, update(record))?;
+            }
+
+            Ok(())
+        }).await?;
+
+        Ok(Some(count))
+    }
+
+    /// Like [`Db::insert_or_update`] but the record is removed by the next
+    /// [`Db::purge_expired`] after `ttl` has passed. Until then it is still
+    /// returned by reads, so callers relying on expiry should check it too.
+    pub async fn insert_with_ttl<T>(&mut self, table_name: String, id: u32, data: T, ttl: Duration) -> DbResult<Option<T>> 
+        where T: Serialize + DeserializeOwned
+    {
+        if self.tables.contains_key(&table_name) {
+            let mut value = serde_json::to_value(data)?;
+            self.metrics.record_serialize(&table_name, 1, 0);
+            self.stamp(&table_name, id, &mut value);
+            self.validate(&table_name, id, &value)?;
+            self.check_unique(&table_name, id, &value)?;
+            // Counted up front for the conversion back once the record is stored
+            self.metrics.record_deserialize(&table_name, 1, 0);
+
+            let expires_at = Utc::now().timestamp().saturating_add(ttl.as_secs() as i64);
+            self.commit_all(vec![
+                WalEntry::Upsert { table: table_name.clone(), id, data: value.clone() },
+                WalEntry::Expire { table: table_name, id, expires_at }
+            ]).await?;
+            return Ok(Some(serde_json::from_value(value)?))
+        }
+
+        Ok(None)
+    }
+
+    /// Deletes every record whose ttl has passed and checkpoints if any were
+    /// found, returning how many were removed. Records a restricting relation
+    /// still points at are skipped and kept until a later purge, so they do not
+    /// hold up the rest.
+    pub async fn purge_expired(&mut self) -> DbResult<usize> {
+        let now = Utc::now().timestamp();
+
+        let expired: Vec<(String, u32)> = self.tables
+            .iter()
+            .flat_map(|(table_name, table)| {
+                table.expires_at
+                    .iter()
+                    .filter(|(_, expires_at)| **expires_at <= now)
+                    .map(|(id, _)| (table_name.clone(), *id))
+            })
+            .collect();
+
+        if expired.is_empty() {
+            return Ok(0)
+        }
+
+        // Skipping a record can restrict one it was expected to be purged
+        // along with, so planning repeats until nothing more is skipped
+        let mut purging = expired;
+        let entries = loop {
+            let batch: HashSet<(String, u32)> = purging.iter().cloned().collect();
+            let mut entries = vec![];
+            let mut planned = HashSet::new();
+            let mut restricted = HashSet::new();
+
+            for target in &purging {
+                match self.plan_deletes_within(vec![target.clone()], &batch) {
+                    Ok(target_entries) => entries.extend(
+                        target_entries
+                            .into_iter()
+                            .filter(|entry| matches!(entry, WalEntry::Delete { table, id } if planned.insert((table.clone(), *id))))
+                    ),
+                    Err(DbError::ForeignKeyViolation { .. }) => { restricted.insert(target.clone()); },
+                    Err(err) => return Err(err)
+                }
+            }
+
+            if restricted.is_empty() {
+                break entries
+            }
+
+            println!("Skipping {} expired records still referenced by a restricting relation", restricted.len());
+            purging.retain(|target| !restricted.contains(target));
+        };
+
+        if purging.is_empty() {
+            return Ok(0)
+        }
+
+        let count = purging.len();
+        self.commit_all(entries).await?;
+        self.checkpoint().await?;
+
+        Ok(count)
+    }
+
+    pub async fn delete_by_id(&mut self, table_name: String, key: impl Into<Key>) -> DbResult<Option<Value>> {
+        if let Some(table) = self.tables.get(&table_name) {
+            let Some(id) = self.row_id(&table_name, key.into()) else {
+                return Ok(None)
+            };
+
+            let Some(data) = table.data.get(&id).cloned() else {
+                return Ok(None)
+            };
+
+            let entries = self.plan_delete(&table_name, id)?;
+            self.commit_all(entries).await?;
+            return Ok(Some(data))
+        }
+
+        Ok(None)
+    }
+
+    /// Deletes every record whose `column` equals `value`, soft deleted ones
+    /// included, logging them as one batch. Returns how many were removed, or
+    /// `None` if there is no such table.
+    pub async fn delete_where(&mut self, table_name: String, column: String, value: String) -> DbResult<Option<usize>> {
+        let Some(table) = self.tables.get(&table_name) else {
+            return Ok(None)
+        };
+
+        let value = Value::String(value);
+        let ids: Vec<u32> = match self.index(&table_name, &column) {
+            Some(index) => index.get(&value),
+            None => table.data
+                .iter()
+                .filter(|(_, x)| x.get(&column) == Some(&value))
+                .map(|(id, _)| *id)
+                .collect()
+        };
+
+        if ids.is_empty() {
+            return Ok(Some(0))
+        }
+
+        let count = ids.len();
+        let entries = self.plan_deletes(ids.into_iter().map(|id| (table_name.clone(), id)).collect())?;
+        self.commit_all(entries).await?;
+
+        Ok(Some(count))
+    }
+
+    /// Hides the record from reads by stamping it with [`DELETED_AT_FIELD`],
+    /// keeping it so [`Db::restore_by_id`] can bring it back. Returns `None` if
+    /// there is no live record with `id`.
+    pub async fn soft_delete_by_id(&mut self, table_name: String, id: u32) -> DbResult<Option<Value>> {
+        let Some(mut record) = self.find_by_id::<Value>(table_name.clone(), id) else {
+            return Ok(None)
+        };
+
+        record[DELETED_AT_FIELD] = Value::from(Utc::now().timestamp());
+        let entries = self.plan_rewrite(table_name, id, &mut record);
+        self.commit_all(entries).await?;
+
+        Ok(Some(record))
+    }
+
+    /// Entries storing `record` again with its version and timestamps bumped
+    /// like any other write, keeping the ttl it had.
+    fn plan_rewrite(&self, table_name: String, id: u32, record: &mut Value) -> Vec<WalEntry> {
+        self.stamp(&table_name, id, record);
+
+        let expires_at = self.tables
+            .get(&table_name)
+            .and_then(|table| table.expires_at.get(&id))
+            .copied();
+
+        let mut entries = vec![WalEntry::Upsert { table: table_name.clone(), id, data: record.clone() }];
+
+        if let Some(expires_at) = expires_at {
+            entries.push(WalEntry::Expire { table: table_name, id, expires_at });
+        }
+
+        entries
+    }
+
+    /// Undoes [`Db::soft_delete_by_id`]. Returns `None` if there is no soft
+    /// deleted record with `id`.
+    pub async fn restore_by_id(&mut self, table_name: String, id: u32) -> DbResult<Option<Value>> {
+        let record = self.tables
+            .get(&table_name)
+            .and_then(|table| table.data.get(&id))
+            .filter(|x| is_deleted(x))
+            .cloned();
+
+        let Some(mut record) = record else {
+            return Ok(None)
+        };
+
+        if let Some(fields) = record.as_object_mut() {
+            fields.remove(DELETED_AT_FIELD);
+        }
+
+        let entries = self.plan_rewrite(table_name, id, &mut record);
+        self.commit_all(entries).await?;
+
+        Ok(Some(record))
+    }
+
+    /// Removes every record of the table, along with whatever cascades from
+    /// them, failing if a restricting relation points at any of them.
+    pub async fn delete_all(&mut self, table_name: String) -> DbResult<bool> {
+        if let Some(table) = self.tables.get(&table_name) {
+            let targets = table.data.keys().map(|id| (table_name.clone(), *id)).collect();
+            let mut entries: Vec<WalEntry> = self.plan_deletes(targets)?
+                .into_iter()
+                .filter(|entry| entry.table() != table_name)
+                .collect();
+            entries.push(WalEntry::Clear { table: table_name });
+            self.commit_all(entries).await?;
+            return Ok(true)
+        }
+
+        Ok(false)
+    }
+ }
+
+/// Shared handle to the [`Db`], letting reads proceed concurrently while
+/// writes take exclusive access.
+#[derive(Clone)]
+pub struct DbHandle {
+    inner: Arc<RwLock<Db>>
+}
+
+impl DbHandle {
+    pub fn new(db: Db) -> Self {
+        Self { inner: Arc::new(RwLock::new(db)) }
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, Db> {
+        self.inner.read().await
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, Db> {
+        self.inner.write().await
+    }
+
+    /// Runs [`Db::snapshot`] into `dir` every `interval` until the runtime shuts down.
+    pub fn spawn_snapshot_task(&self, dir: PathBuf, interval: Duration) -> JoinHandle<()> {
+        let handle = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(err) = handle.read().await.snapshot(&dir).await {
+                    println!("Failed to snapshot db: {}", err);
+                }
+            }
+        })
+    }
+
+    /// Runs [`Db::flush_now`] every `interval` until the runtime shuts down,
+    /// only taking the write lock when the db is dirty.
+    pub fn spawn_flush_task(&self, interval: Duration) -> JoinHandle<()> {
+        let handle = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                if !handle.read().await.is_dirty() {
+                    continue
+                }
+
+                if let Err(err) = handle.write().await.flush_now().await {
+                    println!("Failed to flush db: {}", err);
+                }
+            }
+        })
+    }
+
+    /// Runs [`Db::purge_expired`] every `interval` until the runtime shuts down.
+    pub fn spawn_purge_task(&self, interval: Duration) -> JoinHandle<()> {
+        let handle = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(err) = handle.write().await.purge_expired().await {
+                    println!("Failed to purge expired records: {}", err);
+                }
+            }
+        })
+    }
+}
+
+ #[cfg(test)]
+ mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    const TABLE_NAME: &str = "sample";
+
+    async fn init_db(file_name: &str) -> Db {
+        let mut db = Db::init(String::from(file_name)).await.unwrap();
+        let table_name = String::from(TABLE_NAME);
+        db.add_table(table_name.clone(), true).await.unwrap();
+
+        db
+    }
+
+    async fn upsert_item(db: &mut Db, value: &str) -> (u32, Value) {
+        let id = db.get_increment_last_id(TABLE_NAME.to_string()).await.unwrap().unwrap();
+        let to_insert: Value = json!({"id": id, "value": value});
+        db.insert_or_update::<Value>(TABLE_NAME.to_string(), id, to_insert.clone()).await.unwrap();
+
+        (id, to_insert)
+    }
+
+    #[tokio::test]
+    async fn test_init() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let db = Db::init(file_name).await;
+
+                assert!(db.is_ok())
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_add_table() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let db = init_db(&file_name).await;
+
+                let result = db.find_all::<Value>(TABLE_NAME.to_string());
+
+                assert!(result.is_some());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_insert() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (id, inserted) = upsert_item(&mut db, "sample").await;
+
+                let data = db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap();
+                assert_eq!(data, inserted);
+
+                let (another_id, another_inserted) = upsert_item(&mut db, "another value").await;
+                let another_data = db.find_by_id::<Value>(TABLE_NAME.to_string(), another_id).unwrap(); 
+                assert_eq!(another_data, another_inserted)
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+
+                let (id, inserted) = upsert_item(&mut db, "sample").await;
+                let data = db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap();
+                assert_eq!(data, inserted);
+
+                let to_update: Value = json!({"id": id, "value": "updated"});
+                db.insert_or_update::<Value>(TABLE_NAME.to_string(), id, to_update.clone()).await.unwrap();
+
+                let updated_data = db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap(); 
+                assert_eq!(updated_data, to_update)
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+
+                let (id, inserted) = upsert_item(&mut db, "sample").await;
+
+                let data = db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap();
+                assert_eq!(data, inserted);
+
+                db.delete_by_id(TABLE_NAME.to_string(), id).await.unwrap();
+
+                let data = db.find_by_id::<Value>(TABLE_NAME.to_string(), id);
+                assert!(data.is_none());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_all() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+
+                let (id, inserted) = upsert_item(&mut db, "sample").await;
+
+                let data = db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap();
+                assert_eq!(data, inserted);
+
+                let (another_id, another_inserted) = upsert_item(&mut db, "another value").await;
+                let another_data = db.find_by_id::<Value>(TABLE_NAME.to_string(), another_id).unwrap(); 
+                assert_eq!(another_data, another_inserted);
+
+                db.delete_all(TABLE_NAME.to_string()).await.unwrap();
+
+                let all_result = db.find_all::<Value>(TABLE_NAME.to_string()).unwrap();
+
+                assert_eq!(all_result.len(), 0);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_find_by_value() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                upsert_item(&mut db, "sample").await;
+
+                let result = db.find_by_value::<Value>(TABLE_NAME.to_string(), "value".to_string(), "sample".to_string()).unwrap();
+
+                assert_eq!(result.len(), 1)
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_replay_wal_on_init() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let (id, inserted) = {
+                    let mut db = init_db(&file_name).await;
+                    db.set_checkpoint_interval(usize::MAX);
+                    upsert_item(&mut db, "logged").await
+                };
+
+                let contents = std::fs::read_to_string(&file_name).unwrap();
+                assert!(!contents.contains("logged"));
+
+                let db = Db::init(file_name.clone()).await.unwrap();
+                let data = db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap();
+                assert_eq!(data, inserted);
+
+                let wal_contents = std::fs::read_to_string(format!("{}.wal", file_name)).unwrap();
+                assert!(wal_contents.is_empty());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.set_checkpoint_interval(usize::MAX);
+                upsert_item(&mut db, "logged").await;
+
+                db.checkpoint().await.unwrap();
+
+                let contents = std::fs::read_to_string(&file_name).unwrap();
+                assert!(contents.contains("logged"));
+
+                let wal_contents = std::fs::read_to_string(format!("{}.wal", file_name)).unwrap();
+                assert!(wal_contents.is_empty());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_flush_replaces_file_without_temp_leftover() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                upsert_item(&mut db, "flushed").await;
+                db.checkpoint().await.unwrap();
+
+                let contents = std::fs::read(&file_name).unwrap();
+                assert!(serde_json::from_slice::<Value>(storage::file::unseal(&contents).unwrap()).is_ok());
+                assert!(!Path::new(&format!("{}.tmp", file_name)).exists());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_write_keeps_previous_contents() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let (id, inserted) = {
+                    let mut db = init_db(&file_name).await;
+                    let result = upsert_item(&mut db, "intact").await;
+                    db.checkpoint().await.unwrap();
+                    result
+                };
+
+                // A crash between writing the temp file and renaming it
+                std::fs::write(format!("{}.tmp", file_name), "{\"sample\":{\"next_id\":").unwrap();
+
+                let mut db = Db::init(file_name.clone()).await.unwrap();
+                let data = db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap();
+                assert_eq!(data, inserted);
+
+                db.checkpoint().await.unwrap();
+                let contents = std::fs::read(&file_name).unwrap();
+                assert!(serde_json::from_slice::<Value>(storage::file::unseal(&contents).unwrap()).is_ok());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_find_page() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                upsert_item(&mut db, "first").await;
+                let (_, second) = upsert_item(&mut db, "second").await;
+                let (_, third) = upsert_item(&mut db, "third").await;
+
+                let page = db.find_page::<Value>(TABLE_NAME.to_string(), 1, 1).unwrap();
+                assert_eq!(page, Page { items: vec![second], total: 3, next_offset: Some(2) });
+
+                let last_page = db.find_page::<Value>(TABLE_NAME.to_string(), 2, 5).unwrap();
+                assert_eq!(last_page, Page { items: vec![third], total: 3, next_offset: None });
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_find_by_value_with_index() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (id, _) = upsert_item(&mut db, "sample").await;
+                upsert_item(&mut db, "other").await;
+
+                assert!(db.add_index(TABLE_NAME.to_string(), "value".to_string()));
+                assert!(!db.add_index("missing".to_string(), "value".to_string()));
+
+                let to_update: Value = json!({"id": id, "value": "renamed"});
+                db.insert_or_update::<Value>(TABLE_NAME.to_string(), id, to_update.clone()).await.unwrap();
+
+                let result = db.find_by_value::<Value>(TABLE_NAME.to_string(), "value".to_string(), "sample".to_string()).unwrap();
+                assert!(result.is_empty());
+
+                let result = db.find_by_value::<Value>(TABLE_NAME.to_string(), "value".to_string(), "renamed".to_string()).unwrap();
+                assert_eq!(result, vec![to_update]);
+
+                db.delete_by_id(TABLE_NAME.to_string(), id).await.unwrap();
+                let result = db.find_by_value::<Value>(TABLE_NAME.to_string(), "value".to_string(), "renamed".to_string()).unwrap();
+                assert!(result.is_empty());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_unique_constraint() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (id, _) = upsert_item(&mut db, "sample").await;
+
+                assert!(db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string()).unwrap());
+
+                let duplicate: Value = json!({"id": id + 1, "value": "sample"});
+                let err = db.insert_or_update::<Value>(TABLE_NAME.to_string(), id + 1, duplicate).await.unwrap_err();
+                assert_eq!(
+                    err,
+                    DbError::UniqueViolation {
+                        table: TABLE_NAME.to_string(),
+                        column: "value".to_string(),
+                        value: json!("sample")
+                    }
+                );
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id + 1).is_none());
+
+                let same_record: Value = json!({"id": id, "value": "sample", "extra": true});
+                assert!(db.insert_or_update::<Value>(TABLE_NAME.to_string(), id, same_record).await.is_ok());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_unique_constraint_on_existing_duplicates() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                upsert_item(&mut db, "sample").await;
+                upsert_item(&mut db, "sample").await;
+
+                let result = db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string());
+                assert!(result.is_err());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_transaction_commit() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.add_table("other".to_string(), true).await.unwrap();
+
+                let (id, other_id) = db.transaction(|tx| {
+                    let id = tx.get_increment_last_id(TABLE_NAME.to_string())?;
+                    tx.insert_or_update(TABLE_NAME.to_string(), id, json!({"id": id, "value": "sample"}))?;
+                    let other_id = tx.get_increment_last_id("other".to_string())?;
+                    tx.insert_or_update("other".to_string(), other_id, json!({"id": other_id}))?;
+
+                    assert!(tx.find_by_id::<Value>(TABLE_NAME.to_string(), id).is_some());
+
+                    Ok((id, other_id))
+                }).await.unwrap();
+
+                drop(db);
+                let db = Db::init(file_name.clone()).await.unwrap();
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id).is_some());
+                assert!(db.find_by_id::<Value>("other".to_string(), other_id).is_some());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_transaction_rollback() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (existing_id, existing) = upsert_item(&mut db, "sample").await;
+                db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string()).unwrap();
+
+                let result = db.transaction(|tx| {
+                    tx.delete_by_id(TABLE_NAME.to_string(), existing_id)?;
+                    let id = tx.get_increment_last_id(TABLE_NAME.to_string())?;
+                    tx.insert_or_update(TABLE_NAME.to_string(), id, json!({"id": id, "value": "new"}))?;
+                    let id = tx.get_increment_last_id(TABLE_NAME.to_string())?;
+                    tx.insert_or_update(TABLE_NAME.to_string(), id, json!({"id": id, "value": "new"}))
+                }).await;
+                assert!(result.is_err());
+
+                let all = db.find_all::<Value>(TABLE_NAME.to_string()).unwrap();
+                assert_eq!(all, vec![existing]);
+
+                let result = db.find_by_value::<Value>(TABLE_NAME.to_string(), "value".to_string(), "new".to_string()).unwrap();
+                assert!(result.is_empty());
+
+                let next_id = db.get_increment_last_id(TABLE_NAME.to_string()).await.unwrap().unwrap();
+                assert_eq!(next_id, existing_id + 1);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_find_all_sorted() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (_, b) = upsert_item(&mut db, "b").await;
+                let (_, c) = upsert_item(&mut db, "c").await;
+                let (_, a) = upsert_item(&mut db, "a").await;
+                let (id, _) = upsert_item(&mut db, "none").await;
+                let missing: Value = json!({"id": id});
+                db.insert_or_update::<Value>(TABLE_NAME.to_string(), id, missing.clone()).await.unwrap();
+
+                let result = db.find_all_sorted::<Value>(TABLE_NAME.to_string(), "value".to_string(), Direction::Asc).unwrap();
+                assert_eq!(result, vec![a.clone(), b.clone(), c.clone(), missing.clone()]);
+
+                let result = db.find_all_sorted::<Value>(TABLE_NAME.to_string(), "value".to_string(), Direction::Desc).unwrap();
+                assert_eq!(result, vec![c, b, a, missing]);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_schema_validation() {
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Sample {
+            id: u32,
+            value: String
+        }
+
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = Db::init(file_name).await.unwrap();
+                db.add_table_with_schema::<Sample>(TABLE_NAME.to_string(), true).await.unwrap();
+
+                let valid: Value = json!({"id": 1, "value": "sample"});
+                assert!(db.insert_or_update::<Value>(TABLE_NAME.to_string(), 1, valid).await.is_ok());
+
+                let invalid: Value = json!({"id": 2, "value": 2});
+                let err = db.insert_or_update::<Value>(TABLE_NAME.to_string(), 2, invalid).await.unwrap_err();
+
+                assert!(matches!(err, DbError::Validation { id: 2, .. }));
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 2).is_none());
+            }
+        }).await;
+    }
+
+    fn add_description(record: &mut Value) {
+        record["description"] = json!("");
+    }
+
+    fn increment_revision(record: &mut Value) {
+        let revision = record.get("revision").and_then(Value::as_u64).unwrap_or(0);
+        record["revision"] = json!(revision + 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_migrations() {
+        let migrations = [
+            Migration { table: TABLE_NAME, version: 2, migrate: increment_revision },
+            Migration { table: TABLE_NAME, version: 1, migrate: add_description }
+        ];
+
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let id = {
+                    let mut db = init_db(&file_name).await;
+                    let (id, _) = upsert_item(&mut db, "sample").await;
+                    db.checkpoint().await.unwrap();
+                    id
+                };
+
+                let mut db = Db::init(file_name.clone()).await.unwrap();
+                db.run_migrations(&migrations).await.unwrap();
+                let expected: Value = json!({"id": id, "value": "sample", "description": "", "revision": 1});
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap(), expected);
+
+                drop(db);
+                let mut db = Db::init(file_name.clone()).await.unwrap();
+                db.run_migrations(&migrations).await.unwrap();
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap(), expected);
+
+                db.add_table("fresh".to_string(), true).await.unwrap();
+                db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+                db.checkpoint().await.unwrap();
+
+                let contents: Value = serde_json::from_slice(storage::file::unseal(&std::fs::read(&file_name).unwrap()).unwrap()).unwrap();
+                assert_eq!(contents["fresh"]["schema_version"], json!(0));
+                assert_eq!(contents[TABLE_NAME]["schema_version"], json!(2));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_sled_backend_persists_across_open() {
+        async_run_with_file_create_teardown(|file_name| {
+            let path = format!("{}-sled", file_name);
+            async move {
+                let (id, inserted) = {
+                    let mut db = Db::open(StorageBackend::Sled, Compression::None, path.clone()).await.unwrap();
+                    db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+                    upsert_item(&mut db, "stored").await
+                };
+
+                let mut db = Db::open(StorageBackend::Sled, Compression::None, path.clone()).await.unwrap();
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted));
+
+                let next_id = db.get_increment_last_id(TABLE_NAME.to_string()).await.unwrap();
+                assert_eq!(next_id, Some(id + 1));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_in_memory() {
+        let mut db = Db::in_memory();
+        db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+        let (id, inserted) = upsert_item(&mut db, "ephemeral").await;
+        db.checkpoint().await.unwrap();
+
+        assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted));
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let (expired_id, kept_id) = {
+                    let mut db = init_db(&file_name).await;
+                    let (kept_id, _) = upsert_item(&mut db, "kept").await;
+
+                    let expired_id = db.get_increment_last_id(TABLE_NAME.to_string()).await.unwrap().unwrap();
+                    let record = json!({"id": expired_id, "value": "expired"});
+                    db.insert_with_ttl(TABLE_NAME.to_string(), expired_id, record, Duration::ZERO).await.unwrap();
+
+                    let later_id = db.get_increment_last_id(TABLE_NAME.to_string()).await.unwrap().unwrap();
+                    let record = json!({"id": later_id, "value": "later"});
+                    db.insert_with_ttl(TABLE_NAME.to_string(), later_id, record, Duration::from_secs(3600)).await.unwrap();
+
+                    assert_eq!(db.purge_expired().await.unwrap(), 1);
+                    (expired_id, kept_id)
+                };
+
+                let db = Db::init(file_name.clone()).await.unwrap();
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), expired_id).is_none());
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), kept_id).is_some());
+                assert_eq!(db.find_all::<Value>(TABLE_NAME.to_string()).unwrap().len(), 2);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_upsert_clears_ttl() {
+        for backend in [StorageBackend::Json, StorageBackend::Sled] {
+            async_run_with_file_create_teardown(|file_name| {
+                let file_name = file_name.to_string();
+                async move {
+                    let path = format!("{}-{:?}", file_name, backend);
+                    let mut db = Db::open(backend, Compression::None, path.clone()).await.unwrap();
+                    db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+
+                    let record = json!({"id": 1, "value": "temporary"});
+                    db.insert_with_ttl(TABLE_NAME.to_string(), 1, record, Duration::ZERO).await.unwrap();
+                    db.upsert(TABLE_NAME.to_string(), 1, json!({"id": 1, "value": "permanent"})).await.unwrap();
+
+                    assert_eq!(db.purge_expired().await.unwrap(), 0);
+                    assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 1).is_some());
+
+                    drop(db);
+                    let mut db = Db::open(backend, Compression::None, path).await.unwrap();
+                    assert_eq!(db.purge_expired().await.unwrap(), 0);
+                    assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 1), Some(json!({"id": 1, "value": "permanent"})));
+                }
+            }).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete_and_restore() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (id, inserted) = upsert_item(&mut db, "sample").await;
+                upsert_item(&mut db, "other").await;
+
+                let deleted = db.soft_delete_by_id(TABLE_NAME.to_string(), id).await.unwrap().unwrap();
+                assert!(deleted.get(DELETED_AT_FIELD).is_some());
+                assert!(db.soft_delete_by_id(TABLE_NAME.to_string(), id).await.unwrap().is_none());
+
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id).is_none());
+                assert_eq!(db.find_all::<Value>(TABLE_NAME.to_string()).unwrap().len(), 1);
+                assert_eq!(db.find_page::<Value>(TABLE_NAME.to_string(), 0, 10).unwrap().total, 1);
+                assert_eq!(db.find_all_with_deleted::<Value>(TABLE_NAME.to_string()).unwrap().len(), 2);
+
+                let restored = db.restore_by_id(TABLE_NAME.to_string(), id).await.unwrap();
+                assert_eq!(restored, Some(inserted.clone()));
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted));
+                assert!(db.restore_by_id(TABLE_NAME.to_string(), id).await.unwrap().is_none());
+
+                db.add_versioning(TABLE_NAME.to_string());
+                let mut table = db.table_mut::<Value>(TABLE_NAME);
+                let deleted = table.soft_delete(id).await.unwrap().unwrap();
+                assert_eq!(deleted[VERSION_FIELD], 1);
+                assert!(table.get(id).is_none());
+                let restored = table.restore(id).await.unwrap().unwrap();
+                assert_eq!(restored[VERSION_FIELD], 2);
+                assert_eq!(table.get(id), Some(restored));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_timestamps() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.add_timestamps(TABLE_NAME.to_string());
+
+                let (id, _) = upsert_item(&mut db, "sample").await;
+                let inserted = db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap();
+                assert!(inserted[CREATED_AT_FIELD].is_i64());
+                assert_eq!(inserted[CREATED_AT_FIELD], inserted[UPDATED_AT_FIELD]);
+
+                let backdated = json!({"id": id, "value": "sample", CREATED_AT_FIELD: 1});
+                db.commit(WalEntry::Upsert { table: TABLE_NAME.to_string(), id, data: backdated }).await.unwrap();
+
+                let updated = db
+                    .insert_or_update(TABLE_NAME.to_string(), id, json!({"id": id, "value": "updated"}))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(updated[CREATED_AT_FIELD], json!(1));
+                assert!(updated[UPDATED_AT_FIELD].as_i64() >= inserted[UPDATED_AT_FIELD].as_i64());
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(updated));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.set_checkpoint_interval(usize::MAX);
+                let (id, inserted) = upsert_item(&mut db, "logged").await;
+
+                let dir = PathBuf::from(format!("{}-snapshots", file_name));
+                let path = db.snapshot(&dir).await.unwrap();
+                assert!(path.starts_with(&dir));
+
+                let snapshot = Db::init(path.to_string_lossy().to_string()).await.unwrap();
+                assert_eq!(snapshot.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string()).unwrap();
+                let (id, inserted) = upsert_item(&mut db, "kept").await;
+
+                let dir = PathBuf::from(format!("{}-snapshots", file_name));
+                let path = db.snapshot(&dir).await.unwrap();
+
+                db.delete_all(TABLE_NAME.to_string()).await.unwrap();
+                upsert_item(&mut db, "dropped").await;
+
+                db.restore(&path).await.unwrap();
+                assert_eq!(db.find_all::<Value>(TABLE_NAME.to_string()), Some(vec![inserted.clone()]));
+                assert_eq!(db.find_by_value::<Value>(TABLE_NAME.to_string(), "value".to_string(), "dropped".to_string()), Some(vec![]));
+
+                drop(db);
+                let mut db = Db::init(file_name.clone()).await.unwrap();
+                db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string()).unwrap();
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted.clone()));
+
+                let malformed = dir.join("malformed.json");
+                std::fs::write(&malformed, "{\"sample\":").unwrap();
+                let err = db.restore(&malformed).await.unwrap_err();
+                assert!(matches!(err, DbError::MalformedSnapshot { .. }));
+
+                let duplicate = dir.join("duplicate.json");
+                let contents = json!({TABLE_NAME: {"next_id": 3, "data": {"1": {"value": "a"}, "2": {"value": "a"}}}});
+                std::fs::write(&duplicate, contents.to_string()).unwrap();
+                let err = db.restore(&duplicate).await.unwrap_err();
+                assert!(matches!(err, DbError::UniqueViolation { .. }));
+
+                assert_eq!(db.find_all::<Value>(TABLE_NAME.to_string()), Some(vec![inserted]));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_restore_migrates_old_snapshot() {
+        let migrations = [
+            Migration { table: TABLE_NAME, version: 2, migrate: increment_revision },
+            Migration { table: TABLE_NAME, version: 1, migrate: add_description }
+        ];
+
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (id, _) = upsert_item(&mut db, "sample").await;
+
+                let dir = PathBuf::from(format!("{}-snapshots", file_name));
+                let path = db.snapshot(&dir).await.unwrap();
+
+                db.run_migrations(&migrations).await.unwrap();
+                db.restore(&path).await.unwrap();
+                let expected: Value = json!({"id": id, "value": "sample", "description": "", "revision": 1});
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap(), expected);
+
+                db.checkpoint().await.unwrap();
+                let contents: Value = serde_json::from_slice(storage::file::unseal(&std::fs::read(&file_name).unwrap()).unwrap()).unwrap();
+                assert_eq!(contents[TABLE_NAME]["schema_version"], json!(2));
+
+                drop(db);
+                let mut db = Db::init(file_name.clone()).await.unwrap();
+                db.run_migrations(&migrations).await.unwrap();
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap(), expected);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_message_pack_backend() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let (id, inserted) = {
+                    let mut db = Db::open(StorageBackend::MessagePack, Compression::None, file_name.clone()).await.unwrap();
+                    db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+                    let result = upsert_item(&mut db, "packed").await;
+                    db.checkpoint().await.unwrap();
+                    result
+                };
+
+                assert!(serde_json::from_slice::<Value>(&std::fs::read(&file_name).unwrap()).is_err());
+
+                let db = Db::open(StorageBackend::MessagePack, Compression::None, file_name.clone()).await.unwrap();
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_compressed_file() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let wal_path = format!("{}.wal", file_name);
+                let (id, inserted) = {
+                    let mut db = Db::open(StorageBackend::Json, Compression::Gzip { level: 9 }, file_name.clone()).await.unwrap();
+                    db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+                    db.set_checkpoint_interval(usize::MAX);
+                    upsert_item(&mut db, "compressed").await
+                };
+
+                assert_eq!(Compression::detect(&std::fs::read(&wal_path).unwrap()), Compression::Gzip { level: 6 });
+
+                // Replaying the compressed log checkpoints into a compressed file.
+                let db = Db::open(StorageBackend::Json, Compression::Zstd { level: 19 }, file_name.clone()).await.unwrap();
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted.clone()));
+                assert_eq!(Compression::detect(storage::file::unseal(&std::fs::read(&file_name).unwrap()).unwrap()), Compression::Zstd { level: 3 });
+                drop(db);
+
+                let db = Db::init(file_name.clone()).await.unwrap();
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_append_after_torn_wal() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                for compression in [Compression::None, Compression::Gzip { level: 6 }, Compression::Zstd { level: 3 }] {
+                    let path = format!("{}-{:?}", file_name, compression);
+                    let wal_path = format!("{}.wal", path);
+                    {
+                        let mut db = Db::open(StorageBackend::Json, compression, path.clone()).await.unwrap();
+                        db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+                        db.checkpoint().await.unwrap();
+                    }
+
+                    let torn = compression.compress(b"{\"op\":\"clear\",\"table\":\"sample\"}\n".to_vec()).unwrap();
+                    std::fs::write(&wal_path, &torn[..torn.len() / 2]).unwrap();
+
+                    let inserted = {
+                        let mut db = Db::open(StorageBackend::Json, compression, path.clone()).await.unwrap();
+                        db.set_checkpoint_interval(usize::MAX);
+                        vec![upsert_item(&mut db, "first").await, upsert_item(&mut db, "second").await]
+                    };
+
+                    let db = Db::open(StorageBackend::Json, compression, path).await.unwrap();
+                    for (id, item) in inserted {
+                        assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(item));
+                    }
+                }
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_flush_now() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.set_checkpoint_interval(usize::MAX);
+                db.flush_now().await.unwrap();
+                assert!(!db.is_dirty());
+
+                let (id, inserted) = upsert_item(&mut db, "flushed").await;
+                assert!(db.is_dirty());
+                assert!(!std::fs::read_to_string(&file_name).unwrap().contains("flushed"));
+
+                db.flush_now().await.unwrap();
+                assert!(!db.is_dirty());
+                assert_eq!(std::fs::metadata(format!("{}.wal", file_name)).unwrap().len(), 0);
+
+                drop(db);
+                let db = Db::init(file_name.clone()).await.unwrap();
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(inserted));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_count_and_exists() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+
+                for value in ["a", "b", "a"] {
+                    upsert_item(&mut db, value).await;
+                }
+
+                let (id, _) = upsert_item(&mut db, "a").await;
+                db.soft_delete_by_id(TABLE_NAME.to_string(), id).await.unwrap();
+
+                assert_eq!(db.count(TABLE_NAME.to_string()), Some(3));
+                assert_eq!(db.count_by_value(TABLE_NAME.to_string(), "value".to_string(), "a".to_string()), Some(2));
+
+                assert!(db.add_index(TABLE_NAME.to_string(), "value".to_string()));
+                assert_eq!(db.count_by_value(TABLE_NAME.to_string(), "value".to_string(), "a".to_string()), Some(2));
+
+                assert_eq!(db.count("missing".to_string()), None);
+                assert!(db.exists(TABLE_NAME.to_string(), 1));
+                assert!(!db.exists(TABLE_NAME.to_string(), id));
+                assert!(!db.exists("missing".to_string(), 1));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_update_fields() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let id = db.get_increment_last_id(TABLE_NAME.to_string()).await.unwrap().unwrap();
+                let to_insert = json!({"id": id, "value": "a", "tags": ["x"], "meta": {"color": "red", "size": 1}});
+                db.insert_or_update(TABLE_NAME.to_string(), id, to_insert).await.unwrap();
+
+                let patch = json!({"id": 99, "value": "b", "meta": {"size": null, "shape": "round"}});
+                let updated = db.update_fields(TABLE_NAME.to_string(), id, patch).await.unwrap();
+
+                let expected = json!({"id": id, "value": "b", "tags": ["x"], "meta": {"color": "red", "shape": "round"}});
+                assert_eq!(updated, Some(expected.clone()));
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id), Some(expected));
+
+                assert_eq!(db.update_fields(TABLE_NAME.to_string(), id + 1, json!({})).await.unwrap(), None);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_upsert() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let created = db.upsert(TABLE_NAME.to_string(), 1, json!({"id": 1, "value": "a"})).await.unwrap().unwrap();
+                assert_eq!(created, UpsertResult::Created(json!({"id": 1, "value": "a"})));
+
+                let updated = db.upsert(TABLE_NAME.to_string(), 1, json!({"id": 1, "value": "b"})).await.unwrap().unwrap();
+                assert!(!updated.is_created());
+                assert_eq!(updated.into_inner(), json!({"id": 1, "value": "b"}));
+
+                db.soft_delete_by_id(TABLE_NAME.to_string(), 1).await.unwrap();
+                let recreated = db.upsert(TABLE_NAME.to_string(), 1, json!({"id": 1, "value": "c"})).await.unwrap().unwrap();
+                assert!(recreated.is_created());
+
+                assert!(db.upsert("missing".to_string(), 1, json!({"id": 1})).await.unwrap().is_none());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_where() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                for value in ["a", "b", "a", "a"] {
+                    upsert_item(&mut db, value).await;
+                }
+                db.soft_delete_by_id(TABLE_NAME.to_string(), 4).await.unwrap();
+
+                let removed = db.delete_where(TABLE_NAME.to_string(), "value".to_string(), "a".to_string()).await.unwrap();
+                assert_eq!(removed, Some(3));
+                assert_eq!(db.find_all_with_deleted::<Value>(TABLE_NAME.to_string()), Some(vec![json!({"id": 2, "value": "b"})]));
+
+                assert!(db.add_index(TABLE_NAME.to_string(), "value".to_string()));
+                let removed = db.delete_where(TABLE_NAME.to_string(), "value".to_string(), "b".to_string()).await.unwrap();
+                assert_eq!(removed, Some(1));
+                assert_eq!(db.count(TABLE_NAME.to_string()), Some(0));
+
+                assert_eq!(db.delete_where("missing".to_string(), "value".to_string(), "a".to_string()).await.unwrap(), None);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_foreign_keys() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.add_table("user".to_string(), true).await.unwrap();
+                db.add_table("comment".to_string(), true).await.unwrap();
+                assert!(db.add_foreign_key(TABLE_NAME.to_string(), "created_by".to_string(), "user".to_string(), OnDelete::Cascade));
+                assert!(db.add_foreign_key("comment".to_string(), "item_id".to_string(), TABLE_NAME.to_string(), OnDelete::Restrict));
+                assert!(!db.add_foreign_key(TABLE_NAME.to_string(), "owner".to_string(), "missing".to_string(), OnDelete::Cascade));
+
+                db.insert_or_update("user".to_string(), 1, json!({"id": 1})).await.unwrap();
+                db.insert_or_update(TABLE_NAME.to_string(), 1, json!({"id": 1, "created_by": 1})).await.unwrap();
+                db.insert_or_update(TABLE_NAME.to_string(), 2, json!({"id": 2, "created_by": 1})).await.unwrap();
+                db.insert_or_update("comment".to_string(), 1, json!({"id": 1, "item_id": 2})).await.unwrap();
+
+                let err = db.delete_by_id("user".to_string(), 1).await.unwrap_err();
+                assert_eq!(
+                    err,
+                    DbError::ForeignKeyViolation { table: "comment".to_string(), column: "item_id".to_string(), id: 2 }
+                );
+                assert_eq!(db.count(TABLE_NAME.to_string()), Some(2));
+
+                db.delete_by_id("comment".to_string(), 1).await.unwrap();
+                db.delete_by_id("user".to_string(), 1).await.unwrap();
+                assert_eq!(db.count("user".to_string()), Some(0));
+                assert_eq!(db.count(TABLE_NAME.to_string()), Some(0));
+
+                // Deleting a missing record is a no-op, even with orphans pointing at it
+                db.insert_or_update("comment".to_string(), 2, json!({"id": 2, "item_id": 9})).await.unwrap();
+                let logged = db.stats().await.operations.deletes;
+                assert_eq!(db.delete_by_id(TABLE_NAME.to_string(), 9).await.unwrap(), None);
+                assert_eq!(db.transaction(|tx| tx.delete_by_id(TABLE_NAME.to_string(), 9)).await.unwrap(), None);
+                assert_eq!(db.stats().await.operations.deletes, logged);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_bulk_deletes_check_foreign_keys() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.add_table("comment".to_string(), true).await.unwrap();
+                db.add_table("like".to_string(), true).await.unwrap();
+                assert!(db.add_foreign_key("comment".to_string(), "item_id".to_string(), TABLE_NAME.to_string(), OnDelete::Restrict));
+                assert!(db.add_foreign_key("like".to_string(), "comment_id".to_string(), "comment".to_string(), OnDelete::Cascade));
+
+                for value in ["a", "a", "b"] {
+                    upsert_item(&mut db, value).await;
+                }
+                db.insert_or_update("comment".to_string(), 1, json!({"id": 1, "item_id": 2})).await.unwrap();
+                db.insert_or_update("like".to_string(), 1, json!({"id": 1, "comment_id": 1})).await.unwrap();
+
+                let err = db.delete_where(TABLE_NAME.to_string(), "value".to_string(), "a".to_string()).await.unwrap_err();
+                assert_eq!(err, DbError::ForeignKeyViolation { table: "comment".to_string(), column: "item_id".to_string(), id: 2 });
+                assert_eq!(db.count(TABLE_NAME.to_string()), Some(3));
+
+                assert!(db.delete_all(TABLE_NAME.to_string()).await.is_err());
+                assert_eq!(db.count(TABLE_NAME.to_string()), Some(3));
+                assert!(db.transaction(|tx| tx.delete_all(TABLE_NAME.to_string())).await.is_err());
+                assert_eq!(db.count(TABLE_NAME.to_string()), Some(3));
+
+                db.transaction(|tx| tx.delete_all("comment".to_string())).await.unwrap();
+                assert_eq!(db.count("comment".to_string()), Some(0));
+                assert_eq!(db.count("like".to_string()), Some(0));
+
+                db.insert_or_update("comment".to_string(), 1, json!({"id": 1, "item_id": 2})).await.unwrap();
+                db.insert_or_update("like".to_string(), 1, json!({"id": 1, "comment_id": 1})).await.unwrap();
+                assert!(db.delete_all("comment".to_string()).await.unwrap());
+                assert_eq!(db.count("like".to_string()), Some(0));
+
+                let record = json!({"id": 4, "value": "c"});
+                db.insert_with_ttl(TABLE_NAME.to_string(), 4, record, Duration::ZERO).await.unwrap();
+                db.insert_or_update("comment".to_string(), 2, json!({"id": 2, "item_id": 4})).await.unwrap();
+                db.insert_with_ttl(TABLE_NAME.to_string(), 5, json!({"id": 5, "value": "d"}), Duration::ZERO).await.unwrap();
+                db.insert_with_ttl(TABLE_NAME.to_string(), 6, json!({"id": 6, "value": "e"}), Duration::ZERO).await.unwrap();
+                db.insert_with_ttl("comment".to_string(), 3, json!({"id": 3, "item_id": 6}), Duration::ZERO).await.unwrap();
+                assert_eq!(db.purge_expired().await.unwrap(), 3);
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 4).is_some());
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 5).is_none());
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 6).is_none());
+                assert!(db.find_by_id::<Value>("comment".to_string(), 3).is_none());
+                assert_eq!(db.purge_expired().await.unwrap(), 0);
+
+                assert_eq!(db.delete_where(TABLE_NAME.to_string(), "value".to_string(), "a".to_string()).await.unwrap(), Some(2));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_serde_stats() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (id, _) = upsert_item(&mut db, "counted").await;
+                db.find_all::<Value>(TABLE_NAME.to_string()).unwrap();
+                db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap();
+
+                let stats = db.serde_stats()[TABLE_NAME];
+                assert_eq!((stats.serialized, stats.deserialized, stats.bytes_serialized), (1, 3, 0));
+
+                db.checkpoint().await.unwrap();
+
+                let stats = db.serde_stats()[TABLE_NAME];
+                assert_eq!(stats.serialized, 2);
+                assert!(stats.bytes_serialized > 0);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                for value in ["Red apple", "green Apple", "banana", "apple, red"] {
+                    upsert_item(&mut db, value).await;
+                }
+                db.soft_delete_by_id(TABLE_NAME.to_string(), 4).await.unwrap();
+
+                let ids = |found: Vec<Value>| found.iter().map(|x| x["id"].as_u64().unwrap()).collect::<Vec<u64>>();
+
+                let scanned = db.search::<Value>(TABLE_NAME.to_string(), "apple").unwrap();
+                assert_eq!(ids(scanned), vec![1, 2]);
+
+                assert!(db.add_search_index(TABLE_NAME.to_string()));
+                db.restore_by_id(TABLE_NAME.to_string(), 4).await.unwrap();
+                upsert_item(&mut db, "red banana").await;
+
+                let indexed = db.search::<Value>(TABLE_NAME.to_string(), "RED apple").unwrap();
+                assert_eq!(ids(indexed), vec![1, 4]);
+                assert_eq!(ids(db.search::<Value>(TABLE_NAME.to_string(), "banana").unwrap()), vec![3, 5]);
+                assert!(db.search::<Value>(TABLE_NAME.to_string(), "").unwrap().is_empty());
+                assert!(db.search::<Value>("missing".to_string(), "apple").is_none());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_find_in_range() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let prices = [json!(5), json!(9.5), json!(10), json!("7"), json!(-3), json!(u64::MAX), json!(null)];
+
+                for (i, price) in prices.into_iter().enumerate() {
+                    let id = i as u32 + 1;
+                    db.insert_or_update(TABLE_NAME.to_string(), id, json!({"id": id, "price": price})).await.unwrap();
+                }
+
+                let ids = |found: Vec<Value>| found.iter().map(|x| x["id"].as_u64().unwrap()).collect::<Vec<u64>>();
+                let range = |min: Number, max: Number| db.find_in_range::<Value>(TABLE_NAME.to_string(), "price".to_string(), min, max).unwrap();
+
+                assert_eq!(ids(range(Number::from(5), Number::from(10))), vec![1, 2, 3]);
+                assert_eq!(ids(range(Number::from_f64(5.5).unwrap(), Number::from_f64(9.5).unwrap())), vec![2]);
+                assert_eq!(ids(range(Number::from(-10), Number::from(0))), vec![5]);
+                assert_eq!(ids(range(Number::from(u64::MAX - 1), Number::from(u64::MAX))), vec![6]);
+                assert!(db.find_in_range::<Value>("missing".to_string(), "price".to_string(), Number::from(0), Number::from(1)).is_none());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_find_page_encoded() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let (first, _) = upsert_item(&mut db, "a").await;
+                upsert_item(&mut db, "b").await;
+
+                let page = db.find_page_encoded(TABLE_NAME.to_string(), 0, 1).unwrap();
+                assert_eq!((page.total, page.next_offset), (2, Some(1)));
+                assert_eq!(serde_json::from_slice::<Value>(&page.items[0]).unwrap(), json!({"id": first, "value": "a"}));
+
+                let again = db.find_page_encoded(TABLE_NAME.to_string(), 0, 1).unwrap();
+                assert!(Arc::ptr_eq(&page.items[0], &again.items[0]));
+
+                db.insert_or_update(TABLE_NAME.to_string(), first, json!({"id": first, "value": "changed"})).await.unwrap();
+                let changed = db.find_page_encoded(TABLE_NAME.to_string(), 0, 1).unwrap();
+                assert_eq!(serde_json::from_slice::<Value>(&changed.items[0]).unwrap(), json!({"id": first, "value": "changed"}));
+
+                assert!(db.find_page_encoded("missing".to_string(), 0, 1).is_none());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_uuid_keys() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = Db::init(file_name.clone()).await.unwrap();
+                db.add_table_with_key(TABLE_NAME.to_string(), true, KeyStrategy::Uuid).await.unwrap();
+
+                let key = db.new_key(TABLE_NAME.to_string()).await.unwrap().unwrap();
+                let Key::Uuid(uuid) = key else { panic!("expected a uuid key") };
+
+                let inserted = db.insert_or_update(TABLE_NAME.to_string(), key, json!({"value": "a"})).await.unwrap().unwrap();
+                assert_eq!(inserted, json!({"id": uuid.to_string(), "value": "a"}));
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), uuid), Some(inserted));
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 1), db.find_by_id::<Value>(TABLE_NAME.to_string(), key));
+
+                let updated = db.upsert(TABLE_NAME.to_string(), key, json!({"value": "b"})).await.unwrap().unwrap();
+                assert!(!updated.is_created());
+                assert_eq!(db.count(TABLE_NAME.to_string()), Some(1));
+
+                let other = Key::Uuid(Uuid::new_v4());
+                assert!(!db.exists(TABLE_NAME.to_string(), other));
+                assert!(db.find_by_id::<Value>(TABLE_NAME.to_string(), other).is_none());
+                assert_eq!(db.delete_by_id(TABLE_NAME.to_string(), other).await.unwrap(), None);
+
+                assert!(db.delete_by_id(TABLE_NAME.to_string(), key).await.unwrap().is_some());
+                assert!(!db.exists(TABLE_NAME.to_string(), key));
+
+                assert_eq!("7".parse::<Key>().unwrap(), Key::Id(7));
+                assert_eq!(uuid.to_string().parse::<Key>().unwrap(), key);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete_bumps_version() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.add_versioning(TABLE_NAME.to_string());
+                db.add_timestamps(TABLE_NAME.to_string());
+                let (id, _) = upsert_item(&mut db, "sample").await;
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), id).unwrap()[VERSION_FIELD], 1);
+
+                let deleted = db.soft_delete_by_id(TABLE_NAME.to_string(), id).await.unwrap().unwrap();
+                assert_eq!(deleted[VERSION_FIELD], 2);
+                assert!(deleted[UPDATED_AT_FIELD].is_i64());
+
+                let restored = db.restore_by_id(TABLE_NAME.to_string(), id).await.unwrap().unwrap();
+                assert_eq!(restored[VERSION_FIELD], 3);
+
+                let err = db.insert_or_update_versioned(TABLE_NAME.to_string(), id, json!({"id": id, "value": "stale"}), 1).await.unwrap_err();
+                assert!(matches!(err, DbError::Conflict { expected: 1, actual: 3, .. }));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_versioned_update() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                db.add_versioning(TABLE_NAME.to_string());
+
+                let inserted = db.insert_or_update(TABLE_NAME.to_string(), 1, json!({"id": 1, "value": "a"})).await.unwrap().unwrap();
+                assert_eq!(inserted[VERSION_FIELD], 1);
+
+                let updated = db.insert_or_update_versioned(TABLE_NAME.to_string(), 1, json!({"id": 1, "value": "b"}), 1).await.unwrap().unwrap();
+                assert_eq!(updated[VERSION_FIELD], 2);
+
+                let err = db.insert_or_update_versioned(TABLE_NAME.to_string(), 1, json!({"id": 1, "value": "c"}), 1).await.unwrap_err();
+                assert_eq!(
+                    err,
+                    DbError::Conflict { table: TABLE_NAME.to_string(), id: 1, expected: 1, actual: 2 }
+                );
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 1), Some(updated));
+
+                assert!(db.insert_or_update_versioned(TABLE_NAME.to_string(), 2, json!({"id": 2}), 0).await.unwrap().is_none());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let mut changes = db.watch(TABLE_NAME.to_string());
+
+                let (id, value) = upsert_item(&mut db, "a").await;
+                let updated = json!({"id": id, "value": "b"});
+                db.insert_or_update::<Value>(TABLE_NAME.to_string(), id, updated.clone()).await.unwrap();
+                db.delete_by_id(TABLE_NAME.to_string(), id).await.unwrap();
+
+                assert_eq!(changes.try_recv().unwrap(), ChangeEvent::Inserted { table: TABLE_NAME.to_string(), id, data: value });
+                assert_eq!(changes.try_recv().unwrap(), ChangeEvent::Updated { table: TABLE_NAME.to_string(), id, data: updated });
+                assert_eq!(changes.try_recv().unwrap(), ChangeEvent::Deleted { table: TABLE_NAME.to_string(), id });
+                assert!(changes.try_recv().is_err());
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_watch_transaction() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                let mut changes = db.watch(TABLE_NAME.to_string());
+
+                let result = db.transaction(|tx| {
+                    tx.insert_or_update(TABLE_NAME.to_string(), 1, json!({"id": 1}))?;
+                    Err::<(), _>(DbError::Io("rolled back".to_string()))
+                }).await;
+                assert!(result.is_err());
+                assert!(changes.try_recv().is_err());
+
+                db.transaction(|tx| tx.insert_or_update(TABLE_NAME.to_string(), 1, json!({"id": 1}))).await.unwrap();
+                assert_eq!(changes.try_recv().unwrap(), ChangeEvent::Inserted { table: TABLE_NAME.to_string(), id: 1, data: json!({"id": 1}) });
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_export_csv() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                upsert_item(&mut db, "a").await;
+                let (id, _) = upsert_item(&mut db, "b").await;
+                db.soft_delete_by_id(TABLE_NAME.to_string(), id).await.unwrap();
+
+                let mut out = Vec::new();
+                assert_eq!(db.export_csv(TABLE_NAME.to_string(), &mut out).unwrap(), Some(1));
+                assert_eq!(String::from_utf8(out).unwrap(), "id,value\n1,a\n");
+
+                assert_eq!(db.export_csv("missing".to_string(), Vec::new()).unwrap(), None);
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_import() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                upsert_item(&mut db, "existing").await;
+                db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string()).unwrap();
+
+                let csv = "id,value\n7,a\n,existing\n,b\n";
+                let report = db.import(TABLE_NAME.to_string(), csv.as_bytes(), ImportFormat::Csv).await.unwrap().unwrap();
+
+                assert_eq!(report.ids, vec![2, 4]);
+                assert_eq!(report.errors.len(), 1);
+                assert_eq!(report.errors[0].row, 2);
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 2), Some(json!({"id": 2, "value": "a"})));
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 4), Some(json!({"id": 4, "value": "b"})));
+
+                let json = r#"[{"value": "c"}, "not a record"]"#;
+                let report = db.import(TABLE_NAME.to_string(), json.as_bytes(), ImportFormat::Json).await.unwrap().unwrap();
+                assert_eq!(report.ids, vec![5]);
+                assert_eq!(report.errors, vec![RowError { row: 2, message: "Expected an object, found \"not a record\"".to_string() }]);
+
+                drop(db);
+                let reopened = Db::init(file_name.clone()).await.unwrap();
+                assert_eq!(reopened.find_all::<Value>(TABLE_NAME.to_string()).unwrap().len(), 4);
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_stats() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                upsert_item(&mut db, "a").await;
+                let (id, _) = upsert_item(&mut db, "b").await;
+                db.soft_delete_by_id(TABLE_NAME.to_string(), id).await.unwrap();
+
+                let stats = db.stats().await;
+                assert_eq!(stats.tables[TABLE_NAME], TableStats { rows: 1, soft_deleted: 1 });
+                assert_eq!(stats.operations.upserts, 3);
+                assert_eq!(stats.last_flush_micros, None);
+                assert!(stats.pending_entries > 0);
+
+                db.checkpoint().await.unwrap();
+
+                let stats = db.stats().await;
+                assert_eq!(stats.operations.checkpoints, 1);
+                assert_eq!(stats.pending_entries, 0);
+                assert!(stats.last_flush_micros.is_some());
+                assert_eq!(stats.bytes_on_disk, std::fs::metadata(&file_name).unwrap().len());
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_plan_migrations() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                upsert_item(&mut db, "a").await;
+                db.insert_or_update(TABLE_NAME.to_string(), 2, json!({"id": 2, "value": "b", "label": "b"})).await.unwrap();
+
+                fn add_label(record: &mut Value) {
+                    let value = record["value"].clone();
+                    record.as_object_mut().unwrap().entry("label").or_insert(value);
+                }
+
+                let migrations = [Migration { table: TABLE_NAME, version: 1, migrate: add_label }];
+                let plan = db.plan_migrations(&migrations, 5);
+
+                assert_eq!(plan.tables, vec![TablePlan {
+                    table: TABLE_NAME.to_string(),
+                    from_version: 0,
+                    to_version: 1,
+                    records: 2,
+                    changed: 1,
+                    samples: vec![SampleChange {
+                        id: 1,
+                        before: json!({"id": 1, "value": "a"}),
+                        after: json!({"id": 1, "value": "a", "label": "a"})
+                    }]
+                }]);
+
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 1), Some(json!({"id": 1, "value": "a"})));
+                assert_eq!(db.tables[TABLE_NAME].schema_version, 0);
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_drop_table() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                for backend in [StorageBackend::Json, StorageBackend::Sled] {
+                    let path = format!("{}-{:?}", file_name, backend);
+                    let mut db = Db::open(backend, Compression::None, path.clone()).await.unwrap();
+                    db.add_table(TABLE_NAME.to_string(), false).await.unwrap();
+                    db.add_table("other".to_string(), false).await.unwrap();
+                    db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string()).unwrap();
+                    upsert_item(&mut db, "a").await;
+
+                    assert!(db.drop_table(TABLE_NAME.to_string()).await.unwrap());
+                    assert!(!db.drop_table(TABLE_NAME.to_string()).await.unwrap());
+                    assert_eq!(db.find_all::<Value>(TABLE_NAME.to_string()), None);
+
+                    drop(db);
+                    let mut db = Db::open(backend, Compression::None, path).await.unwrap();
+                    assert_eq!(db.find_all::<Value>(TABLE_NAME.to_string()), None);
+                    assert!(db.find_all::<Value>("other".to_string()).is_some());
+
+                    db.add_table(TABLE_NAME.to_string(), false).await.unwrap();
+                    assert_eq!(upsert_item(&mut db, "a").await.0, 1);
+                }
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_rename_table() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                for backend in [StorageBackend::Json, StorageBackend::Sled] {
+                    let path = format!("{}-{:?}", file_name, backend);
+                    let mut db = Db::open(backend, Compression::None, path.clone()).await.unwrap();
+                    db.add_table(TABLE_NAME.to_string(), false).await.unwrap();
+                    db.add_table("taken".to_string(), false).await.unwrap();
+                    db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string()).unwrap();
+                    let (id, inserted) = upsert_item(&mut db, "a").await;
+                    db.insert_with_ttl(TABLE_NAME.to_string(), 2, json!({"id": 2, "value": "b"}), Duration::from_secs(3600)).await.unwrap();
+
+                    assert!(!db.rename_table(TABLE_NAME.to_string(), "taken".to_string()).await.unwrap());
+                    assert_eq!(
+                        db.rename_table(TABLE_NAME.to_string(), "sample/1".to_string()).await,
+                        Err(DbError::InvalidTableName("sample/1".to_string()))
+                    );
+                    assert!(matches!(db.add_table("taken/1".to_string(), false).await, Err(DbError::InvalidTableName(_))));
+                    assert!(db.rename_table(TABLE_NAME.to_string(), "renamed".to_string()).await.unwrap());
+                    assert_eq!(db.find_all::<Value>(TABLE_NAME.to_string()), None);
+                    assert_eq!(db.find_by_value::<Value>("renamed".to_string(), "value".to_string(), "a".to_string()), Some(vec![inserted.clone()]));
+
+                    let err = db.insert_or_update("renamed".to_string(), 3, json!({"id": 3, "value": "a"})).await.unwrap_err();
+                    assert!(matches!(err, DbError::UniqueViolation { .. }));
+
+                    drop(db);
+                    let mut db = Db::open(backend, Compression::None, path).await.unwrap();
+                    assert_eq!(db.find_by_id::<Value>("renamed".to_string(), id), Some(inserted));
+                    assert_eq!(db.tables["renamed"].expires_at.len(), 1);
+                    assert_eq!(db.get_increment_last_id("renamed".to_string()).await.unwrap(), Some(2));
+                }
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_update_where() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                for value in ["a", "b", "a", "a"] {
+                    upsert_item(&mut db, value).await;
+                }
+                db.soft_delete_by_id(TABLE_NAME.to_string(), 4).await.unwrap();
+
+                let rename = |mut record: Value| {
+                    record["value"] = json!("c");
+                    record
+                };
+                let updated = db.update_where(TABLE_NAME.to_string(), |x: &Value| x["value"] == "a", rename).await.unwrap();
+                assert_eq!(updated, Some(2));
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 3), Some(json!({"id": 3, "value": "c"})));
+                assert_eq!(db.find_where(TABLE_NAME.to_string(), |x: &Value| x["value"] == "c").map(|x| x.len()), Some(2));
+                assert_eq!(db.tables[TABLE_NAME].data[&4]["value"], "a");
+
+                db.delete_by_id(TABLE_NAME.to_string(), 3).await.unwrap();
+                db.add_unique_constraint(TABLE_NAME.to_string(), "value".to_string()).unwrap();
+                let err = db.update_where(TABLE_NAME.to_string(), |_: &Value| true, |mut record: Value| {
+                    record["value"] = json!("b");
+                    record
+                }).await.unwrap_err();
+                assert!(matches!(err, DbError::UniqueViolation { .. }));
+                assert_eq!(db.find_by_id::<Value>(TABLE_NAME.to_string(), 1), Some(json!({"id": 1, "value": "c"})));
+
+                assert_eq!(db.update_where("missing".to_string(), |_: &Value| true, |x: Value| x).await.unwrap(), None);
+            }
+        }).await;
+    }
+
+
+    #[tokio::test]
+    async fn test_aggregate() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = init_db(&file_name).await;
+                for value in ["a", "b", "a", "a"] {
+                    upsert_item(&mut db, value).await;
+                }
+                db.soft_delete_by_id(TABLE_NAME.to_string(), 4).await.unwrap();
+
+                assert_eq!(db.aggregate(TABLE_NAME.to_string(), "id".to_string(), Agg::Count), Some(json!(3)));
+                assert_eq!(db.aggregate(TABLE_NAME.to_string(), "id".to_string(), Agg::Avg), Some(json!(2.0)));
+                assert_eq!(
+                    db.group_by(TABLE_NAME.to_string(), "value".to_string(), "id".to_string(), Agg::Max),
+                    Some(BTreeMap::from([("a".to_string(), json!(3)), ("b".to_string(), json!(2))]))
+                );
+
+                assert_eq!(db.aggregate("missing".to_string(), "id".to_string(), Agg::Sum), None);
+            }
+        }).await;
+    }
+ }
\ No newline at end of file
@@ -0,0 +1,240 @@
+use std::cmp::Ordering;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+
+use super::{is_deleted, TableData};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Contains
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Asc,
+    Desc
+}
+
+struct Filter {
+    column: String,
+    op: Op,
+    value: Value
+}
+
+impl Filter {
+    fn matches(&self, record: &Value) -> bool {
+        let Some(field) = record.get(&self.column) else {
+            return self.op == Op::Ne
+        };
+
+        match self.op {
+            Op::Eq => *field == self.value,
+            Op::Ne => *field != self.value,
+            Op::Gt => compare_values(field, &self.value) == Some(Ordering::Greater),
+            Op::Gte => matches!(compare_values(field, &self.value), Some(Ordering::Greater | Ordering::Equal)),
+            Op::Lt => compare_values(field, &self.value) == Some(Ordering::Less),
+            Op::Lte => matches!(compare_values(field, &self.value), Some(Ordering::Less | Ordering::Equal)),
+            Op::Contains => match (field, &self.value) {
+                (Value::String(field), Value::String(value)) => field.contains(value.as_str()),
+                (Value::Array(field), value) => field.contains(value),
+                _ => false
+            }
+        }
+    }
+}
+
+/// Compares two json values of the same kind, numbers by value and strings
+/// lexicographically. Values of different kinds are not comparable.
+pub(crate) fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => compare_numbers(a, b),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None
+    }
+}
+
+/// Compares json numbers exactly when both are integers, so large ids and
+/// counts do not collide as floats, and as floats otherwise.
+pub(crate) fn compare_numbers(a: &Number, b: &Number) -> Option<Ordering> {
+    match (a.as_i64(), b.as_i64(), a.as_u64(), b.as_u64()) {
+        (Some(a), Some(b), _, _) => Some(a.cmp(&b)),
+        (_, _, Some(a), Some(b)) => Some(a.cmp(&b)),
+        // Only one side is past i64::MAX
+        (Some(_), None, _, Some(_)) => Some(Ordering::Less),
+        (None, Some(_), Some(_), _) => Some(Ordering::Greater),
+        _ => a.as_f64()?.partial_cmp(&b.as_f64()?)
+    }
+}
+
+/// Orders records by a field, placing records missing the field last
+/// regardless of direction.
+pub(crate) fn compare_by_field(a: &Value, b: &Value, column: &str, direction: Direction) -> Ordering {
+    match (a.get(column), b.get(column)) {
+        (Some(a), Some(b)) => {
+            let ordering = compare_values(a, b).unwrap_or(Ordering::Equal);
+
+            match direction {
+                Direction::Asc => ordering,
+                Direction::Desc => ordering.reverse()
+            }
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal
+    }
+}
+
+/// Query over a single table, built with chained calls and evaluated in one
+/// pass over the records by [`Query::fetch`].
+pub struct Query<'a, T> {
+    table: Option<&'a TableData>,
+    filters: Vec<Filter>,
+    sort: Option<(String, Direction)>,
+    offset: usize,
+    limit: Option<usize>,
+    marker: PhantomData<T>
+}
+
+impl<'a, T> Query<'a, T>
+    where T: DeserializeOwned
+{
+    pub(super) fn new(table: Option<&'a TableData>) -> Self {
+        Self {
+            table,
+            filters: vec![],
+            sort: None,
+            offset: 0,
+            limit: None,
+            marker: PhantomData
+        }
+    }
+
+    pub fn filter<V: Into<Value>>(mut self, column: &str, op: Op, value: V) -> Self {
+        self.filters.push(Filter { column: column.to_string(), op, value: value.into() });
+        self
+    }
+
+    pub fn sort_by(mut self, column: &str, direction: Direction) -> Self {
+        self.sort = Some((column.to_string(), direction));
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Runs the query, returning `None` if the table does not exist.
+    pub fn fetch(self) -> Option<Vec<T>> {
+        let table = self.table?;
+
+        let mut records: Vec<&Value> = table
+            .data
+            .values()
+            .filter(|x| !is_deleted(x))
+            .filter(|x| self.filters.iter().all(|filter| filter.matches(x)))
+            .collect();
+
+        if let Some((column, direction)) = &self.sort {
+            records.sort_by(|a, b| compare_by_field(a, b, column, *direction));
+        }
+
+        Some(
+            records
+                .into_iter()
+                .skip(self.offset)
+                .take(self.limit.unwrap_or(usize::MAX))
+                .cloned()
+                .map(|x| serde_json::from_value::<T>(x).unwrap())
+                .collect()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::db::Db;
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    const TABLE_NAME: &str = "sample";
+
+    async fn init_db(file_name: String) -> Db {
+        let mut db = Db::init(file_name).await.unwrap();
+        db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+
+        for (name, quantity) in [("foo", 3), ("bar", 1), ("food", 2), ("baz", 5)] {
+            let id = db.get_increment_last_id(TABLE_NAME.to_string()).await.unwrap().unwrap();
+            let to_insert = json!({"id": id, "name": name, "quantity": quantity});
+            db.insert_or_update(TABLE_NAME.to_string(), id, to_insert).await.unwrap();
+        }
+
+        db
+    }
+
+    fn names(records: Vec<Value>) -> Vec<String> {
+        records
+            .into_iter()
+            .map(|x| x["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_filter_sort_limit() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let db = init_db(file_name).await;
+
+                let result = db.query::<Value>(TABLE_NAME)
+                    .filter("name", Op::Contains, "foo")
+                    .sort_by("quantity", Direction::Asc)
+                    .fetch()
+                    .unwrap();
+                assert_eq!(names(result), vec!["food", "foo"]);
+
+                let result = db.query::<Value>(TABLE_NAME)
+                    .filter("quantity", Op::Gte, 2)
+                    .sort_by("id", Direction::Desc)
+                    .limit(2)
+                    .fetch()
+                    .unwrap();
+                assert_eq!(names(result), vec!["baz", "food"]);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_missing_table() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let db = init_db(file_name).await;
+
+                assert!(db.query::<Value>("missing").fetch().is_none());
+            }
+        }).await;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,18 @@
+/// What deleting a referenced record does to the records pointing at it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnDelete {
+    /// The delete fails with [`super::DbError::ForeignKeyViolation`].
+    Restrict,
+    /// The referencing records are deleted along with it.
+    Cascade
+}
+
+/// Foreign key declaring that `column` of `table` holds ids of records in
+/// the `references` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relation {
+    pub table: String,
+    pub column: String,
+    pub references: String,
+    pub on_delete: OnDelete
+}
\ No newline at end of file
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::db::DbResult;
+
+
+/// Encodes single records, for backends that store each record on its own.
+pub trait Codec: Send + Sync {
+    fn encode(&self, record: &Value) -> DbResult<Vec<u8>>;
+
+    fn decode(&self, bytes: &[u8]) -> DbResult<Value>;
+}
+
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode(&self, record: &Value) -> DbResult<Vec<u8>> {
+        Ok(serde_json::to_vec(record)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> DbResult<Value> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+pub struct MessagePackCodec;
+
+impl Codec for MessagePackCodec {
+    fn encode(&self, record: &Value) -> DbResult<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(record)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> DbResult<Value> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+/// The codec a table's records are stored with, kept with the table so they
+/// can be decoded again. Set with [`crate::db::Db::set_codec`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CodecKind {
+    #[default]
+    Json,
+    MessagePack
+}
+
+impl CodecKind {
+    pub fn codec(&self) -> &'static dyn Codec {
+        match self {
+            CodecKind::Json => &JsonCodec,
+            CodecKind::MessagePack => &MessagePackCodec
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == CodecKind::default()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,739 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use fs4::fs_std::FileExt;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use super::{Scanned, Storage};
+use crate::db::metrics::SerdeMetrics;
+use crate::db::wal::{Wal, WalEntry};
+use crate::db::{DbError, DbResult, TableData};
+
+
+/// Encoding of the main db file. The write-ahead log is json in either case.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    #[default]
+    Json,
+    MessagePack
+}
+
+impl FileFormat {
+    pub(crate) fn encode(&self, tables: &HashMap<String, TableData>) -> DbResult<Vec<u8>> {
+        let mut encoded = BTreeMap::new();
+
+        for (name, table) in tables {
+            encoded.insert(name.clone(), self.encode_table(table)?);
+        }
+
+        self.assemble(&encoded)
+    }
+
+    fn encode_table(&self, table: &TableData) -> DbResult<Vec<u8>> {
+        Ok(match self {
+            FileFormat::Json => serde_json::to_vec(table)?,
+            FileFormat::MessagePack => rmp_serde::to_vec_named(table)?
+        })
+    }
+
+    /// Joins separately encoded tables into the map a whole file decodes as,
+    /// so unchanged tables need not be serialized again.
+    fn assemble(&self, encoded: &BTreeMap<String, Vec<u8>>) -> DbResult<Vec<u8>> {
+        let mut contents = Vec::with_capacity(encoded.values().map(|table| table.len() + 32).sum());
+
+        match self {
+            FileFormat::Json => {
+                contents.push(b'{');
+
+                for (i, (name, table)) in encoded.iter().enumerate() {
+                    if i > 0 {
+                        contents.push(b',');
+                    }
+
+                    serde_json::to_writer(&mut contents, name)?;
+                    contents.push(b':');
+                    contents.extend_from_slice(table);
+                }
+
+                contents.push(b'}');
+            },
+            FileFormat::MessagePack => {
+                rmp::encode::write_map_len(&mut contents, encoded.len() as u32)?;
+
+                for (name, table) in encoded {
+                    rmp::encode::write_str(&mut contents, name)?;
+                    contents.extend_from_slice(table);
+                }
+            }
+        }
+
+        Ok(contents)
+    }
+
+    /// Decodes a db file, treating an empty one as having no tables.
+    pub(crate) fn decode(&self, contents: &[u8]) -> DbResult<HashMap<String, TableData>> {
+        if contents.is_empty() {
+            return Ok(HashMap::new())
+        }
+
+        Ok(match self {
+            FileFormat::Json => serde_json::from_slice(contents)?,
+            FileFormat::MessagePack => rmp_serde::from_slice(contents)?
+        })
+    }
+
+    /// Decodes a db file while reading it, see [`FileFormat::decode`].
+    fn decode_reader<R: BufRead>(&self, mut reader: R) -> std::io::Result<HashMap<String, TableData>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(HashMap::new())
+        }
+
+        match self {
+            FileFormat::Json => Ok(serde_json::from_reader(reader)?),
+            FileFormat::MessagePack => rmp_serde::from_read(reader)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+        }
+    }
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+const DEFAULT_GZIP_LEVEL: u32 = 6;
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+fn default_gzip_level() -> u32 {
+    DEFAULT_GZIP_LEVEL
+}
+
+fn default_zstd_level() -> i32 {
+    DEFAULT_ZSTD_LEVEL
+}
+
+/// Compression applied to the main db file and the write-ahead log when they
+/// are written. Reads detect the compression from the magic bytes instead, so
+/// changing this setting takes effect at the next checkpoint without
+/// converting anything.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    None,
+    /// `level` ranges from 0 (store) to 9 (smallest).
+    Gzip {
+        #[serde(default = "default_gzip_level")]
+        level: u32
+    },
+    /// `level` ranges from 1 to 22, with 0 picking zstd's own default.
+    Zstd {
+        #[serde(default = "default_zstd_level")]
+        level: i32
+    }
+}
+
+impl Compression {
+    /// Detects the compression of `contents`, reporting the default level as
+    /// the level used is not recorded in the stream.
+    pub fn detect(contents: &[u8]) -> Self {
+        if contents.starts_with(GZIP_MAGIC) {
+            Compression::Gzip { level: DEFAULT_GZIP_LEVEL }
+        } else if contents.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd { level: DEFAULT_ZSTD_LEVEL }
+        } else {
+            Compression::None
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        matches!(self, Compression::None)
+    }
+
+    pub fn compress(&self, contents: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        match *self {
+            Compression::None => Ok(contents),
+            Compression::Gzip { level } => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                encoder.write_all(&contents)?;
+                encoder.finish()
+            },
+            Compression::Zstd { level } => zstd::encode_all(contents.as_slice(), level)
+        }
+    }
+
+    /// Wraps `reader` in a decoder chosen by its magic bytes, so large files
+    /// are decompressed as they are parsed rather than buffered whole.
+    /// Concatenated gzip members and zstd frames are read back to back.
+    pub fn decoder<'a, R: BufRead + 'a>(mut reader: R) -> std::io::Result<Box<dyn Read + 'a>> {
+        Ok(match Self::detect(reader.fill_buf()?) {
+            Compression::None => Box::new(reader),
+            Compression::Gzip { .. } => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+            Compression::Zstd { .. } => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?)
+        })
+    }
+
+    /// Decodes the single gzip member or zstd frame `contents` starts with,
+    /// returning it along with the number of bytes it took up. A frame cut
+    /// short, down to a partial magic, fails with `UnexpectedEof`.
+    pub fn decode_frame(contents: &[u8]) -> std::io::Result<(Vec<u8>, usize)> {
+        let mut decoded = Vec::new();
+
+        let rest = match Self::detect(contents) {
+            Compression::Gzip { .. } => {
+                let mut decoder = flate2::bufread::GzDecoder::new(contents);
+                decoder.read_to_end(&mut decoded)?;
+                decoder.into_inner()
+            },
+            Compression::Zstd { .. } => {
+                let mut decoder = zstd::stream::read::Decoder::with_buffer(contents)?.single_frame();
+                decoder.read_to_end(&mut decoded)?;
+                decoder.finish()
+            },
+            Compression::None if GZIP_MAGIC.starts_with(contents) || ZSTD_MAGIC.starts_with(contents) => {
+                return Err(std::io::ErrorKind::UnexpectedEof.into())
+            },
+            Compression::None => return Err(invalid_data("Expected a gzip member or zstd frame".to_string()))
+        };
+
+        Ok((decoded, contents.len() - rest.len()))
+    }
+
+    /// Decompresses `contents` according to its magic bytes.
+    pub fn decompress(contents: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        if Self::detect(&contents).is_none() {
+            return Ok(contents)
+        }
+
+        let mut decompressed = Vec::new();
+        Self::decoder(contents.as_slice())?.read_to_end(&mut decompressed)?;
+
+        Ok(decompressed)
+    }
+}
+
+/// Start of the header line of db files written with a checksum, followed by
+/// the hex sha256 of the rest of the file. Files without it, from before
+/// checksums, are read unchecked.
+const CHECKSUM_MAGIC: &[u8] = b"PSDB ";
+const HEADER_LEN: usize = CHECKSUM_MAGIC.len() + 64 + 1;
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn checksum(digest: Sha256) -> String {
+    format!("{:x}", digest.finalize())
+}
+
+fn header_checksum(header: &[u8]) -> &[u8] {
+    &header[CHECKSUM_MAGIC.len()..HEADER_LEN - 1]
+}
+
+/// Prepends the checksum header to the (compressed) contents of a db file.
+fn seal(contents: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::with_capacity(HEADER_LEN + contents.len());
+    sealed.extend_from_slice(CHECKSUM_MAGIC);
+    sealed.extend_from_slice(checksum(Sha256::new_with_prefix(contents)).as_bytes());
+    sealed.push(b'\n');
+    sealed.extend_from_slice(contents);
+
+    sealed
+}
+
+/// Checks and strips the header added by [`seal`], passing files without one through.
+pub(crate) fn unseal(contents: &[u8]) -> std::io::Result<&[u8]> {
+    if !contents.starts_with(CHECKSUM_MAGIC) {
+        return Ok(contents)
+    }
+
+    if contents.len() < HEADER_LEN {
+        return Err(invalid_data("file is truncated".to_string()))
+    }
+
+    let (header, body) = contents.split_at(HEADER_LEN);
+
+    if checksum(Sha256::new_with_prefix(body)).as_bytes() != header_checksum(header) {
+        return Err(invalid_data("checksum mismatch".to_string()))
+    }
+
+    Ok(body)
+}
+
+/// Passes reads through while hashing every byte read.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+
+        Ok(read)
+    }
+}
+
+/// Whether the file starts with the header written by [`seal`]. Files from
+/// before checksums do not.
+fn is_sealed(path: &Path) -> std::io::Result<bool> {
+    let mut start = Vec::with_capacity(CHECKSUM_MAGIC.len());
+    std::fs::File::open(path)?.take(CHECKSUM_MAGIC.len() as u64).read_to_end(&mut start)?;
+
+    Ok(start.is_empty() || start == CHECKSUM_MAGIC)
+}
+
+/// Decodes a db file while reading it, checking its checksum if it has one.
+/// Any damage, be it a bad checksum or a file cut short, is reported as
+/// [`std::io::ErrorKind::InvalidData`].
+fn read_file(path: &Path, format: FileFormat) -> std::io::Result<HashMap<String, TableData>> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+
+    if !reader.fill_buf()?.starts_with(CHECKSUM_MAGIC) {
+        return Compression::decoder(reader)
+            .and_then(|decoder| format.decode_reader(BufReader::new(decoder)))
+            .map_err(|err| invalid_data(err.to_string()))
+    }
+
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header).map_err(|_| invalid_data("file is truncated".to_string()))?;
+
+    let mut hashing = HashingReader { inner: reader, hasher: Sha256::new() };
+    let tables = Compression::decoder(BufReader::new(&mut hashing))
+        .and_then(|decoder| format.decode_reader(BufReader::new(decoder)));
+
+    // Whatever the decoder left unread still counts towards the checksum
+    std::io::copy(&mut hashing, &mut std::io::sink())?;
+
+    if checksum(hashing.hasher).as_bytes() != header_checksum(&header) {
+        return Err(invalid_data("checksum mismatch".to_string()))
+    }
+
+    tables.map_err(|err| invalid_data(err.to_string()))
+}
+
+/// Rewrites a db file from one format to another, e.g. to move an existing
+/// json file over to [`FileFormat::MessagePack`]. Any write-ahead log next to
+/// `source` should be checkpointed first, as it is not carried over. The
+/// compression of `source` is kept.
+pub async fn convert(source: &Path, source_format: FileFormat, target: &Path, target_format: FileFormat) -> DbResult<()> {
+    let contents = tokio::fs::read(source).await?;
+    let contents = unseal(&contents)?;
+    let compression = Compression::detect(contents);
+    let tables = source_format.decode(&Compression::decompress(contents.to_vec())?)?;
+    write_atomic(target, &seal(&compression.compress(target_format.encode(&tables)?)?)).await?;
+
+    Ok(())
+}
+
+/// Stores the tables as a single file, with mutations appended to a `.wal`
+/// sibling until the next checkpoint rewrites the file. Each table's encoding
+/// is kept between checkpoints and only redone for tables logged to since.
+/// The file is written with a checksum header, and the one it replaces is
+/// kept as a `.bak` sibling to recover from if it turns out damaged.
+pub struct FileStorage {
+    path: PathBuf,
+    format: FileFormat,
+    compression: Compression,
+    wal: Wal,
+    encoded: BTreeMap<String, Vec<u8>>,
+    dirty: HashSet<String>,
+    metrics: SerdeMetrics,
+    /// Held open for its advisory lock, released when the storage is dropped.
+    _lock: std::fs::File
+}
+
+impl FileStorage {
+    /// Fails with [`DbError::Locked`] if another process has the file open, so
+    /// two servers never overwrite each other's checkpoints.
+    pub async fn open(file_name: String, format: FileFormat, compression: Compression) -> DbResult<Self> {
+        let lock = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(format!("{}.lock", file_name))?;
+
+        if lock.try_lock_exclusive().is_err() {
+            return Err(DbError::Locked { path: file_name })
+        }
+
+        let wal = Wal::open(format!("{}.wal", file_name), compression).await?;
+
+        Ok(Self {
+            path: PathBuf::from(file_name),
+            format,
+            compression,
+            wal,
+            encoded: BTreeMap::new(),
+            dirty: HashSet::new(),
+            metrics: SerdeMetrics::default(),
+            _lock: lock
+        })
+    }
+
+    fn sibling(&self, extension: &str) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), extension))
+    }
+
+    /// Keeps the file about to be overwritten as `.bak`. It is hard linked,
+    /// so the new file is renamed into place without touching the backup.
+    async fn back_up(&self) -> std::io::Result<()> {
+        if !self.path.exists() {
+            return Ok(())
+        }
+
+        let backup = self.sibling("bak");
+
+        match tokio::fs::remove_file(&backup).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+
+        if tokio::fs::hard_link(&self.path, &backup).await.is_err() {
+            tokio::fs::copy(&self.path, &backup).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites a file from before checksums in the current layout, keeping
+    /// the original as `.legacy`. The log is left alone to be replayed on top.
+    async fn upgrade_legacy(&self, tables: &HashMap<String, TableData>) -> DbResult<()> {
+        let legacy = self.sibling("legacy");
+        tokio::fs::copy(&self.path, &legacy).await?;
+
+        let contents = seal(&self.compression.compress(self.format.encode(tables)?)?);
+        write_atomic(&self.path, &contents).await?;
+
+        println!(
+            "Db file {} predates checksums and was upgraded: {} tables with {} records rewritten with a checksum, \
+             tables without a schema version start at 0 and get every migration. The original is kept as {}",
+            self.path.display(),
+            tables.len(),
+            tables.values().map(|table| table.data.len()).sum::<usize>(),
+            legacy.display()
+        );
+
+        Ok(())
+    }
+
+    /// Falls back to the `.bak` file when the main file is unreadable, moving
+    /// the damaged one aside as `.corrupt` and restoring the backup in its place.
+    /// Fails with [`DbError::Corrupt`] if there is no usable backup either.
+    async fn recover(&self, err: std::io::Error) -> DbResult<HashMap<String, TableData>> {
+        let corrupt = |message: String| DbError::Corrupt { path: self.path.display().to_string(), message };
+
+        if err.kind() != std::io::ErrorKind::InvalidData {
+            return Err(err.into())
+        }
+
+        let backup = self.sibling("bak");
+
+        if !backup.exists() {
+            return Err(corrupt(format!("{}, and there is no backup", err)))
+        }
+
+        let format = self.format;
+        let backup_path = backup.clone();
+        let tables = tokio::task::spawn_blocking(move || read_file(&backup_path, format))
+            .await?
+            .map_err(|backup_err| corrupt(format!("{}, and the backup is unreadable too: {}", err, backup_err)))?;
+
+        let moved_to = self.sibling("corrupt");
+        tokio::fs::rename(&self.path, &moved_to).await?;
+        write_atomic(&self.path, &tokio::fs::read(&backup).await?).await?;
+
+        println!(
+            "Db file {} is damaged ({}), recovered it from {}. Writes checkpointed since the backup are lost; the damaged file is kept as {}",
+            self.path.display(),
+            err,
+            backup.display(),
+            moved_to.display()
+        );
+
+        Ok(tables)
+    }
+}
+
+impl Storage for FileStorage {
+    fn scan(&mut self) -> BoxFuture<'_, DbResult<Scanned>> {
+        async move {
+            let mut tables: HashMap<String, TableData> = HashMap::new();
+
+            if self.path.exists() {
+                let path = self.path.clone();
+                let format = self.format;
+
+                tables = match tokio::task::spawn_blocking(move || read_file(&path, format)).await? {
+                    Ok(tables) => tables,
+                    Err(err) => self.recover(err).await?
+                };
+
+                if !is_sealed(&self.path)? {
+                    self.upgrade_legacy(&tables).await?;
+                }
+            }
+
+            let entries = self.wal.read_entries().await?;
+
+            Ok((tables, entries))
+        }.boxed()
+    }
+
+    fn put<'a>(&'a mut self, entries: &'a [WalEntry]) -> BoxFuture<'a, DbResult<()>> {
+        async move {
+            self.wal.append_all(entries).await?;
+            self.dirty.extend(entries.iter().map(|entry| entry.table().to_string()));
+
+            Ok(())
+        }.boxed()
+    }
+
+    fn checkpoint<'a>(&'a mut self, tables: &'a HashMap<String, TableData>) -> BoxFuture<'a, DbResult<()>> {
+        async move {
+            self.encoded.retain(|name, _| tables.contains_key(name) && !self.dirty.contains(name));
+
+            for (name, table) in tables {
+                if !self.encoded.contains_key(name) {
+                    let encoded = self.format.encode_table(table)?;
+                    self.metrics.record_serialize(name, table.data.len(), encoded.len());
+                    self.encoded.insert(name.clone(), encoded);
+                }
+            }
+
+            self.dirty.clear();
+
+            let contents = seal(&self.compression.compress(self.format.assemble(&self.encoded)?)?);
+
+            self.back_up().await?;
+            write_atomic(&self.path, &contents).await?;
+            self.wal.truncate().await?;
+
+            Ok(())
+        }.boxed()
+    }
+
+    fn replace<'a>(&'a mut self, tables: &'a HashMap<String, TableData>) -> BoxFuture<'a, DbResult<()>> {
+        self.encoded.clear();
+        self.checkpoint(tables)
+    }
+
+    fn set_metrics(&mut self, metrics: SerdeMetrics) {
+        self.metrics = metrics;
+    }
+
+    fn size_on_disk(&self) -> u64 {
+        [self.path.clone(), self.sibling("wal"), self.sibling("bak")]
+            .iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+}
+
+/// Replaces the file atomically: the data is written and synced to a sibling
+/// temp file which is then renamed over the original, so a crash mid-write
+/// leaves the previous contents intact.
+pub(crate) async fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let temp_path = temp_path_for(path);
+    {
+        let mut temp_file = File::create(&temp_path).await?;
+        temp_file.write_all(data).await?;
+        temp_file.sync_all().await?;
+    }
+
+    tokio::fs::rename(&temp_path, path).await?;
+    sync_parent_dir(path).await
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    PathBuf::from(temp_path)
+}
+
+/// Syncs the directory entry so a completed rename survives a crash.
+#[cfg(unix)]
+async fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        Some(parent) => File::open(parent).await?.sync_all().await,
+        None => File::open(".").await?.sync_all().await
+    }
+}
+
+#[cfg(not(unix))]
+async fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_convert_json_to_message_pack() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let table = TableData {
+                    next_id: 2,
+                    schema_version: 0,
+                    codec: Default::default(),
+                    data: BTreeMap::from([(1, json!({"id": 1, "tags": ["a"], "nested": {"n": 1.5}}))]),
+                    expires_at: BTreeMap::from([(1, 100)])
+                };
+                let tables = HashMap::from([("sample".to_string(), table)]);
+                std::fs::write(&file_name, FileFormat::Json.encode(&tables).unwrap()).unwrap();
+
+                let target = PathBuf::from(format!("{}.msgpack", file_name));
+                convert(Path::new(&file_name), FileFormat::Json, &target, FileFormat::MessagePack).await.unwrap();
+
+                let mut storage = FileStorage::open(target.to_string_lossy().to_string(), FileFormat::MessagePack, Compression::None).await.unwrap();
+                let (converted, _) = storage.scan().await.unwrap();
+
+                assert_eq!(
+                    serde_json::to_value(&converted).unwrap(),
+                    serde_json::to_value(&tables).unwrap()
+                );
+            }
+        }).await;
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        let contents = br#"{"sample": {"next_id": 1, "data": {}}}"#.repeat(8);
+
+        for compression in [Compression::None, Compression::Gzip { level: DEFAULT_GZIP_LEVEL }, Compression::Zstd { level: DEFAULT_ZSTD_LEVEL }] {
+            let compressed = compression.compress(contents.clone()).unwrap();
+
+            assert_eq!(Compression::detect(&compressed), compression);
+            assert_eq!(compressed.len() < contents.len(), !compression.is_none());
+            assert_eq!(Compression::decompress(compressed).unwrap(), contents);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_reencodes_dirty_tables() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                for format in [FileFormat::Json, FileFormat::MessagePack] {
+                    let path = format!("{}-{:?}", file_name, format);
+                    let mut storage = FileStorage::open(path.clone(), format, Compression::None).await.unwrap();
+                    let table = |value: &str| TableData {
+                        next_id: 2,
+                        schema_version: 0,
+                        codec: Default::default(),
+                        data: BTreeMap::from([(1, serde_json::json!({"id": 1, "value": value}))]),
+                        expires_at: BTreeMap::new()
+                    };
+
+                    let mut tables = HashMap::from([("a".to_string(), table("first")), ("b".to_string(), table("first"))]);
+                    storage.checkpoint(&tables).await.unwrap();
+
+                    // Only `a` is logged to, so a stale `b` must keep its old encoding.
+                    tables.insert("a".to_string(), table("second"));
+                    tables.insert("b".to_string(), table("unlogged"));
+                    storage.put(&[WalEntry::Delete { table: "a".to_string(), id: 5 }]).await.unwrap();
+                    storage.checkpoint(&tables).await.unwrap();
+                    drop(storage);
+
+                    let (scanned, _) = FileStorage::open(path, format, Compression::None).await.unwrap().scan().await.unwrap();
+
+                    assert_eq!(scanned["a"].data[&1]["value"], "second");
+                    assert_eq!(scanned["b"].data[&1]["value"], "first");
+                }
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_open_locks_file() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let storage = FileStorage::open(file_name.clone(), FileFormat::Json, Compression::None).await.unwrap();
+
+                let err = FileStorage::open(file_name.clone(), FileFormat::Json, Compression::None).await.err().unwrap();
+                assert_eq!(err, DbError::Locked { path: file_name.clone() });
+
+                drop(storage);
+                assert!(FileStorage::open(file_name, FileFormat::Json, Compression::None).await.is_ok());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_recover_from_backup() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let table = |value: &str| TableData {
+                    next_id: 2,
+                    schema_version: 0,
+                    codec: Default::default(),
+                    data: BTreeMap::from([(1, json!({"id": 1, "value": value}))]),
+                    expires_at: BTreeMap::new()
+                };
+                let mut storage = FileStorage::open(file_name.clone(), FileFormat::Json, Compression::None).await.unwrap();
+                storage.checkpoint(&HashMap::from([("a".to_string(), table("first"))])).await.unwrap();
+                storage.checkpoint(&HashMap::from([("a".to_string(), table("second"))])).await.unwrap();
+                drop(storage);
+
+                // Flip a byte in the middle of the checkpointed contents
+                let mut contents = std::fs::read(&file_name).unwrap();
+                let middle = contents.len() / 2;
+                contents[middle] ^= 0xff;
+                std::fs::write(&file_name, contents).unwrap();
+
+                let mut storage = FileStorage::open(file_name.clone(), FileFormat::Json, Compression::None).await.unwrap();
+                let (tables, _) = storage.scan().await.unwrap();
+
+                assert_eq!(tables["a"].data[&1]["value"], "first");
+                assert!(Path::new(&format!("{}.corrupt", file_name)).exists());
+                assert!(read_file(Path::new(&file_name), FileFormat::Json).is_ok());
+
+                std::fs::remove_file(format!("{}.bak", file_name)).unwrap();
+                std::fs::write(&file_name, b"{\"a\":{\"next_id\"").unwrap();
+
+                let err = storage.scan().await.err().unwrap();
+                assert!(matches!(err, DbError::Corrupt { .. }));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_legacy_file() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let legacy = r#"{"sample":{"next_id":2,"data":{"1":{"id":1}}}}"#;
+                std::fs::write(&file_name, legacy).unwrap();
+
+                let mut storage = FileStorage::open(file_name.clone(), FileFormat::Json, Compression::None).await.unwrap();
+                let (tables, _) = storage.scan().await.unwrap();
+
+                assert_eq!(tables["sample"].data[&1], json!({"id": 1}));
+                assert_eq!(tables["sample"].schema_version, 0);
+                assert_eq!(std::fs::read_to_string(format!("{}.legacy", file_name)).unwrap(), legacy);
+                assert!(is_sealed(Path::new(&file_name)).unwrap());
+
+                let (rescanned, _) = storage.scan().await.unwrap();
+                assert_eq!(rescanned["sample"].data, tables["sample"].data);
+            }
+        }).await;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,73 @@
+pub mod codec;
+pub mod file;
+pub mod memory;
+pub mod sled;
+
+use std::collections::HashMap;
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+
+use super::metrics::SerdeMetrics;
+use super::wal::WalEntry;
+use super::{DbResult, TableData};
+use self::file::{Compression, FileFormat, FileStorage};
+use self::sled::SledStorage;
+
+
+/// Engine the [`super::Db`] persists its tables with, chosen by `storage` in the config.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// A json snapshot file with a write-ahead log next to it.
+    #[default]
+    Json,
+    /// Like `Json` but the file is MessagePack encoded, which is smaller and
+    /// faster to write for larger tables.
+    MessagePack,
+    /// A sled database directory.
+    Sled
+}
+
+/// Stored tables and the mutations logged after they were last written out.
+pub(crate) type Scanned = (HashMap<String, TableData>, Vec<WalEntry>);
+
+/// Where the tables of a [`super::Db`] live between restarts.
+///
+/// The db keeps every table in memory and hands each mutation to the storage
+/// before applying it, so a backend only has to load what it stored and make
+/// writes durable.
+pub(crate) trait Storage: Send + Sync {
+    /// Reads back every stored table along with any mutations logged since
+    /// they were last written out, which the db replays on top.
+    fn scan(&mut self) -> BoxFuture<'_, DbResult<Scanned>>;
+
+    /// Durably stores the mutations, all or none of them.
+    fn put<'a>(&'a mut self, entries: &'a [WalEntry]) -> BoxFuture<'a, DbResult<()>>;
+
+    /// Compacts whatever `put` accumulated, given the current tables.
+    fn checkpoint<'a>(&'a mut self, tables: &'a HashMap<String, TableData>) -> BoxFuture<'a, DbResult<()>>;
+
+    /// Discards everything stored and durably stores `tables` instead, all or
+    /// nothing.
+    fn replace<'a>(&'a mut self, tables: &'a HashMap<String, TableData>) -> BoxFuture<'a, DbResult<()>>;
+
+    /// Hands over the metrics to count the bytes records are encoded into and
+    /// decoded from. Backends that encode nothing can ignore them.
+    fn set_metrics(&mut self, _metrics: SerdeMetrics) {}
+
+    /// Bytes the stored tables and log currently take up on disk.
+    fn size_on_disk(&self) -> u64 {
+        0
+    }
+}
+
+/// Opens the storage for `backend`. `compression` only applies to the file
+/// backends.
+pub(crate) async fn open(backend: StorageBackend, compression: Compression, path: String) -> DbResult<Box<dyn Storage>> {
+    Ok(match backend {
+        StorageBackend::Json => Box::new(FileStorage::open(path, FileFormat::Json, compression).await?),
+        StorageBackend::MessagePack => Box::new(FileStorage::open(path, FileFormat::MessagePack, compression).await?),
+        StorageBackend::Sled => Box::new(SledStorage::open(path).await?)
+    })
+}
\ No newline at end of file
@@ -0,0 +1,481 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use super::codec::CodecKind;
+use crate::db::metrics::SerdeMetrics;
+use super::{Scanned, Storage};
+use crate::db::wal::WalEntry;
+use crate::db::{DbResult, TableData};
+
+const LOCK_RETRIES: u32 = 50;
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Table bookkeeping stored under `meta/{table}`, next to its records under
+/// `data/{table}/{id}` and their expiry times under `expires/{table}/{id}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct TableMeta {
+    next_id: u32,
+    schema_version: u32,
+    #[serde(default)]
+    codec: CodecKind
+}
+
+/// sled reports a held lock file as an io error of kind `Other`, while the
+/// other io errors of opening carry the kind of the operation that failed.
+fn is_lock_held(err: &sled::Error) -> bool {
+    matches!(err, sled::Error::Io(err) if err.kind() == io::ErrorKind::Other)
+}
+
+fn meta_key(table: &str) -> Vec<u8> {
+    format!("meta/{}", table).into_bytes()
+}
+
+fn expires_prefix(table: &str) -> Vec<u8> {
+    format!("expires/{}/", table).into_bytes()
+}
+
+fn expires_key(table: &str, id: u32) -> Vec<u8> {
+    format!("expires/{}/{:010}", table, id).into_bytes()
+}
+
+fn data_prefix(table: &str) -> Vec<u8> {
+    format!("data/{}/", table).into_bytes()
+}
+
+/// Zero padded so keys sort in id order.
+fn data_key(table: &str, id: u32) -> Vec<u8> {
+    format!("data/{}/{:010}", table, id).into_bytes()
+}
+
+/// Reads and decodes the records of one table. Tables are separate key
+/// ranges, so [`SledStorage`] loads them in parallel on the blocking pool.
+fn load_table(db: &sled::Db, table_name: &str, meta: TableMeta, metrics: &SerdeMetrics) -> DbResult<TableData> {
+    let prefix = data_prefix(table_name);
+    let mut data = BTreeMap::new();
+    let mut bytes = 0;
+
+    for record in db.scan_prefix(&prefix) {
+        let (key, value) = record?;
+        let id: u32 = String::from_utf8_lossy(&key[prefix.len()..]).parse()?;
+        bytes += value.len();
+        data.insert(id, meta.codec.codec().decode(&value)?);
+    }
+
+    metrics.record_deserialize(table_name, data.len(), bytes);
+
+    let prefix = expires_prefix(table_name);
+    let mut expires_at = BTreeMap::new();
+
+    for expiry in db.scan_prefix(&prefix) {
+        let (key, value) = expiry?;
+        let id: u32 = String::from_utf8_lossy(&key[prefix.len()..]).parse()?;
+        expires_at.insert(id, serde_json::from_slice(&value)?);
+    }
+
+    Ok(TableData {
+        next_id: meta.next_id,
+        schema_version: meta.schema_version,
+        codec: meta.codec,
+        data,
+        expires_at
+    })
+}
+
+/// Stores each record as its own key in a sled database, so a mutation only
+/// writes the records it touches and there is nothing to compact.
+pub struct SledStorage {
+    db: sled::Db,
+    metrics: SerdeMetrics
+}
+
+impl SledStorage {
+    /// Every batch is flushed explicitly, so sled's background flusher is
+    /// turned off. It would otherwise keep the directory locked for a moment
+    /// after the storage is dropped, failing an immediate reopen. sled still
+    /// releases the lock file through deferred reclamation, so a held lock is
+    /// retried for a short while before giving up.
+    pub async fn open(path: String) -> DbResult<Self> {
+        let config = sled::Config::new()
+            .path(path)
+            .flush_every_ms(None);
+
+        let mut attempts = 0;
+        let db = loop {
+            let config = config.clone();
+
+            match tokio::task::spawn_blocking(move || config.open()).await? {
+                Err(err) if is_lock_held(&err) && attempts < LOCK_RETRIES => {
+                    attempts += 1;
+                    tokio::time::sleep(LOCK_RETRY_DELAY).await;
+                },
+                result => break result?
+            }
+        };
+
+        Ok(Self { db, metrics: SerdeMetrics::default() })
+    }
+
+    fn meta(&self, staged: &BTreeMap<Vec<u8>, Option<Vec<u8>>>, table: &str) -> DbResult<Option<TableMeta>> {
+        let key = meta_key(table);
+
+        let stored = match staged.get(&key) {
+            Some(value) => value.clone(),
+            None => self.db.get(&key)?.map(|value| value.to_vec())
+        };
+
+        Ok(match stored {
+            Some(value) => Some(serde_json::from_slice(&value)?),
+            None => None
+        })
+    }
+
+    /// Removes every record of `table`, including ones staged earlier in the batch.
+    fn stage_clear(&self, staged: &mut BTreeMap<Vec<u8>, Option<Vec<u8>>>, table: &str) -> DbResult<()> {
+        for prefix in [data_prefix(table), expires_prefix(table)] {
+            for key in self.db.scan_prefix(&prefix).keys() {
+                staged.insert(key?.to_vec(), None);
+            }
+
+            for (key, value) in staged.iter_mut() {
+                if key.starts_with(&prefix) {
+                    *value = None;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves every record and expiry time of `from` under the keys of `to`,
+    /// including ones staged earlier in the batch.
+    fn stage_rename(&self, staged: &mut BTreeMap<Vec<u8>, Option<Vec<u8>>>, from: &str, to: &str) -> DbResult<()> {
+        self.stage_clear(staged, to)?;
+
+        for (from_prefix, to_prefix) in [(data_prefix(from), data_prefix(to)), (expires_prefix(from), expires_prefix(to))] {
+            let mut current: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+
+            for item in self.db.scan_prefix(&from_prefix) {
+                let (key, value) = item?;
+                current.insert(key.to_vec(), Some(value.to_vec()));
+            }
+
+            current.extend(staged.range(from_prefix.clone()..).take_while(|(key, _)| key.starts_with(&from_prefix)).map(|(key, value)| (key.clone(), value.clone())));
+
+            for (key, value) in current {
+                if let Some(value) = value {
+                    staged.insert([to_prefix.as_slice(), &key[from_prefix.len()..]].concat(), Some(value));
+                }
+                staged.insert(key, None);
+            }
+        }
+
+        if let Some(meta) = self.meta(staged, from)? {
+            staged.insert(meta_key(to), Some(serde_json::to_vec(&meta)?));
+            staged.insert(meta_key(from), None);
+        }
+
+        Ok(())
+    }
+
+    /// Re-encodes every record of `table`, including ones staged earlier in
+    /// the batch, from one codec to another.
+    fn stage_reencode(&self, staged: &mut BTreeMap<Vec<u8>, Option<Vec<u8>>>, table: &str, from: CodecKind, to: CodecKind) -> DbResult<()> {
+        let prefix = data_prefix(table);
+
+        for item in self.db.scan_prefix(&prefix) {
+            let (key, value) = item?;
+            staged.entry(key.to_vec()).or_insert(Some(value.to_vec()));
+        }
+
+        for (key, value) in staged.iter_mut() {
+            if let (true, Some(bytes)) = (key.starts_with(&prefix), value) {
+                *bytes = to.codec().encode(&from.codec().decode(bytes)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turns the mutations into the key writes they amount to.
+    fn stage(&self, entries: &[WalEntry]) -> DbResult<BTreeMap<Vec<u8>, Option<Vec<u8>>>> {
+        let mut staged: BTreeMap<Vec<u8>, Option<Vec<u8>>> = BTreeMap::new();
+
+        for entry in entries {
+            match entry {
+                WalEntry::AddTable { table, schema_version } => {
+                    self.stage_clear(&mut staged, table)?;
+
+                    let meta = TableMeta { next_id: 1, schema_version: *schema_version, codec: CodecKind::default() };
+                    staged.insert(meta_key(table), Some(serde_json::to_vec(&meta)?));
+                },
+                WalEntry::NextId { table, next_id } => {
+                    if let Some(mut meta) = self.meta(&staged, table)? {
+                        meta.next_id = *next_id;
+                        staged.insert(meta_key(table), Some(serde_json::to_vec(&meta)?));
+                    }
+                },
+                WalEntry::SchemaVersion { table, schema_version } => {
+                    if let Some(mut meta) = self.meta(&staged, table)? {
+                        meta.schema_version = *schema_version;
+                        staged.insert(meta_key(table), Some(serde_json::to_vec(&meta)?));
+                    }
+                },
+                WalEntry::Codec { table, codec } => {
+                    if let Some(mut meta) = self.meta(&staged, table)? {
+                        self.stage_reencode(&mut staged, table, meta.codec, *codec)?;
+                        meta.codec = *codec;
+                        staged.insert(meta_key(table), Some(serde_json::to_vec(&meta)?));
+                    }
+                },
+                WalEntry::Upsert { table, id, data } => {
+                    let codec = self.meta(&staged, table)?.map(|meta| meta.codec).unwrap_or_default();
+                    let encoded = codec.codec().encode(data)?;
+                    self.metrics.record_serialize(table, 1, encoded.len());
+                    staged.insert(data_key(table, *id), Some(encoded));
+                    staged.insert(expires_key(table, *id), None);
+                },
+                WalEntry::Expire { table, id, expires_at } => {
+                    staged.insert(expires_key(table, *id), Some(serde_json::to_vec(expires_at)?));
+                },
+                WalEntry::Delete { table, id } => {
+                    staged.insert(data_key(table, *id), None);
+                    staged.insert(expires_key(table, *id), None);
+                },
+                WalEntry::Clear { table } => {
+                    self.stage_clear(&mut staged, table)?;
+                },
+                WalEntry::DropTable { table } => {
+                    self.stage_clear(&mut staged, table)?;
+                    staged.insert(meta_key(table), None);
+                },
+                WalEntry::RenameTable { table, to } => {
+                    self.stage_rename(&mut staged, table, to)?;
+                }
+            }
+        }
+
+        Ok(staged)
+    }
+}
+
+impl Storage for SledStorage {
+    fn scan(&mut self) -> BoxFuture<'_, DbResult<Scanned>> {
+        async move {
+            let mut loads = vec![];
+
+            for item in self.db.scan_prefix("meta/") {
+                let (key, value) = item?;
+                let table_name = String::from_utf8_lossy(&key["meta/".len()..]).to_string();
+                let meta: TableMeta = serde_json::from_slice(&value)?;
+                let db = self.db.clone();
+                let metrics = self.metrics.clone();
+
+                loads.push(tokio::task::spawn_blocking(move || {
+                    load_table(&db, &table_name, meta, &metrics)
+                        .map(|table| (table_name, table))
+                }));
+            }
+
+            let mut tables: HashMap<String, TableData> = HashMap::new();
+
+            for load in futures::future::join_all(loads).await {
+                let (table_name, table) = load??;
+                tables.insert(table_name, table);
+            }
+
+            Ok((tables, vec![]))
+        }.boxed()
+    }
+
+    fn put<'a>(&'a mut self, entries: &'a [WalEntry]) -> BoxFuture<'a, DbResult<()>> {
+        async move {
+            let mut batch = sled::Batch::default();
+
+            for (key, value) in self.stage(entries)? {
+                match value {
+                    Some(value) => batch.insert(key, value),
+                    None => batch.remove(key)
+                }
+            }
+
+            self.db.apply_batch(batch)?;
+            self.db.flush_async().await?;
+
+            Ok(())
+        }.boxed()
+    }
+
+    fn checkpoint<'a>(&'a mut self, _tables: &'a HashMap<String, TableData>) -> BoxFuture<'a, DbResult<()>> {
+        async move {
+            self.db.flush_async().await?;
+
+            Ok(())
+        }.boxed()
+    }
+
+    fn replace<'a>(&'a mut self, tables: &'a HashMap<String, TableData>) -> BoxFuture<'a, DbResult<()>> {
+        async move {
+            let mut batch = sled::Batch::default();
+
+            for key in self.db.iter().keys() {
+                batch.remove(key?);
+            }
+
+            for (table_name, table) in tables {
+                let meta = TableMeta { next_id: table.next_id, schema_version: table.schema_version, codec: table.codec };
+                batch.insert(meta_key(table_name), serde_json::to_vec(&meta)?);
+
+                let mut bytes = 0;
+
+                for (id, record) in &table.data {
+                    let encoded = table.codec.codec().encode(record)?;
+                    bytes += encoded.len();
+                    batch.insert(data_key(table_name, *id), encoded);
+                }
+
+                self.metrics.record_serialize(table_name, table.data.len(), bytes);
+
+                for (id, expires_at) in &table.expires_at {
+                    batch.insert(expires_key(table_name, *id), serde_json::to_vec(expires_at)?);
+                }
+            }
+
+            self.db.apply_batch(batch)?;
+            self.db.flush_async().await?;
+
+            Ok(())
+        }.boxed()
+    }
+
+    fn set_metrics(&mut self, metrics: SerdeMetrics) {
+        self.metrics = metrics;
+    }
+
+    fn size_on_disk(&self) -> u64 {
+        self.db.size_on_disk().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_clear_drops_records_staged_in_same_batch() {
+        async_run_with_file_create_teardown(|file_name| {
+            let path = format!("{}-sled", file_name);
+            async move {
+                let mut storage = SledStorage::open(path).await.unwrap();
+                let table = "sample".to_string();
+
+                storage.put(&[
+                    WalEntry::AddTable { table: table.clone(), schema_version: 0 },
+                    WalEntry::Upsert { table: table.clone(), id: 1, data: json!({"id": 1}) },
+                    WalEntry::Clear { table: table.clone() },
+                    WalEntry::Upsert { table: table.clone(), id: 2, data: json!({"id": 2}) },
+                    WalEntry::NextId { table: table.clone(), next_id: 3 }
+                ]).await.unwrap();
+
+                let (tables, entries) = storage.scan().await.unwrap();
+                let stored = &tables[&table];
+
+                assert!(entries.is_empty());
+                assert_eq!(stored.next_id, 3);
+                assert_eq!(stored.data.keys().copied().collect::<Vec<u32>>(), vec![2]);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_scan_loads_every_table() {
+        async_run_with_file_create_teardown(|file_name| {
+            let path = format!("{}-sled", file_name);
+            async move {
+                let mut storage = SledStorage::open(path).await.unwrap();
+                let names = ["a", "b", "c", "d"];
+
+                for (i, name) in names.iter().enumerate() {
+                    let records: Vec<WalEntry> = (1..=i as u32 + 1)
+                        .map(|id| WalEntry::Upsert { table: name.to_string(), id, data: json!({"id": id}) })
+                        .collect();
+                    storage.put(&[WalEntry::AddTable { table: name.to_string(), schema_version: 0 }]).await.unwrap();
+                    storage.put(&records).await.unwrap();
+                }
+
+                let (tables, _) = storage.scan().await.unwrap();
+
+                for (i, name) in names.iter().enumerate() {
+                    assert_eq!(tables[*name].data.len(), i + 1);
+                }
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_replace_discards_previous_tables() {
+        async_run_with_file_create_teardown(|file_name| {
+            let path = format!("{}-sled", file_name);
+            async move {
+                let mut storage = SledStorage::open(path).await.unwrap();
+
+                storage.put(&[
+                    WalEntry::AddTable { table: "old".to_string(), schema_version: 0 },
+                    WalEntry::Upsert { table: "old".to_string(), id: 1, data: json!({"id": 1}) }
+                ]).await.unwrap();
+
+                let replacement = TableData {
+                    next_id: 2,
+                    schema_version: 1,
+                    codec: CodecKind::MessagePack,
+                    data: BTreeMap::from([(1, json!({"id": 1, "value": "new"}))]),
+                    expires_at: BTreeMap::new()
+                };
+                storage.replace(&HashMap::from([("new".to_string(), replacement)])).await.unwrap();
+
+                let (tables, _) = storage.scan().await.unwrap();
+                assert_eq!(tables.keys().collect::<Vec<&String>>(), vec!["new"]);
+                assert_eq!(tables["new"].schema_version, 1);
+                assert_eq!(tables["new"].codec, CodecKind::MessagePack);
+                assert_eq!(tables["new"].data[&1], json!({"id": 1, "value": "new"}));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_codec_reencodes_records() {
+        async_run_with_file_create_teardown(|file_name| {
+            let path = format!("{}-sled", file_name);
+            async move {
+                let mut storage = SledStorage::open(path).await.unwrap();
+
+                storage.put(&[
+                    WalEntry::AddTable { table: "sample".to_string(), schema_version: 0 },
+                    WalEntry::Upsert { table: "sample".to_string(), id: 1, data: json!({"id": 1}) }
+                ]).await.unwrap();
+                storage.put(&[
+                    WalEntry::Upsert { table: "sample".to_string(), id: 2, data: json!({"id": 2}) },
+                    WalEntry::Codec { table: "sample".to_string(), codec: CodecKind::MessagePack },
+                    WalEntry::Upsert { table: "sample".to_string(), id: 3, data: json!({"id": 3}) }
+                ]).await.unwrap();
+
+                for id in 1..=3 {
+                    let stored = storage.db.get(data_key("sample", id)).unwrap().unwrap();
+                    assert_eq!(rmp_serde::from_slice::<serde_json::Value>(&stored).unwrap(), json!({"id": id}));
+                }
+
+                let (tables, _) = storage.scan().await.unwrap();
+                assert_eq!(tables["sample"].codec, CodecKind::MessagePack);
+                assert_eq!(tables["sample"].data.len(), 3);
+            }
+        }).await;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::query::Direction;
+use super::{Db, DbResult, Key, Page, UpsertResult};
+
+
+/// Typed view over a single table, returned by [`Db::table`] for reads and
+/// [`Db::table_mut`] for writes, so callers name the table and record type once.
+pub struct Table<D, T> {
+    db: D,
+    name: String,
+    marker: PhantomData<T>
+}
+
+impl<D, T> Table<D, T> {
+    pub(super) fn new(db: D, name: &str) -> Self {
+        Self { db, name: name.to_string(), marker: PhantomData }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<D, T> Table<D, T>
+    where D: Deref<Target = Db>,
+        T: DeserializeOwned
+{
+    pub fn get(&self, key: impl Into<Key>) -> Option<T> {
+        self.db.find_by_id(self.name.clone(), key)
+    }
+
+    pub fn list(&self) -> Option<Vec<T>> {
+        self.db.find_all(self.name.clone())
+    }
+
+    pub fn list_with_deleted(&self) -> Option<Vec<T>> {
+        self.db.find_all_with_deleted(self.name.clone())
+    }
+
+    pub fn list_sorted(&self, field: String, direction: Direction) -> Option<Vec<T>> {
+        self.db.find_all_sorted(self.name.clone(), field, direction)
+    }
+
+    pub fn page(&self, offset: usize, limit: usize) -> Option<Page<T>> {
+        self.db.find_page(self.name.clone(), offset, limit)
+    }
+
+    pub fn find_by_value(&self, column: &str, value: String) -> Option<Vec<T>> {
+        self.db.find_by_value(self.name.clone(), column.to_string(), value)
+    }
+
+    pub fn find_in_range(&self, column: &str, min: serde_json::Number, max: serde_json::Number) -> Option<Vec<T>> {
+        self.db.find_in_range(self.name.clone(), column.to_string(), min, max)
+    }
+
+    pub fn search(&self, query: &str) -> Option<Vec<T>> {
+        self.db.search(self.name.clone(), query)
+    }
+
+    pub fn find_where<F: Fn(&T) -> bool>(&self, predicate: F) -> Option<Vec<T>> {
+        self.db.find_where(self.name.clone(), predicate)
+    }
+
+    pub fn exists(&self, key: impl Into<Key>) -> bool {
+        self.db.exists(self.name.clone(), key)
+    }
+
+    pub fn count(&self) -> Option<usize> {
+        self.db.count(self.name.clone())
+    }
+
+    pub fn count_by_value(&self, column: &str, value: String) -> Option<usize> {
+        self.db.count_by_value(self.name.clone(), column.to_string(), value)
+    }
+}
+
+impl<D, T> Table<D, T>
+    where D: DerefMut<Target = Db>,
+        T: Serialize + DeserializeOwned + Clone
+{
+    /// Inserts the record built from the next id of the table.
+    pub async fn insert<F>(&mut self, build: F) -> DbResult<Option<T>>
+        where F: FnOnce(u32) -> T
+    {
+        let Some(id) = self.db.get_increment_last_id(self.name.clone()).await? else {
+            return Ok(None)
+        };
+
+        self.db.insert_or_update(self.name.clone(), id, build(id)).await
+    }
+
+    /// Inserts the record built from a new key of the table, see [`Db::new_key`].
+    pub async fn insert_keyed<F>(&mut self, build: F) -> DbResult<Option<T>>
+        where F: FnOnce(Key) -> T
+    {
+        let Some(key) = self.db.new_key(self.name.clone()).await? else {
+            return Ok(None)
+        };
+
+        self.db.insert_or_update(self.name.clone(), key, build(key)).await
+    }
+
+    /// Replaces an existing record, returning `None` if there is none with `id`.
+    pub async fn update(&mut self, key: impl Into<Key>, data: T) -> DbResult<Option<T>> {
+        let key = key.into();
+
+        if !self.exists(key) {
+            return Ok(None)
+        }
+
+        self.db.insert_or_update(self.name.clone(), key, data).await
+    }
+
+    /// Replaces an existing record if it is still at `expected_version`, see
+    /// [`Db::insert_or_update_versioned`].
+    pub async fn update_versioned(&mut self, key: impl Into<Key>, data: T, expected_version: u64) -> DbResult<Option<T>> {
+        self.db.insert_or_update_versioned(self.name.clone(), key, data, expected_version).await
+    }
+
+    /// Stores the record under `id` whether or not it exists, see [`Db::upsert`].
+    pub async fn upsert(&mut self, id: u32, data: T) -> DbResult<Option<UpsertResult<T>>> {
+        self.db.upsert(self.name.clone(), id, data).await
+    }
+
+    /// Merges `partial` into the record, see [`Db::update_fields`].
+    pub async fn update_fields(&mut self, id: u32, partial: serde_json::Value) -> DbResult<Option<T>> {
+        let updated = self.db.update_fields(self.name.clone(), id, partial).await?;
+
+        Ok(updated.map(|x| serde_json::from_value::<T>(x).unwrap()))
+    }
+
+    /// Transforms every record matching `predicate`, see [`Db::update_where`].
+    pub async fn update_where<P, U>(&mut self, predicate: P, update: U) -> DbResult<Option<usize>>
+        where P: Fn(&T) -> bool,
+            U: FnMut(T) -> T
+    {
+        self.db.update_where(self.name.clone(), predicate, update).await
+    }
+
+    pub async fn delete(&mut self, key: impl Into<Key>) -> DbResult<Option<T>> {
+        let deleted = self.db.delete_by_id(self.name.clone(), key).await?;
+
+        Ok(deleted.map(|x| serde_json::from_value::<T>(x).unwrap()))
+    }
+
+    pub async fn delete_where(&mut self, column: &str, value: String) -> DbResult<Option<usize>> {
+        self.db.delete_where(self.name.clone(), column.to_string(), value).await
+    }
+
+    pub async fn soft_delete(&mut self, id: u32) -> DbResult<Option<T>> {
+        let deleted = self.db.soft_delete_by_id(self.name.clone(), id).await?;
+
+        Ok(deleted.map(|x| serde_json::from_value::<T>(x).unwrap()))
+    }
+
+    pub async fn restore(&mut self, id: u32) -> DbResult<Option<T>> {
+        let restored = self.db.restore_by_id(self.name.clone(), id).await?;
+
+        Ok(restored.map(|x| serde_json::from_value::<T>(x).unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    const TABLE_NAME: &str = "sample";
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Sample {
+        id: u32,
+        value: String
+    }
+
+    #[tokio::test]
+    async fn test_crud() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = Db::init(file_name).await.unwrap();
+                db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+
+                let mut table = db.table_mut::<Sample>(TABLE_NAME);
+                let inserted = table.insert(|id| Sample { id, value: "sample".to_string() }).await.unwrap().unwrap();
+                assert_eq!(table.get(inserted.id), Some(inserted.clone()));
+
+                let updated = Sample { id: inserted.id, value: "updated".to_string() };
+                assert_eq!(table.update(inserted.id, updated.clone()).await.unwrap(), Some(updated.clone()));
+                assert_eq!(table.list(), Some(vec![updated.clone()]));
+
+                let missing = Sample { id: 99, value: "missing".to_string() };
+                assert!(table.update(99, missing).await.unwrap().is_none());
+
+                assert_eq!(table.soft_delete(inserted.id).await.unwrap(), Some(updated.clone()));
+                assert_eq!(table.list(), Some(vec![]));
+                assert_eq!(table.list_with_deleted(), Some(vec![updated.clone()]));
+                assert_eq!(table.restore(inserted.id).await.unwrap(), Some(updated.clone()));
+
+                assert_eq!(table.delete(inserted.id).await.unwrap(), Some(updated));
+                assert_eq!(db.table::<Sample>(TABLE_NAME).list(), Some(vec![]));
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_find_where() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = Db::init(file_name).await.unwrap();
+                db.add_table(TABLE_NAME.to_string(), true).await.unwrap();
+
+                let mut table = db.table_mut::<Sample>(TABLE_NAME);
+                for value in ["apple", "banana", "avocado"] {
+                    table.insert(|id| Sample { id, value: value.to_string() }).await.unwrap();
+                }
+
+                let found = table.find_where(|x| x.value.starts_with('a') && x.id > 1).unwrap();
+                assert_eq!(found, vec![Sample { id: 3, value: "avocado".to_string() }]);
+
+                assert!(db.table::<Sample>("missing").find_where(|_| true).is_none());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_missing_table() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut db = Db::init(file_name).await.unwrap();
+
+                let inserted = db.table_mut::<Sample>("missing")
+                    .insert(|id| Sample { id, value: "sample".to_string() })
+                    .await
+                    .unwrap();
+
+                assert!(inserted.is_none());
+                assert!(db.table::<Sample>("missing").list().is_none());
+            }
+        }).await;
+    }
+}
\ No newline at end of file
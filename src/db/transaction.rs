@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::index::Index;
+use super::wal::WalEntry;
+use super::{Db, DbError, DbResult, TableData};
+
+
+/// State of a table and its indexes before a transaction first touched it.
+pub(super) type TableSnapshot = (Option<TableData>, Option<Vec<Index>>);
+
+/// Buffered set of mutations created by [`Db::transaction`].
+///
+/// Mutations are applied to the in-memory tables as they are made so later
+/// reads see them, while the state of each touched table is kept aside to
+/// roll back to.
+pub struct Transaction<'a> {
+    db: &'a mut Db,
+    entries: Vec<WalEntry>,
+    originals: HashMap<String, TableSnapshot>
+}
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(db: &'a mut Db) -> Self {
+        Self {
+            db,
+            entries: vec![],
+            originals: HashMap::new()
+        }
+    }
+
+    pub(super) fn into_parts(self) -> (Vec<WalEntry>, HashMap<String, TableSnapshot>) {
+        (self.entries, self.originals)
+    }
+
+    fn stage(&mut self, table_name: &str, entry: WalEntry) {
+        if !self.originals.contains_key(table_name) {
+            self.originals.insert(
+                table_name.to_string(),
+                (self.db.tables.get(table_name).cloned(), self.db.indexes.get(table_name).cloned())
+            );
+        }
+
+        self.db.apply(entry.clone());
+        self.entries.push(entry);
+    }
+
+    fn table(&self, table_name: &str) -> Result<&TableData, DbError> {
+        self.db
+            .tables
+            .get(table_name)
+            .ok_or(DbError::TableNotFound(table_name.to_string()))
+    }
+
+    pub fn find_by_id<T>(&self, table_name: String, id: u32) -> Option<T>
+        where T: DeserializeOwned
+    {
+        self.db.find_by_id(table_name, id)
+    }
+
+    pub fn find_by_value<T>(&self, table_name: String, column: String, value: String) -> Option<Vec<T>>
+        where T: DeserializeOwned
+    {
+        self.db.find_by_value(table_name, column, value)
+    }
+
+    pub fn get_increment_last_id(&mut self, table_name: String) -> DbResult<u32> {
+        let id = self.table(&table_name)?.next_id;
+        self.stage(&table_name.clone(), WalEntry::NextId { table: table_name, next_id: id + 1 });
+
+        Ok(id)
+    }
+
+    pub fn insert_or_update<T>(&mut self, table_name: String, id: u32, data: T) -> DbResult<T>
+        where T: Serialize + DeserializeOwned
+    {
+        self.table(&table_name)?;
+
+        let mut value = serde_json::to_value(&data)?;
+        self.db.metrics.record_serialize(&table_name, 1, 0);
+        self.db.stamp(&table_name, id, &mut value);
+        self.db.validate(&table_name, id, &value)?;
+        self.db.check_unique(&table_name, id, &value)?;
+        self.db.metrics.record_deserialize(&table_name, 1, 0);
+        self.stage(&table_name.clone(), WalEntry::Upsert { table: table_name, id, data: value.clone() });
+
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub fn delete_by_id(&mut self, table_name: String, id: u32) -> DbResult<Option<Value>> {
+        let Some(data) = self.table(&table_name)?.data.get(&id).cloned() else {
+            return Ok(None)
+        };
+
+        for entry in self.db.plan_delete(&table_name, id)? {
+            let table_name = entry.table().to_string();
+            self.stage(&table_name, entry);
+        }
+
+        Ok(Some(data))
+    }
+
+    /// Removes every record of the table, along with whatever cascades from
+    /// them, failing if a restricting relation points at any of them.
+    pub fn delete_all(&mut self, table_name: String) -> DbResult<()> {
+        let targets = self.table(&table_name)?.data.keys().map(|id| (table_name.clone(), *id)).collect();
+
+        for entry in self.db.plan_deletes(targets)? {
+            let entry_table = entry.table().to_string();
+
+            if entry_table != table_name {
+                self.stage(&entry_table, entry);
+            }
+        }
+
+        self.stage(&table_name.clone(), WalEntry::Clear { table: table_name });
+
+        Ok(())
+    }
+}
\ No newline at end of file
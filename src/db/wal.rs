@@ -0,0 +1,259 @@
+use std::io;
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use super::storage::codec::CodecKind;
+use super::storage::file::Compression;
+
+
+/// A single mutation recorded in the write-ahead log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalEntry {
+    AddTable {
+        table: String,
+        #[serde(default)]
+        schema_version: u32
+    },
+    NextId { table: String, next_id: u32 },
+    SchemaVersion { table: String, schema_version: u32 },
+    Codec { table: String, codec: CodecKind },
+    Upsert { table: String, id: u32, data: Value },
+    /// Marks a record to be removed by [`super::Db::purge_expired`] once the
+    /// unix timestamp `expires_at` has passed.
+    Expire { table: String, id: u32, expires_at: i64 },
+    Delete { table: String, id: u32 },
+    Clear { table: String },
+    DropTable { table: String },
+    /// Moves the table with its records and `next_id` over to the name `to`.
+    RenameTable { table: String, to: String }
+}
+
+impl WalEntry {
+    pub fn table(&self) -> &str {
+        match self {
+            WalEntry::AddTable { table, .. }
+            | WalEntry::NextId { table, .. }
+            | WalEntry::SchemaVersion { table, .. }
+            | WalEntry::Codec { table, .. }
+            | WalEntry::Upsert { table, .. }
+            | WalEntry::Expire { table, .. }
+            | WalEntry::Delete { table, .. }
+            | WalEntry::Clear { table }
+            | WalEntry::DropTable { table }
+            | WalEntry::RenameTable { table, .. } => table
+        }
+    }
+}
+
+/// Parses newline terminated entries up to the first torn one, returning them
+/// along with the number of bytes they took up. Only the last line may be torn.
+fn read_lines(raw: &[u8]) -> io::Result<(Vec<WalEntry>, usize)> {
+    let mut entries = Vec::new();
+    let mut valid_len = 0;
+
+    while let Some(end) = raw[valid_len..].iter().position(|x| *x == b'\n') {
+        let line = &raw[valid_len..valid_len + end];
+
+        match serde_json::from_slice::<WalEntry>(line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) if valid_len + end + 1 == raw.len() => break,
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+
+        valid_len += end + 1;
+    }
+
+    Ok((entries, valid_len))
+}
+
+/// Decodes appended batches one frame at a time, stopping at a frame cut
+/// short by a crash. Returns the entries and the bytes of the complete frames.
+fn read_frames(raw: &[u8]) -> io::Result<(Vec<WalEntry>, usize)> {
+    let mut entries = Vec::new();
+    let mut valid_len = 0;
+
+    while valid_len < raw.len() {
+        let (decoded, frame_len) = match Compression::decode_frame(&raw[valid_len..]) {
+            Ok(frame) => frame,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err)
+        };
+
+        let (frame_entries, lines_len) = read_lines(&decoded)?;
+
+        if lines_len < decoded.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Torn entry inside a complete frame"))
+        }
+
+        entries.extend(frame_entries);
+        valid_len += frame_len;
+    }
+
+    Ok((entries, valid_len))
+}
+
+/// Append-only log of [`WalEntry`] records, one JSON document per line. With
+/// compression each appended batch is its own gzip member or zstd frame, so
+/// the log stays appendable and decodes as one stream.
+pub struct Wal {
+    file: File,
+    compression: Compression
+}
+
+impl Wal {
+    pub async fn open(file_name: String, compression: Compression) -> io::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(file_name)
+            .await?;
+
+        Ok(Self { file, compression })
+    }
+
+    /// Reads back every complete entry. A trailing line or frame that fails to
+    /// parse is treated as a torn write from a crash and cut off, so the next
+    /// append starts on a clean boundary instead of being glued onto it.
+    pub async fn read_entries(&mut self) -> io::Result<Vec<WalEntry>> {
+        let mut raw = Vec::new();
+        self.file.rewind().await?;
+        self.file.read_to_end(&mut raw).await?;
+
+        let (entries, valid_len) = match Compression::detect(&raw).is_none() {
+            true => read_lines(&raw)?,
+            false => read_frames(&raw)?
+        };
+
+        if valid_len < raw.len() {
+            self.file.set_len(valid_len as u64).await?;
+            self.file.sync_data().await?;
+        }
+
+        Ok(entries)
+    }
+
+    pub async fn append(&mut self, entry: &WalEntry) -> io::Result<()> {
+        self.append_all(std::slice::from_ref(entry)).await
+    }
+
+    /// Appends the entries with a single sync, so a batch costs one disk flush.
+    pub async fn append_all(&mut self, entries: &[WalEntry]) -> io::Result<()> {
+        let mut lines = String::new();
+
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+
+        let contents = self.compression.compress(lines.into_bytes())?;
+        self.file.write_all(&contents).await?;
+        self.file.sync_data().await
+    }
+
+    pub async fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0).await?;
+        self.file.sync_data().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_append_and_read() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut wal = Wal::open(file_name, Compression::None).await.unwrap();
+                let entry = WalEntry::Upsert { table: "sample".to_string(), id: 1, data: json!({"id": 1}) };
+                wal.append(&entry).await.unwrap();
+
+                let entries = wal.read_entries().await.unwrap();
+
+                assert_eq!(entries, vec![entry]);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_ignores_torn_last_entry() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let entry = WalEntry::Delete { table: "sample".to_string(), id: 1 };
+                let contents = format!("{}\n{{\"op\":\"ups", serde_json::to_string(&entry).unwrap());
+                tokio::fs::write(&file_name, contents).await.unwrap();
+
+                let mut wal = Wal::open(file_name.clone(), Compression::None).await.unwrap();
+                let entries = wal.read_entries().await.unwrap();
+                assert_eq!(entries, vec![entry.clone()]);
+
+                let appended = [WalEntry::Clear { table: "sample".to_string() }, WalEntry::Delete { table: "sample".to_string(), id: 2 }];
+                wal.append_all(&appended).await.unwrap();
+
+                let mut wal = Wal::open(file_name, Compression::None).await.unwrap();
+                let entries = wal.read_entries().await.unwrap();
+                assert_eq!(entries, [vec![entry], appended.to_vec()].concat());
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_compressed_batches() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                for compression in [Compression::Gzip { level: 6 }, Compression::Zstd { level: 3 }] {
+                    let path = format!("{}-{:?}", file_name, compression);
+                    let first = WalEntry::Delete { table: "sample".to_string(), id: 1 };
+                    let second = WalEntry::Clear { table: "sample".to_string() };
+
+                    let mut wal = Wal::open(path.clone(), compression).await.unwrap();
+                    wal.append(&first).await.unwrap();
+                    wal.append(&second).await.unwrap();
+                    assert_eq!(wal.read_entries().await.unwrap(), vec![first.clone(), second]);
+
+                    let torn = compression.compress(b"{\"op\":\"clear\",\"table\":\"sample\"}\n".to_vec()).unwrap();
+                    wal.file.write_all(&torn[..torn.len() / 2]).await.unwrap();
+
+                    let entries = wal.read_entries().await.unwrap();
+                    assert_eq!(entries.first(), Some(&first));
+                    assert_eq!(entries.len(), 2);
+
+                    let third = WalEntry::Delete { table: "sample".to_string(), id: 3 };
+                    wal.append(&third).await.unwrap();
+                    wal.append(&third).await.unwrap();
+
+                    let mut wal = Wal::open(path, compression).await.unwrap();
+                    let entries = wal.read_entries().await.unwrap();
+                    assert_eq!(entries.len(), 4);
+                    assert_eq!(entries.last(), Some(&third));
+                }
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_truncate() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async move {
+                let mut wal = Wal::open(file_name, Compression::None).await.unwrap();
+                wal.append(&WalEntry::Clear { table: "sample".to_string() }).await.unwrap();
+                wal.truncate().await.unwrap();
+
+                assert!(wal.read_entries().await.unwrap().is_empty());
+            }
+        }).await;
+    }
+}
\ No newline at end of file
@@ -0,0 +1,29 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Number of events a lagging [`super::Db::watch`] receiver can fall behind
+/// before it starts missing them.
+pub const WATCH_CAPACITY: usize = 256;
+
+/// A change applied to a watched table, sent once the write is stored.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ChangeEvent {
+    Inserted { table: String, id: u32, data: Value },
+    Updated { table: String, id: u32, data: Value },
+    Deleted { table: String, id: u32 },
+    /// The table was emptied, dropped, renamed away or replaced by a restore,
+    /// so whatever a watcher knows of it is stale.
+    Cleared { table: String }
+}
+
+impl ChangeEvent {
+    pub fn table(&self) -> &str {
+        match self {
+            ChangeEvent::Inserted { table, .. }
+            | ChangeEvent::Updated { table, .. }
+            | ChangeEvent::Deleted { table, .. }
+            | ChangeEvent::Cleared { table } => table
+        }
+    }
+}
\ No newline at end of file
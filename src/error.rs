@@ -0,0 +1,226 @@
+use poem::error::ResponseError;
+use poem::http::StatusCode;
+use poem::middleware::ReqId;
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use serde_json::{json, Value};
+
+use crate::auth::policy::Violation;
+use crate::db::DbError;
+use crate::response::GenericResponse;
+
+
+/// Errors handlers return, each with its own status and code so clients can
+/// tell failures apart. Internal errors keep their details out of the body;
+/// their causes, outermost first, are logged by [`ErrorLogMiddleware`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppError {
+    NotFound,
+    InvalidCredentials,
+    PolicyViolation(Vec<Violation>),
+    ChallengeRequired(Value),
+    InvalidPrecondition,
+    Db(DbError),
+    Internal(Vec<String>)
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound => "not_found",
+            Self::InvalidCredentials => "invalid_credentials",
+            Self::PolicyViolation(_) => "policy_violation",
+            Self::ChallengeRequired(_) => "challenge_required",
+            Self::InvalidPrecondition => "invalid_precondition",
+            Self::Db(DbError::Io(_)) => "storage_io",
+            Self::Db(DbError::Serialization(_)) => "serialization_failed",
+            Self::Db(DbError::TableNotFound(_)) => "table_not_found",
+            Self::Db(DbError::InvalidTableName(_)) => "invalid_table_name",
+            Self::Db(DbError::LockPoisoned(_)) => "lock_poisoned",
+            Self::Db(DbError::UniqueViolation { .. }) => "unique_violation",
+            Self::Db(DbError::Validation { .. }) => "invalid_record",
+            Self::Db(DbError::ForeignKeyViolation { .. }) => "foreign_key_violation",
+            Self::Db(DbError::Conflict { .. }) => "version_conflict",
+            Self::Db(DbError::MalformedSnapshot { .. }) => "malformed_snapshot",
+            Self::Db(DbError::Locked { .. }) => "storage_locked",
+            Self::Db(DbError::Corrupt { .. }) => "storage_corrupt",
+            Self::Internal(_) => "internal_error"
+        }
+    }
+
+    /// Adds a description of what was being done when an internal error
+    /// happened. Other errors are returned to the client as they are.
+    pub fn with_context(self, context: impl Into<String>) -> Self {
+        match self {
+            Self::Internal(mut causes) => {
+                causes.insert(0, context.into());
+                Self::Internal(causes)
+            },
+            err if err.status().is_server_error() => Self::Internal(vec![context.into(), err.to_string()]),
+            err => err
+        }
+    }
+
+    /// Details returned next to the code.
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            Self::PolicyViolation(violations) => Some(json!({ "violations": violations })),
+            Self::ChallengeRequired(challenge) => Some(json!({ "challenge": challenge })),
+            Self::Db(DbError::UniqueViolation { column, value, .. }) => Some(json!({ "column": column, "value": value })),
+            Self::Db(DbError::Validation { id, message, .. }) => Some(json!({ "id": id, "reason": message })),
+            Self::Db(DbError::ForeignKeyViolation { table, column, .. }) => Some(json!({ "table": table, "column": column })),
+            Self::Db(DbError::Conflict { expected, actual, .. }) => Some(json!({ "expected": expected, "actual": actual })),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Not found"),
+            Self::InvalidCredentials => write!(f, "Invalid username or password"),
+            Self::PolicyViolation(_) => write!(f, "Credentials do not meet the policy"),
+            Self::ChallengeRequired(_) => write!(f, "Too many failed attempts, solve the challenge to continue"),
+            Self::InvalidPrecondition => write!(f, "If-Match must be * or a quoted version"),
+            Self::Db(err) => write!(f, "{}", err),
+            Self::Internal(causes) => write!(f, "{}", causes.join(": "))
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl ResponseError for AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::PolicyViolation(_) | Self::Db(DbError::Validation { .. } | DbError::InvalidTableName(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::ChallengeRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            Self::InvalidPrecondition | Self::Db(DbError::Conflict { .. }) => StatusCode::PRECONDITION_FAILED,
+            Self::Db(DbError::UniqueViolation { .. } | DbError::ForeignKeyViolation { .. }) => StatusCode::CONFLICT,
+            Self::Db(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn as_response(&self) -> Response {
+        let status = self.status();
+        let message = match status.is_server_error() {
+            true => "Internal server error".to_string(),
+            false => self.to_string()
+        };
+
+        let mut data = json!({ "code": self.code() });
+
+        if let Some(Value::Object(details)) = self.details() {
+            data.as_object_mut().unwrap().extend(details);
+        }
+
+        GenericResponse::<Value>{
+            message: Some(message),
+            status_code_u16: status.as_u16(),
+            data: Some(data)
+        }.into_response()
+    }
+}
+
+impl From<DbError> for AppError {
+    fn from(value: DbError) -> Self {
+        Self::Db(value)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(value: jsonwebtoken::errors::Error) -> Self {
+        Self::Internal(vec!["Encoding token".to_string(), value.to_string()])
+    }
+}
+
+/// `anyhow`-style context for results whose error converts into [`AppError`].
+pub trait Context<T> {
+    fn context(self, context: &str) -> Result<T, AppError>;
+}
+
+impl<T, E: Into<AppError>> Context<T> for Result<T, E> {
+    fn context(self, context: &str) -> Result<T, AppError> {
+        self.map_err(|err| err.into().with_context(context))
+    }
+}
+
+/// Logs every server error with its request id and full chain of causes,
+/// which the response body leaves out. Needs poem's `RequestId` outside it.
+#[derive(Clone)]
+pub struct ErrorLogMiddleware;
+
+impl<E: Endpoint> Middleware<E> for ErrorLogMiddleware {
+    type Output = ErrorLogMiddlewareImpl<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ErrorLogMiddlewareImpl { ep }
+    }
+}
+
+pub struct ErrorLogMiddlewareImpl<E> {
+    ep: E
+}
+
+impl<E: Endpoint> Endpoint for ErrorLogMiddlewareImpl<E> {
+    type Output = E::Output;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let request_id = req.extensions().get::<ReqId>().map(ToString::to_string).unwrap_or_default();
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+
+        let result = self.ep.call(req).await;
+
+        if let Err(err) = &result {
+            if err.status().is_server_error() {
+                let causes = match err.downcast_ref::<AppError>() {
+                    Some(AppError::Internal(causes)) => causes.join("\n  caused by: "),
+                    _ => err.to_string()
+                };
+
+                println!("[{}] {} {} failed: {}", request_id, method, uri, causes);
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_db_error() {
+        let app_error = AppError::from(DbError::UniqueViolation {
+            table: "user".to_string(),
+            column: "username".to_string(),
+            value: json!("taken")
+        });
+
+        assert_eq!(app_error.status(), StatusCode::CONFLICT);
+        assert_eq!(app_error.details(), Some(json!({ "column": "username", "value": "taken" })));
+
+        let app_error = AppError::from(DbError::from(std::io::Error::other("disk full")));
+
+        assert_eq!(app_error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(app_error.code(), "storage_io");
+    }
+
+    #[test]
+    fn test_context_chain() {
+        let result: Result<(), _> = Err(DbError::Io("No space left on device".to_string()));
+        let app_error = result.context("Flushing db").context("Inserting item").unwrap_err();
+
+        assert_eq!(app_error.to_string(), "Inserting item: Flushing db: Storage io failed: No space left on device");
+
+        let response = app_error.as_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let not_found: Result<(), _> = Err(AppError::NotFound);
+        assert_eq!(not_found.context("Reading item").unwrap_err(), AppError::NotFound);
+    }
+}
\ No newline at end of file
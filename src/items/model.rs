@@ -2,19 +2,73 @@ use serde::{Serialize, Deserialize};
 use poem::{http::StatusCode, Error, FromRequest, Result};
 use serde_json::Value;
 
+use crate::db::query::Direction;
+use crate::db::Agg;
+use crate::patch::MaybeUndefined;
+
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Item {
     pub id: u32,
-    pub name: String
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>
 }
 
 impl Item {
     pub fn new(id: u32, name: String) -> Self {
-        Self { id, name }
+        Self { id, name, description: None, created_at: None, updated_at: None, version: None }
+    }
+}
+
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+pub const MAX_PAGE_LIMIT: usize = 100;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ItemListQuery {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    #[serde(alias = "sortBy")]
+    pub sort_by: Option<String>,
+    #[serde(default)]
+    pub direction: Direction,
+    /// Words every returned item must contain, see [`crate::db::Db::search`].
+    /// Matches are listed in id order and `sort_by` is ignored.
+    pub q: Option<String>
+}
+
+impl ItemListQuery {
+    pub fn offset(&self) -> usize {
+        self.offset.unwrap_or(0)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT)
     }
 }
 
+fn default_stats_column() -> String {
+    "id".to_string()
+}
+
+/// Aggregation served by `GET /items/stats`, counting items by default.
+#[derive(Serialize, Deserialize)]
+pub struct ItemStatsQuery {
+    #[serde(default = "default_stats_column")]
+    pub column: String,
+    #[serde(default)]
+    pub agg: Agg,
+    /// Aggregate per value of this column instead of over every item.
+    #[serde(alias = "groupBy")]
+    pub group_by: Option<String>
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ItemCreateBody {
     pub name: String
@@ -57,6 +111,44 @@ impl<'a> FromRequest<'a> for ItemUpdateBody {
     }
 }
 
+/// Body of `PATCH /items/:id`. Absent fields are left as they are and a
+/// `null` description clears it, while the name can only be replaced.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ItemPatchBody {
+    #[serde(default, skip_serializing_if = "MaybeUndefined::is_undefined")]
+    pub name: MaybeUndefined<String>,
+    #[serde(default, skip_serializing_if = "MaybeUndefined::is_undefined")]
+    pub description: MaybeUndefined<String>
+}
+
+impl<'a> FromRequest<'a> for ItemPatchBody {
+    async fn from_request(
+            _: &'a poem::Request,
+            body: &mut poem::RequestBody,
+        ) -> Result<Self> {
+        let body = body
+            .take()
+            .unwrap()
+            .into_json::<ItemPatchBody>()
+            .await
+            .map_err(|_| Error::from_string("Malformed body", StatusCode::BAD_REQUEST))?;
+
+        if body.name.is_null() {
+            return Err(Error::from_string("Name cannot be null", StatusCode::BAD_REQUEST))
+        }
+
+        Ok(body)
+    }
+}
+
+/// The merge patch the body amounts to, see [`MaybeUndefined`].
+impl From<ItemPatchBody> for Value {
+    fn from(value: ItemPatchBody) -> Value {
+        serde_json::to_value(value)
+            .unwrap()
+    }
+}
+
 impl From<Value> for Item {
     fn from(value: Value) -> Self {
         serde_json::from_value::<Item>(value)
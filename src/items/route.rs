@@ -1,42 +1,73 @@
-use std::sync::{Arc, Mutex};
-
 use poem::http::StatusCode;
-use poem::Error;
-use poem::{get, handler, Route, Result, error::NotFoundError};
-use poem::web::{Data, Path};
+use poem::{get, handler, IntoResponse, Request, Response, Route};
+use poem::web::{Data, Path, Query};
 use serde_json::Value;
 
-use crate::db::Db;
-use crate::items::model::{Item, ItemCreateBody, ItemUpdateBody};
-use crate::response::GenericResponse;
+use crate::auth::error::missing_mutate;
+use crate::db::{DbError, DbHandle, Page};
+use crate::error::{AppError, Context};
+use crate::items::model::{Item, ItemCreateBody, ItemListQuery, ItemPatchBody, ItemStatsQuery, ItemUpdateBody};
+use crate::naming::FieldNaming;
+use crate::response::{encoded_page_response, GenericResponse};
 
-const ITEM_TABLE_NAME: &str = "item";
+pub const ITEM_TABLE_NAME: &str = "item";
 
 #[handler]
-fn get_all_items(db: Data<&Arc<Mutex<Db>>>) -> Result<GenericResponse<Vec<Item>>> {
-    let db_ref = db
-        .lock()
-        .map_err(|_| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))
-        .expect("Getting db lock");
-    let items = db_ref
-        .find_all::<Item>(String::from(ITEM_TABLE_NAME))
-        .unwrap_or_default();
-
-    Ok(GenericResponse::<Vec<Item>>{
+async fn get_all_items(Query(query): Query<ItemListQuery>, db: Data<&DbHandle>) -> Result<Response, AppError> {
+    let db_ref = db.read().await;
+    let items = db_ref.table::<Item>(ITEM_TABLE_NAME);
+    let page = match (&query.q, &query.sort_by) {
+        (Some(q), _) => items
+            .search(q)
+            .map(|items| Page::from_items(items, query.offset(), query.limit())),
+        (None, Some(field)) => items
+            .list_sorted(field.clone(), query.direction)
+            .map(|items| Page::from_items(items, query.offset(), query.limit())),
+        // Stored encodings are in snake case, so other policies go through serde
+        (None, None) if FieldNaming::current() != FieldNaming::SnakeCase => items.page(query.offset(), query.limit()),
+        // Plain pages are served from the stored encodings, skipping serde
+        (None, None) => {
+            let page = db_ref
+                .find_page_encoded(ITEM_TABLE_NAME.to_string(), query.offset(), query.limit())
+                .unwrap_or(Page { items: vec![], total: 0, next_offset: None });
+
+            return Ok(encoded_page_response(page))
+        }
+    }
+    .unwrap_or(Page { items: vec![], total: 0, next_offset: None });
+
+    Ok(GenericResponse::<Page<Item>>{
+        message: None,
+        status_code_u16: StatusCode::OK.as_u16(),
+        data: Some(page)
+    }.into_response())
+}
+
+#[handler]
+async fn get_item_stats(Query(query): Query<ItemStatsQuery>, db: Data<&DbHandle>) -> Result<GenericResponse<Value>, AppError> {
+    let db_ref = db.read().await;
+    let stats = match query.group_by {
+        Some(group_column) => db_ref
+            .group_by(ITEM_TABLE_NAME.to_string(), group_column, query.column, query.agg)
+            .map(|groups| Value::Object(groups.into_iter().collect())),
+        None => db_ref.aggregate(ITEM_TABLE_NAME.to_string(), query.column, query.agg)
+    }
+    .ok_or(DbError::TableNotFound(ITEM_TABLE_NAME.to_string()))?;
+
+    Ok(GenericResponse::<Value>{
         message: None,
         status_code_u16: StatusCode::OK.as_u16(),
-        data: Some(items)
+        data: Some(stats)
     })
 }
 
 #[handler]
-fn get_item_by_id(Path(id): Path<u32>, db: Data<&Arc<Mutex<Db>>>) -> Result<GenericResponse<Item>> {
-    let db_ref = db
-        .lock()
-        .map_err(|_| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))
-        .expect("Getting db lock");
-    let item = db_ref.find_by_id::<Item>(String::from(ITEM_TABLE_NAME), id)
-        .ok_or(NotFoundError)?;
+async fn get_item_by_id(Path(id): Path<u32>, db: Data<&DbHandle>) -> Result<GenericResponse<Item>, AppError> {
+    let db_ref = db.read().await;
+    let item = db_ref
+        .table::<Item>(ITEM_TABLE_NAME)
+        .get(id)
+        .ok_or(AppError::NotFound)?;
 
     Ok(GenericResponse::<Item>{
         message: None,
@@ -45,21 +76,16 @@ fn get_item_by_id(Path(id): Path<u32>, db: Data<&Arc<Mutex<Db>>>) -> Result<Gene
     })
 }
 
-#[poem_grants::protect("MUTATE")]
+#[poem_grants::protect("MUTATE", error = missing_mutate)]
 #[handler]
-fn create_item(payload: ItemCreateBody, db: Data<&Arc<Mutex<Db>>>) -> Result<GenericResponse<Item>> {
-    
-    let mut db_ref = db
-        .lock()
-        .map_err(|_| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))
-        .expect("Getting db lock");
-    let id = db_ref.get_increment_last_id(ITEM_TABLE_NAME.to_string()).unwrap().unwrap();
-    let to_insert = Item::new(id, payload.name);
+async fn create_item(payload: ItemCreateBody, db: Data<&DbHandle>) -> Result<GenericResponse<Item>, AppError> {
+    let mut db_ref = db.write().await;
     let item = db_ref
-        .insert_or_update(ITEM_TABLE_NAME.to_string(), id, to_insert)
-        .unwrap()
-        .unwrap();
-        
+        .table_mut::<Item>(ITEM_TABLE_NAME)
+        .insert(|id| Item::new(id, payload.name))
+        .await
+        .context("Inserting item")?
+        .ok_or(DbError::TableNotFound(ITEM_TABLE_NAME.to_string()))?;
 
     Ok(GenericResponse::<Item>{
         message: None,
@@ -68,38 +94,71 @@ fn create_item(payload: ItemCreateBody, db: Data<&Arc<Mutex<Db>>>) -> Result<Gen
     })
 }
 
-#[poem_grants::protect("MUTATE")]
+/// Version asked for by an `If-Match` header such as `"3"`. A missing header
+/// or `*` makes the update unconditional. Any other value, weak tags
+/// included, can never match the current version and fails the precondition.
+fn if_match_version(req: &Request) -> Result<Option<u64>, AppError> {
+    let value = match req.headers().get("If-Match") {
+        Some(value) => value.to_str().map_err(|_| AppError::InvalidPrecondition)?.trim(),
+        None => return Ok(None)
+    };
+
+    if value == "*" {
+        return Ok(None)
+    }
+
+    value.trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| AppError::InvalidPrecondition)
+}
+
+#[poem_grants::protect("MUTATE", error = missing_mutate)]
 #[handler]
-fn put_item(Path(id): Path<u32>, payload: ItemUpdateBody, db: Data<&Arc<Mutex<Db>>>) -> Result<GenericResponse<Item>> {
-    let mut db_ref = db
-        .lock()
-        .map_err(|_| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))
-        .expect("Getting db lock");
-    db_ref
-        .find_by_id::<Item>(ITEM_TABLE_NAME.to_string(), id)
-        .ok_or(NotFoundError)?;
-    let to_update = Item::new(id, payload.name);
-    db_ref
-        .insert_or_update(ITEM_TABLE_NAME.to_string(), id, to_update.clone())
-        .unwrap();
+async fn put_item(req: &Request, Path(id): Path<u32>, payload: ItemUpdateBody, db: Data<&DbHandle>) -> Result<GenericResponse<Item>, AppError> {
+    let mut db_ref = db.write().await;
+    let mut items = db_ref.table_mut::<Item>(ITEM_TABLE_NAME);
+    let item = match if_match_version(req)? {
+        Some(version) => items.update_versioned(id, Item::new(id, payload.name), version).await,
+        None => items.update(id, Item::new(id, payload.name)).await
+    }
+    .context("Updating item")?
+    .ok_or(AppError::NotFound)?;
 
     Ok(GenericResponse::<Item>{
         message: None,
         status_code_u16: StatusCode::OK.as_u16(),
-        data: Some(to_update)
+        data: Some(item)
     })
 }
 
-#[poem_grants::protect("MUTATE")]
+#[poem_grants::protect("MUTATE", error = missing_mutate)]
 #[handler]
-fn delete_item(Path(id): Path<u32>, db: Data<&Arc<Mutex<Db>>>) -> Result<GenericResponse<Value>> {
-    let mut db_ref = db
-        .lock()
-        .map_err(|_| Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))
-        .expect("Getting db lock");
+async fn patch_item(Path(id): Path<u32>, payload: ItemPatchBody, db: Data<&DbHandle>) -> Result<GenericResponse<Item>, AppError> {
+    let mut db_ref = db.write().await;
+    let item = db_ref
+        .table_mut::<Item>(ITEM_TABLE_NAME)
+        .update_fields(id, payload.into())
+        .await
+        .context("Patching item")?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(GenericResponse::<Item>{
+        message: None,
+        status_code_u16: StatusCode::OK.as_u16(),
+        data: Some(item)
+    })
+}
+
+#[poem_grants::protect("MUTATE", error = missing_mutate)]
+#[handler]
+async fn delete_item(Path(id): Path<u32>, db: Data<&DbHandle>) -> Result<GenericResponse<Value>, AppError> {
+    let mut db_ref = db.write().await;
     db_ref
-        .delete_by_id(ITEM_TABLE_NAME.to_string(), id)
-        .unwrap();
+        .table_mut::<Item>(ITEM_TABLE_NAME)
+        .delete(id)
+        .await
+        .context("Deleting item")?;
 
     Ok(GenericResponse::<Value>{
         message: Some("Item deleted successfully".to_string()),
@@ -112,40 +171,42 @@ fn delete_item(Path(id): Path<u32>, db: Data<&Arc<Mutex<Db>>>) -> Result<Generic
 pub fn item_routes() -> Route {
     Route::new()
         .at("/", get(get_all_items).post(create_item))
+        .at("/stats", get(get_item_stats))
         .at(
             "/:id", 
-            get(get_item_by_id).put(put_item).delete(delete_item)
+            get(get_item_by_id).put(put_item).patch(patch_item).delete(delete_item)
         )
 }
 
 #[cfg(test)]
 mod tests {
-    use poem::{http::StatusCode, Endpoint};
+    use poem::{http::{Method, StatusCode}, Endpoint};
 
-    use crate::test::{async_run_with_file_create_teardown, ApiTestClient};
+    use crate::db::Db;
+    use crate::test::{async_run_with_file_create_teardown, ApiTestClient, PermissionCase, TEST_PERMISSION};
 
     use super::*;
 
-    fn insert_item(db: &mut Db, name: String) {
+    async fn insert_item(db: &mut Db, name: String) {
         let table_name = "item".to_string();
-        db.add_table(table_name.clone(), false).unwrap();
-        let id = db.get_increment_last_id(table_name.clone()).unwrap().unwrap();
+        db.add_table(table_name.clone(), false).await.unwrap();
+        let id = db.get_increment_last_id(table_name.clone()).await.unwrap().unwrap();
         let to_insert = Item::new(id, name);
-        db.insert_or_update(table_name.clone(), id, to_insert).unwrap();
+        db.insert_or_update(table_name.clone(), id, to_insert).await.unwrap();
     }
 
-    fn init_client(file_name: String) -> ApiTestClient<impl Endpoint> {
+    async fn init_client(file_name: String) -> ApiTestClient<impl Endpoint> {
         let routes = Route::new().nest(
             "/items", item_routes()
         );
-        let test_client = ApiTestClient::init(routes, file_name.as_str());
+        let test_client = ApiTestClient::init(routes, file_name.as_str()).await;
         {
-            let mut db = test_client.db.lock().unwrap();
-            db.add_table("item".to_string(), false).unwrap();
-            db.delete_all("item".to_string()).unwrap();
+            let mut db = test_client.db.write().await;
+            db.add_table("item".to_string(), false).await.unwrap();
+            db.delete_all("item".to_string()).await.unwrap();
         }
 
-        return test_client
+        test_client
     }
 
     #[tokio::test]
@@ -153,30 +214,153 @@ mod tests {
         async_run_with_file_create_teardown(|file_name| {
             let file_name = file_name.to_string();
             async {
-                let test_client = init_client(file_name);
+                let test_client = init_client(file_name).await;
                 {
-                    let mut db = test_client.db.lock().unwrap();
-                    insert_item(&mut db, String::from("item 1"));
-                    insert_item(&mut db, String::from("item 2"));
-                    insert_item(&mut db, String::from("item 3"));
+                    let mut db = test_client.db.write().await;
+                    insert_item(&mut db, String::from("item 1")).await;
+                    insert_item(&mut db, String::from("item 2")).await;
+                    insert_item(&mut db, String::from("item 3")).await;
                 }
                 let response = test_client.client.get("/items").send().await;
         
                 let expected_data = serde_json::json!({
-                    "data": [
-                        {
-                            "id": 1,
-                            "name": "item 1"
-                        },
-                        {
-                            "id": 2,
-                            "name": "item 2"
-                        },
-                        {
-                            "id": 3,
-                            "name": "item 3"
-                        }
-                    ]
+                    "data": {
+                        "items": [
+                            {
+                                "id": 1,
+                                "name": "item 1"
+                            },
+                            {
+                                "id": 2,
+                                "name": "item 2"
+                            },
+                            {
+                                "id": 3,
+                                "name": "item 3"
+                            }
+                        ],
+                        "total": 3,
+                        "next_offset": null
+                    }
+                });
+        
+                response.assert_status_is_ok();
+                response.assert_json(expected_data).await;
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_items_page() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    insert_item(&mut db, String::from("item 1")).await;
+                    insert_item(&mut db, String::from("item 2")).await;
+                    insert_item(&mut db, String::from("item 3")).await;
+                }
+                let response = test_client.client.get("/items")
+                    .query("offset", &1)
+                    .query("limit", &1)
+                    .send()
+                    .await;
+        
+                let expected_data = serde_json::json!({
+                    "data": {
+                        "items": [
+                            {
+                                "id": 2,
+                                "name": "item 2"
+                            }
+                        ],
+                        "total": 3,
+                        "next_offset": 2
+                    }
+                });
+        
+                response.assert_status_is_ok();
+                response.assert_json(expected_data).await;
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_items_sorted() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    insert_item(&mut db, String::from("b")).await;
+                    insert_item(&mut db, String::from("c")).await;
+                    insert_item(&mut db, String::from("a")).await;
+                }
+                let response = test_client.client.get("/items")
+                    .query("sort_by", &"name")
+                    .query("direction", &"desc")
+                    .query("limit", &2)
+                    .send()
+                    .await;
+        
+                let expected_data = serde_json::json!({
+                    "data": {
+                        "items": [
+                            {
+                                "id": 2,
+                                "name": "c"
+                            },
+                            {
+                                "id": 1,
+                                "name": "b"
+                            }
+                        ],
+                        "total": 3,
+                        "next_offset": 2
+                    }
+                });
+        
+                response.assert_status_is_ok();
+                response.assert_json(expected_data).await;
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_search_items() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    insert_item(&mut db, String::from("Red apple")).await;
+                    insert_item(&mut db, String::from("banana")).await;
+                    insert_item(&mut db, String::from("green apple")).await;
+                }
+                let response = test_client.client.get("/items")
+                    .query("q", &"apple")
+                    .send()
+                    .await;
+        
+                let expected_data = serde_json::json!({
+                    "data": {
+                        "items": [
+                            {
+                                "id": 1,
+                                "name": "Red apple"
+                            },
+                            {
+                                "id": 3,
+                                "name": "green apple"
+                            }
+                        ],
+                        "total": 2,
+                        "next_offset": null
+                    }
                 });
         
                 response.assert_status_is_ok();
@@ -190,12 +374,12 @@ mod tests {
         async_run_with_file_create_teardown(|file_name| {
             let file_name = file_name.to_string();
             async {
-                let test_client = init_client(file_name);
+                let test_client = init_client(file_name).await;
                 {
-                    let mut db = test_client.db.lock().unwrap();
-                    insert_item(&mut db, String::from("item 1"));
-                    insert_item(&mut db, String::from("item 2"));
-                    insert_item(&mut db, String::from("item 3"));
+                    let mut db = test_client.db.write().await;
+                    insert_item(&mut db, String::from("item 1")).await;
+                    insert_item(&mut db, String::from("item 2")).await;
+                    insert_item(&mut db, String::from("item 3")).await;
                 }
         
                 let response = test_client.client.get("/items/2").send().await;
@@ -218,7 +402,7 @@ mod tests {
         async_run_with_file_create_teardown(|file_name| {
             let file_name = file_name.to_string();
             async {      
-                let test_client = init_client(file_name);
+                let test_client = init_client(file_name).await;
                 let response = test_client.client.get("/items/99").send().await;
         
                 response.assert_status(StatusCode::NOT_FOUND);
@@ -231,7 +415,7 @@ mod tests {
         async_run_with_file_create_teardown(|file_name| {
             let file_name = file_name.to_string();
             async {      
-                let test_client = init_client(file_name);
+                let test_client = init_client(file_name).await;
         
                 let response = test_client.client.post("/items")
                     .body_json(&ItemCreateBody{ name: "item 1".to_string() })
@@ -257,11 +441,11 @@ mod tests {
         async_run_with_file_create_teardown(|file_name| {
             let file_name = file_name.to_string();
             async {
-                let test_client = init_client(file_name);
+                let test_client = init_client(file_name).await;
 
                 {
-                    let mut db = test_client.db.lock().unwrap();
-                    insert_item(&mut db, "item 1".to_string());
+                    let mut db = test_client.db.write().await;
+                    insert_item(&mut db, "item 1".to_string()).await;
                 }
         
                 let put_response = test_client.client.put("/items/1")
@@ -281,12 +465,61 @@ mod tests {
         }).await;
     }
 
+    #[tokio::test]
+    async fn test_put_item_if_match() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+
+                {
+                    let mut db = test_client.db.write().await;
+                    db.add_versioning("item".to_string());
+                    insert_item(&mut db, "item 1".to_string()).await;
+                }
+
+                let stale_response = test_client.client.put("/items/1")
+                    .body_json(&ItemUpdateBody{ name: "stale".to_string() })
+                    .header("Authorization", format!("Bearer {}", test_client.token))
+                    .header("If-Match", "\"2\"")
+                    .send()
+                    .await;
+
+                stale_response.assert_status(StatusCode::PRECONDITION_FAILED);
+
+                for invalid in ["garbage", "W/\"1\"", "\"\""] {
+                    let invalid_response = test_client.client.put("/items/1")
+                        .body_json(&ItemUpdateBody{ name: "invalid".to_string() })
+                        .header("Authorization", format!("Bearer {}", test_client.token))
+                        .header("If-Match", invalid)
+                        .send()
+                        .await;
+
+                    invalid_response.assert_status(StatusCode::PRECONDITION_FAILED);
+                    invalid_response.json().await.value().object().get("data").object().get("code").assert_string("invalid_precondition");
+                }
+
+                let put_response = test_client.client.put("/items/1")
+                    .body_json(&ItemUpdateBody{ name: "item 1 updated".to_string() })
+                    .header("Authorization", format!("Bearer {}", test_client.token))
+                    .header("If-Match", "\"1\"")
+                    .send()
+                    .await;
+
+                put_response.assert_status_is_ok();
+                let json = put_response.json().await;
+                json.value().object().get("data").object().get("name").assert_string("item 1 updated");
+                json.value().object().get("data").object().get("version").assert_i64(2);
+            }
+        }).await;
+    }
+
     #[tokio::test]
     async fn test_delete_item() {
         async_run_with_file_create_teardown(|file_name| {
             let file_name = file_name.to_string();
             async {
-                let test_client = init_client(file_name);
+                let test_client = init_client(file_name).await;
 
                 let response = test_client.client.delete("/items/1")
                     .header("Authorization", format!("Bearer {}", test_client.token))
@@ -297,4 +530,192 @@ mod tests {
             }
         }).await;
     }
+
+    #[tokio::test]
+    async fn test_permission_matrix() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    insert_item(&mut db, "item 1".to_string()).await;
+                }
+
+                let body = serde_json::json!({"name": "item"});
+                let permission_sets: &[Option<&[&str]>] = &[None, Some(&[]), Some(&[TEST_PERMISSION])];
+                let cases = [
+                    PermissionCase {
+                        method: Method::GET,
+                        uri: "/items",
+                        body: None,
+                        expected: vec![StatusCode::OK, StatusCode::OK, StatusCode::OK]
+                    },
+                    PermissionCase {
+                        method: Method::GET,
+                        uri: "/items/1",
+                        body: None,
+                        expected: vec![StatusCode::OK, StatusCode::OK, StatusCode::OK]
+                    },
+                    PermissionCase {
+                        method: Method::POST,
+                        uri: "/items",
+                        body: Some(body.clone()),
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::CREATED]
+                    },
+                    PermissionCase {
+                        method: Method::PUT,
+                        uri: "/items/1",
+                        body: Some(body.clone()),
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    },
+                    PermissionCase {
+                        method: Method::PATCH,
+                        uri: "/items/1",
+                        body: Some(body),
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    },
+                    PermissionCase {
+                        method: Method::DELETE,
+                        uri: "/items/1",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    }
+                ];
+
+                test_client.assert_permission_matrix(permission_sets, &cases).await;
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_auth_challenges() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                let body = ItemCreateBody{ name: "item 1".to_string() };
+
+                let missing = test_client.client.post("/items").body_json(&body).send().await;
+                missing.assert_status(StatusCode::UNAUTHORIZED);
+                missing.assert_header("WWW-Authenticate", "Bearer");
+                missing.assert_json(serde_json::json!({
+                    "data": { "code": "missing_token" },
+                    "message": "A bearer token is required"
+                })).await;
+
+                let invalid = test_client.client.post("/items")
+                    .body_json(&body)
+                    .header("Authorization", "Bearer not-a-token")
+                    .send()
+                    .await;
+                invalid.assert_status(StatusCode::UNAUTHORIZED);
+                invalid.assert_header(
+                    "WWW-Authenticate",
+                    "Bearer error=\"invalid_token\", error_description=\"The token is malformed\""
+                );
+                invalid.assert_json(serde_json::json!({
+                    "data": { "code": "token_malformed" },
+                    "message": "Invalid token: The token is malformed"
+                })).await;
+
+                let jwt_data = test_client.jwt_manager.create_token_data("reader".to_string(), vec![]);
+                let token = test_client.jwt_manager.encode(jwt_data).unwrap();
+                let forbidden = test_client.client.post("/items")
+                    .body_json(&body)
+                    .header("Authorization", format!("Bearer {}", token))
+                    .send()
+                    .await;
+                forbidden.assert_status(StatusCode::FORBIDDEN);
+                forbidden.assert_header("WWW-Authenticate", "Bearer error=\"insufficient_scope\", scope=\"MUTATE\"");
+                forbidden.assert_json(serde_json::json!({
+                    "data": { "code": "insufficient_scope", "required_permissions": ["MUTATE"] },
+                    "message": "Missing required permissions: MUTATE"
+                })).await;
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_item_stats() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                {
+                    let mut db = test_client.db.write().await;
+                    for name in ["a", "b", "a"] {
+                        insert_item(&mut db, name.to_string()).await;
+                    }
+                }
+
+                let response = test_client.client.get("/items/stats").send().await;
+                response.assert_status_is_ok();
+                response.assert_json(serde_json::json!({"data": 3})).await;
+
+                let response = test_client.client.get("/items/stats").query("agg", &"max").send().await;
+                response.assert_json(serde_json::json!({"data": 3})).await;
+
+                let response = test_client.client.get("/items/stats")
+                    .query("agg", &"sum")
+                    .query("group_by", &"name")
+                    .send()
+                    .await;
+                response.assert_json(serde_json::json!({"data": {"a": 4, "b": 2}})).await;
+
+                let response = test_client.client.get("/items/stats")
+                    .query("agg", &"sum")
+                    .query("groupBy", &"name")
+                    .send()
+                    .await;
+                response.assert_json(serde_json::json!({"data": {"a": 4, "b": 2}})).await;
+
+                test_client.client.get("/items/stats").query("agg", &"median").send().await.assert_status(StatusCode::BAD_REQUEST);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_patch_item() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+
+                {
+                    let mut db = test_client.db.write().await;
+                    insert_item(&mut db, "item 1".to_string()).await;
+                }
+
+                let patch = |body: Value| test_client.client.patch("/items/1")
+                    .body_json(&body)
+                    .header("Authorization", format!("Bearer {}", test_client.token))
+                    .send();
+
+                let response = patch(serde_json::json!({"description": "first"})).await;
+                response.assert_status_is_ok();
+                response.assert_json(serde_json::json!({"data": {"id": 1, "name": "item 1", "description": "first"}})).await;
+
+                // Absent fields are left alone
+                let response = patch(serde_json::json!({"name": "renamed"})).await;
+                response.assert_json(serde_json::json!({"data": {"id": 1, "name": "renamed", "description": "first"}})).await;
+
+                // An explicit null clears the field
+                let response = patch(serde_json::json!({"description": null})).await;
+                response.assert_json(serde_json::json!({"data": {"id": 1, "name": "renamed"}})).await;
+
+                let stored = test_client.db.read().await.find_by_id::<Value>("item".to_string(), 1).unwrap();
+                assert!(stored.get("description").is_none());
+
+                patch(serde_json::json!({"name": null})).await.assert_status(StatusCode::BAD_REQUEST);
+
+                test_client.client.patch("/items/2")
+                    .body_json(&serde_json::json!({"name": "missing"}))
+                    .header("Authorization", format!("Bearer {}", test_client.token))
+                    .send()
+                    .await
+                    .assert_status(StatusCode::NOT_FOUND);
+            }
+        }).await;
+    }
 }
\ No newline at end of file
@@ -1,20 +1,38 @@
+pub mod admin;
 pub mod db;
+pub mod error;
 pub mod items;
+pub mod naming;
+pub mod patch;
 pub mod test;
 pub mod response;
 pub mod auth;
+pub mod cache;
+pub mod config;
+pub mod replay;
+pub mod scim;
 
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::time::Duration;
 
+use admin::route::admin_routes;
+use auth::challenge::{ChallengeGuard, ProofOfWork};
 use auth::route::auth_routes;
-use poem::middleware::{AddData, Tracing};
+use cache::{CacheControlMiddleware, CachePolicy, ResponseCacheMiddleware};
+use config::{Profile, ServerConfig, DEFAULT_CONFIG_FILE};
+use error::ErrorLogMiddleware;
+use naming::FieldNamingMiddleware;
+use poem::middleware::{AddData, RequestId, Tracing};
 use poem::Middleware;
 use poem::{listener::TcpListener, EndpointExt, Route, Server};
-use response::GenericResponse;
-use serde_json::Value;
+use replay::{ReplayMiddleware, ReplayMode};
+use response::error_response;
+use scim::route::scim_routes;
 
-use crate::items::route::item_routes;
-use crate::db::Db;
+use crate::auth::model::User;
+use crate::items::model::Item;
+use crate::items::route::{item_routes, ITEM_TABLE_NAME};
+use crate::db::{Db, DbHandle};
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
@@ -22,31 +40,106 @@ async fn main() -> Result<(), std::io::Error> {
         .with_env_filter("poem=trace")
         .init();
 
-    let mut db = Db::init("./data.json".to_string()).expect("Initializing db");
-    db.add_table("item".to_string(), false).unwrap();
-    db.add_table("user".to_string(), false).unwrap();
-    let db_ref = Arc::new(Mutex::new(db));
+    let config_file = std::env::var("CONFIG_FILE").unwrap_or(DEFAULT_CONFIG_FILE.to_string());
+    let profile = std::env::args()
+        .skip_while(|arg| arg != "--profile")
+        .nth(1)
+        .map(|profile| profile.parse::<Profile>().expect("Parsing profile"));
+    let config = ServerConfig::load_with_profile(&config_file, profile).expect("Loading config");
 
-    let jwt_manager = auth::jwt::Manager::init("secret".to_string(), 24);
+    // `--migrate-plan` prints what the pending migrations would change and exits
+    if std::env::args().any(|arg| arg == "--migrate-plan") {
+        let db = Db::open_unmigrated(config.storage, config.compression, config.db_file.clone()).await.expect("Initializing db");
+        let plan = db.plan_migrations(db::migrations::MIGRATIONS, 3);
+        println!("{}", serde_json::to_string_pretty(&plan).expect("Serializing migration plan"));
+
+        return Ok(())
+    }
+
+    let mut db = match config.ephemeral || std::env::args().any(|arg| arg == "--ephemeral") {
+        true => Db::in_memory(),
+        false => Db::open(config.storage, config.compression, config.db_file.clone()).await.expect("Initializing db")
+    };
+    db.add_table_with_schema::<Item>("item".to_string(), false).await.unwrap();
+    db.add_timestamps("item".to_string());
+    db.add_versioning("item".to_string());
+    db.add_search_index("item".to_string());
+    db.add_table_with_schema::<User>("user".to_string(), false).await.unwrap();
+    db.add_unique_constraint("user".to_string(), "username".to_string()).expect("Adding username constraint");
+    db.set_checkpoint_interval(config.flush.max_pending_entries);
+
+    for (table, codec) in &config.table_codecs {
+        db.set_codec(table.clone(), *codec).await.expect("Setting table codec");
+    }
+
+    // `--export-csv <table>` prints the table as CSV instead of serving
+    if let Some(table) = std::env::args().skip_while(|arg| arg != "--export-csv").nth(1) {
+        match db.export_csv(table.clone(), std::io::stdout().lock()).expect("Exporting table") {
+            Some(rows) => eprintln!("Exported {} rows of {}", rows, table),
+            None => eprintln!("Table {} not found", table)
+        }
+
+        return Ok(())
+    }
+
+    let db_ref = DbHandle::new(db);
+    db_ref.spawn_purge_task(Duration::from_secs(config.purge_interval_secs));
+
+    if config.flush.interval_secs > 0 {
+        db_ref.spawn_flush_task(Duration::from_secs(config.flush.interval_secs));
+    }
+
+    if config.snapshot.interval_secs > 0 {
+        db_ref.spawn_snapshot_task(PathBuf::from(&config.snapshot.dir), Duration::from_secs(config.snapshot.interval_secs));
+    }
+
+    let jwt_manager = auth::jwt::Manager::init(config.jwt_secret.clone(), config.jwt_expiration_hours)
+        .with_leeway(config.jwt_leeway_secs);
     let jwt_middleware = auth::middleware::JwtMiddleware{ manager: jwt_manager.clone() };
+    let challenge_guard = ChallengeGuard::new(ProofOfWork::new(config.challenge.difficulty), &config.challenge);
+    let response_cache = ResponseCacheMiddleware::new(&config.cache.responses, db_ref.read().await.cache_metrics());
+
+    if config.cache.responses.enabled {
+        response_cache.invalidate_on(db_ref.write().await.watch(ITEM_TABLE_NAME.to_string()));
+    }
     
     let app = Route::new()
-        .nest("/items", item_routes())
-        .nest("/", auth_routes())
+        .nest(
+            "/items",
+            item_routes()
+                .with_if(config.cache.responses.enabled, response_cache)
+                .with(CacheControlMiddleware{ policy: config.cache.items.clone() })
+        )
+        .nest("/admin", admin_routes().with(CacheControlMiddleware{ policy: CachePolicy::NoStore }))
+        .nest("/scim/v2", scim_routes().with(CacheControlMiddleware{ policy: CachePolicy::NoStore }))
+        .nest("/", auth_routes().with(CacheControlMiddleware{ policy: config.cache.auth.clone() }))
         .with(
             jwt_middleware
-                .combine(AddData::new(db_ref))
+                .combine(AddData::new(db_ref.clone()))
                 .combine(AddData::new(jwt_manager))
+                .combine(AddData::new(config.credential_policy.clone()))
+                .combine(AddData::new(challenge_guard))
+                .combine(AddData::new(config.auth_provider.build()))
                 .combine(Tracing)
         )
-        .catch_all_error(|err| async move {
-            GenericResponse::<Value>{ 
-                message: Some(err.to_string()),
-                status_code_u16: err.status().as_u16(),
-                data: None
-            }
-        });
-    Server::new(TcpListener::bind("0.0.0.0:3000"))
-        .run(app)
-        .await
+        .with(ErrorLogMiddleware)
+        .catch_all_error(|err| async move { error_response(err) })
+        .with(FieldNamingMiddleware::new(config.field_naming))
+        .with_if(config.cors.is_enabled(), config.cors.build())
+        .with(RequestId::default())
+        .with_if(
+            config.replay.mode != ReplayMode::Off,
+            ReplayMiddleware::new(&config.replay)
+        );
+    Server::new(TcpListener::bind(config.bind_address))
+        .run_with_graceful_shutdown(
+            app,
+            async { tokio::signal::ctrl_c().await.expect("Listening for ctrl-c") },
+            None
+        )
+        .await?;
+
+    db_ref.write().await.flush_now().await.expect("Flushing db on shutdown");
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,455 @@
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response};
+use serde::ser::{self, SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+
+tokio::task_local! {
+    static FIELD_NAMING: FieldNaming;
+}
+
+/// Case of the struct field names in json responses, set with `field_naming`
+/// in the config. Models are written in snake case and take the camel case
+/// spelling of their multi-word fields as a serde `alias`, so input in either
+/// case is accepted whatever the policy.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldNaming {
+    #[default]
+    SnakeCase,
+    CamelCase
+}
+
+impl FieldNaming {
+    /// The policy of the request being handled, set by [`FieldNamingMiddleware`].
+    pub fn current() -> Self {
+        FIELD_NAMING.try_with(|naming| *naming).unwrap_or_default()
+    }
+
+    pub fn rename(&self, name: &str) -> String {
+        match self {
+            FieldNaming::SnakeCase => to_snake_case(name),
+            FieldNaming::CamelCase => to_camel_case(name)
+        }
+    }
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut renamed = String::with_capacity(name.len() + 4);
+
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                renamed.push('_');
+            }
+            renamed.push(c.to_ascii_lowercase());
+        } else {
+            renamed.push(c);
+        }
+    }
+
+    renamed
+}
+
+fn to_camel_case(name: &str) -> String {
+    let mut renamed = String::with_capacity(name.len());
+    let mut upper_next = false;
+
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '_' if i > 0 => upper_next = true,
+            c if upper_next => {
+                renamed.push(c.to_ascii_uppercase());
+                upper_next = false;
+            },
+            c => renamed.push(c)
+        }
+    }
+
+    renamed
+}
+
+/// Serializes `value` with the field names of its structs, however deeply
+/// nested, following `naming`. Map keys are data, such as table names or
+/// grouped values, and are kept as they are.
+pub struct Renamed<'a, T: ?Sized> {
+    pub naming: FieldNaming,
+    pub value: &'a T
+}
+
+impl<T: Serialize + ?Sized> Serialize for Renamed<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.naming {
+            // Models are already written in snake case
+            FieldNaming::SnakeCase => self.value.serialize(serializer),
+            naming => self.value.serialize(RenamingSerializer { inner: serializer, naming })
+        }
+    }
+}
+
+/// Forwards to `inner`, writing structs out as maps under renamed keys.
+struct RenamingSerializer<S> {
+    inner: S,
+    naming: FieldNaming
+}
+
+impl<S> RenamingSerializer<S> {
+    fn wrap<'a, T: ?Sized>(&self, value: &'a T) -> Renamed<'a, T> {
+        Renamed { naming: self.naming, value }
+    }
+}
+
+/// A compound being serialized by a [`RenamingSerializer`].
+struct Compound<C> {
+    inner: C,
+    naming: FieldNaming
+}
+
+impl<C> Compound<C> {
+    fn wrap<'a, T: ?Sized>(&self, value: &'a T) -> Renamed<'a, T> {
+        Renamed { naming: self.naming, value }
+    }
+}
+
+/// A struct variant, collected so it can be written as `{variant: {fields}}`.
+struct StructVariant<M> {
+    inner: M,
+    naming: FieldNaming,
+    variant: &'static str,
+    fields: Map<String, Value>
+}
+
+impl<S: Serializer> Serializer for RenamingSerializer<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Compound<S::SerializeSeq>;
+    type SerializeTuple = Compound<S::SerializeTuple>;
+    type SerializeTupleStruct = Compound<S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Compound<S::SerializeTupleVariant>;
+    type SerializeMap = Compound<S::SerializeMap>;
+    type SerializeStruct = Compound<S::SerializeMap>;
+    type SerializeStructVariant = StructVariant<S::SerializeMap>;
+
+    fn serialize_bool(self, v: bool) -> Result<S::Ok, S::Error> { self.inner.serialize_bool(v) }
+    fn serialize_i8(self, v: i8) -> Result<S::Ok, S::Error> { self.inner.serialize_i8(v) }
+    fn serialize_i16(self, v: i16) -> Result<S::Ok, S::Error> { self.inner.serialize_i16(v) }
+    fn serialize_i32(self, v: i32) -> Result<S::Ok, S::Error> { self.inner.serialize_i32(v) }
+    fn serialize_i64(self, v: i64) -> Result<S::Ok, S::Error> { self.inner.serialize_i64(v) }
+    fn serialize_i128(self, v: i128) -> Result<S::Ok, S::Error> { self.inner.serialize_i128(v) }
+    fn serialize_u8(self, v: u8) -> Result<S::Ok, S::Error> { self.inner.serialize_u8(v) }
+    fn serialize_u16(self, v: u16) -> Result<S::Ok, S::Error> { self.inner.serialize_u16(v) }
+    fn serialize_u32(self, v: u32) -> Result<S::Ok, S::Error> { self.inner.serialize_u32(v) }
+    fn serialize_u64(self, v: u64) -> Result<S::Ok, S::Error> { self.inner.serialize_u64(v) }
+    fn serialize_u128(self, v: u128) -> Result<S::Ok, S::Error> { self.inner.serialize_u128(v) }
+    fn serialize_f32(self, v: f32) -> Result<S::Ok, S::Error> { self.inner.serialize_f32(v) }
+    fn serialize_f64(self, v: f64) -> Result<S::Ok, S::Error> { self.inner.serialize_f64(v) }
+    fn serialize_char(self, v: char) -> Result<S::Ok, S::Error> { self.inner.serialize_char(v) }
+    fn serialize_str(self, v: &str) -> Result<S::Ok, S::Error> { self.inner.serialize_str(v) }
+    fn serialize_bytes(self, v: &[u8]) -> Result<S::Ok, S::Error> { self.inner.serialize_bytes(v) }
+    fn serialize_none(self) -> Result<S::Ok, S::Error> { self.inner.serialize_none() }
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> { self.inner.serialize_unit() }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_some(&value)
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_struct(name)
+    }
+
+    fn serialize_unit_variant(self, name: &'static str, index: u32, variant: &'static str) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit_variant(name, index, variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<S::Ok, S::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_newtype_struct(name, &value)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, name: &'static str, index: u32, variant: &'static str, value: &T) -> Result<S::Ok, S::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_newtype_variant(name, index, variant, &value)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        Ok(Compound { inner: self.inner.serialize_seq(len)?, naming: self.naming })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        Ok(Compound { inner: self.inner.serialize_tuple(len)?, naming: self.naming })
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, S::Error> {
+        Ok(Compound { inner: self.inner.serialize_tuple_struct(name, len)?, naming: self.naming })
+    }
+
+    fn serialize_tuple_variant(self, name: &'static str, index: u32, variant: &'static str, len: usize) -> Result<Self::SerializeTupleVariant, S::Error> {
+        Ok(Compound { inner: self.inner.serialize_tuple_variant(name, index, variant, len)?, naming: self.naming })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        Ok(Compound { inner: self.inner.serialize_map(len)?, naming: self.naming })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct, S::Error> {
+        Ok(Compound { inner: self.inner.serialize_map(Some(len))?, naming: self.naming })
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, S::Error> {
+        Ok(StructVariant { inner: self.inner.serialize_map(Some(1))?, naming: self.naming, variant, fields: Map::new() })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+impl<C: ser::SerializeSeq> ser::SerializeSeq for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_element(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeTuple> ser::SerializeTuple for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_element(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeTupleStruct> ser::SerializeTupleStruct for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_field(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: ser::SerializeTupleVariant> ser::SerializeTupleVariant for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_field(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeMap> SerializeMap for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), C::Error> {
+        self.inner.serialize_key(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_value(&value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<C: SerializeMap> ser::SerializeStruct for Compound<C> {
+    type Ok = C::Ok;
+    type Error = C::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), C::Error> {
+        let value = self.wrap(value);
+        self.inner.serialize_entry(&self.naming.rename(key), &value)
+    }
+
+    fn end(self) -> Result<C::Ok, C::Error> {
+        self.inner.end()
+    }
+}
+
+impl<M: SerializeMap> ser::SerializeStructVariant for StructVariant<M> {
+    type Ok = M::Ok;
+    type Error = M::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), M::Error> {
+        let value = serde_json::to_value(Renamed { naming: self.naming, value }).map_err(ser::Error::custom)?;
+        self.fields.insert(self.naming.rename(key), value);
+
+        Ok(())
+    }
+
+    fn end(mut self) -> Result<M::Ok, M::Error> {
+        self.inner.serialize_entry(self.variant, &self.fields)?;
+        self.inner.end()
+    }
+}
+
+/// Makes the configured [`FieldNaming`] the [`FieldNaming::current`] one for
+/// the requests it wraps, which [`crate::response::GenericResponse`] renders
+/// its data with. Bodies are never rewritten, so routes with a fixed wire
+/// format such as SCIM, which do not render through it, are left alone.
+#[derive(Clone)]
+pub struct FieldNamingMiddleware {
+    naming: FieldNaming
+}
+
+impl FieldNamingMiddleware {
+    pub fn new(naming: FieldNaming) -> Self {
+        Self { naming }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for FieldNamingMiddleware {
+    type Output = FieldNamingMiddlewareImpl<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        FieldNamingMiddlewareImpl { ep, naming: self.naming }
+    }
+}
+
+pub struct FieldNamingMiddlewareImpl<E> {
+    ep: E,
+    naming: FieldNaming
+}
+
+impl<E: Endpoint> Endpoint for FieldNamingMiddlewareImpl<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> poem::Result<Self::Output> {
+        FIELD_NAMING
+            .scope(self.naming, async { Ok(self.ep.call(req).await?.into_response()) })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use poem::http::StatusCode;
+    use poem::web::Json;
+    use poem::{get, handler, post, test::TestClient, EndpointExt, Route};
+    use serde_json::json;
+
+    use crate::response::GenericResponse;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct TableCount {
+        soft_deleted: u32
+    }
+
+    #[derive(Serialize)]
+    enum Change {
+        Renamed { old_name: String }
+    }
+
+    #[derive(Serialize)]
+    struct Report {
+        next_offset: Option<u32>,
+        per_table: BTreeMap<String, TableCount>,
+        grouped: Value,
+        changes: Vec<Change>
+    }
+
+    fn report() -> Report {
+        Report {
+            next_offset: Some(2),
+            per_table: BTreeMap::from([("user_token".to_string(), TableCount { soft_deleted: 1 })]),
+            grouped: json!({"item_1": {"row_count": 1}}),
+            changes: vec![Change::Renamed { old_name: "a".to_string() }]
+        }
+    }
+
+    #[handler]
+    fn show() -> GenericResponse<Report> {
+        GenericResponse { status_code_u16: StatusCode::OK.as_u16(), message: None, data: Some(report()) }
+    }
+
+    #[handler]
+    fn echo(Json(body): Json<Value>) -> Json<Value> {
+        Json(body)
+    }
+
+    fn client(naming: FieldNaming) -> TestClient<impl Endpoint> {
+        TestClient::new(
+            Route::new()
+                .at("/", get(show))
+                .at("/echo", post(echo))
+                .with(FieldNamingMiddleware::new(naming))
+        )
+    }
+
+    #[test]
+    fn test_rename() {
+        assert_eq!(FieldNaming::CamelCase.rename("next_offset"), "nextOffset");
+        assert_eq!(FieldNaming::CamelCase.rename("id"), "id");
+        assert_eq!(FieldNaming::CamelCase.rename("_private"), "_private");
+        assert_eq!(FieldNaming::SnakeCase.rename("nextOffset"), "next_offset");
+        assert_eq!(FieldNaming::SnakeCase.rename("next_offset"), "next_offset");
+    }
+
+    #[test]
+    fn test_renames_struct_fields_only() {
+        let renamed = serde_json::to_value(Renamed { naming: FieldNaming::CamelCase, value: &report() }).unwrap();
+
+        assert_eq!(renamed, json!({
+            "nextOffset": 2,
+            "perTable": {"user_token": {"softDeleted": 1}},
+            "grouped": {"item_1": {"row_count": 1}},
+            "changes": [{"Renamed": {"oldName": "a"}}]
+        }));
+        assert_eq!(
+            serde_json::to_value(Renamed { naming: FieldNaming::SnakeCase, value: &report() }).unwrap(),
+            serde_json::to_value(report()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_camel_case_responses() {
+        let response = client(FieldNaming::CamelCase).get("/").send().await;
+
+        response.assert_status_is_ok();
+        let json = response.json().await;
+        let data = json.value().object().get("data").object();
+        data.get("nextOffset").assert_i64(2);
+        data.get("perTable").object().get("user_token").object().get("softDeleted").assert_i64(1);
+    }
+
+    #[tokio::test]
+    async fn test_bodies_are_not_rewritten() {
+        for naming in [FieldNaming::SnakeCase, FieldNaming::CamelCase] {
+            let body = json!({"createdAt": 1, "item_1": {"updated_at": 2}});
+            let response = client(naming).post("/echo").body_json(&body).send().await;
+
+            response.assert_json(body).await;
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+
+/// A field of a patch body that tells an explicit `null` apart from a missing
+/// key: `Null` clears the field while `Undefined` leaves it unchanged.
+///
+/// Fields need `#[serde(default, skip_serializing_if = "MaybeUndefined::is_undefined")]`
+/// so a body serializes back into the merge patch [`crate::db::Db::update_fields`]
+/// takes, where `null` removes the field.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum MaybeUndefined<T> {
+    #[default]
+    Undefined,
+    Null,
+    Value(T)
+}
+
+impl<T> MaybeUndefined<T> {
+    pub fn is_undefined(&self) -> bool {
+        matches!(self, MaybeUndefined::Undefined)
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, MaybeUndefined::Null)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for MaybeUndefined<T> {
+    /// Only called for keys that are present, missing ones take the default.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Option::<T>::deserialize(deserializer)? {
+            Some(value) => MaybeUndefined::Value(value),
+            None => MaybeUndefined::Null
+        })
+    }
+}
+
+impl<T: Serialize> Serialize for MaybeUndefined<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            MaybeUndefined::Value(value) => value.serialize(serializer),
+            _ => serializer.serialize_none()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Body {
+        #[serde(default, skip_serializing_if = "MaybeUndefined::is_undefined")]
+        description: MaybeUndefined<String>
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let absent: Body = serde_json::from_value(json!({})).unwrap();
+        let null: Body = serde_json::from_value(json!({"description": null})).unwrap();
+        let value: Body = serde_json::from_value(json!({"description": "x"})).unwrap();
+
+        assert_eq!(absent.description, MaybeUndefined::Undefined);
+        assert_eq!(null.description, MaybeUndefined::Null);
+        assert_eq!(value.description, MaybeUndefined::Value("x".to_string()));
+    }
+
+    #[test]
+    fn test_serialize_as_merge_patch() {
+        let patch = |description| serde_json::to_value(Body { description }).unwrap();
+
+        assert_eq!(patch(MaybeUndefined::Undefined), json!({}));
+        assert_eq!(patch(MaybeUndefined::Null), json!({"description": Value::Null}));
+        assert_eq!(patch(MaybeUndefined::Value("x".to_string())), json!({"description": "x"}));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use poem::http::{header, HeaderMap, StatusCode};
+use poem::{Body, Endpoint, IntoResponse, Middleware, Request, Response, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Record,
+    Replay
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ReplayConfig {
+    pub mode: ReplayMode,
+    pub dir: String
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            mode: ReplayMode::Off,
+            dir: "./recordings".to_string()
+        }
+    }
+}
+
+const REDACTED: &str = "[redacted]";
+
+/// Routes whose request bodies carry credentials and are never written out.
+const CREDENTIAL_PATHS: [&str; 2] = ["/login", "/register"];
+
+/// A request and the response it produced, as stored on disk with
+/// credentials redacted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedExchange {
+    pub method: String,
+    pub uri: String,
+    #[serde(default)]
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: String,
+    pub status_code_u16: u16,
+    pub headers: Vec<(String, String)>,
+    pub response_body: String
+}
+
+/// FNV-1a over the request line and body, used as the recording file name.
+/// Unlike `DefaultHasher` it is stable across builds.
+fn fingerprint(method: &str, uri: &str, body: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in method.bytes().chain([b' ']).chain(uri.bytes()).chain([b'\n']).chain(body.iter().copied()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    format!("{:016x}", hash)
+}
+
+fn recorded_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter(|(name, _)| *name != header::CONTENT_LENGTH)
+        .filter_map(|(name, value)| {
+            let value = match *name == header::AUTHORIZATION {
+                true => REDACTED,
+                false => value.to_str().ok()?
+            };
+
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Replaces every `token` field of a json body, however deeply nested.
+fn redact_tokens(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                match name == "token" {
+                    true => *field = Value::String(REDACTED.to_string()),
+                    false => redact_tokens(field)
+                }
+            }
+        },
+        Value::Array(values) => values.iter_mut().for_each(redact_tokens),
+        _ => {}
+    }
+}
+
+fn recorded_body(body: &[u8]) -> String {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact_tokens(&mut value);
+            value.to_string()
+        },
+        Err(_) => String::from_utf8_lossy(body).to_string()
+    }
+}
+
+/// Dev middleware that records every exchange to `dir`, or serves previously
+/// recorded responses without calling the endpoint. Exchanges are matched on
+/// method, uri and request body; headers are not part of the match. Repeats
+/// of the same request are numbered, so a replay serves their responses in
+/// the order they were recorded, and a 404 once they run out.
+#[derive(Clone)]
+pub struct ReplayMiddleware {
+    pub mode: ReplayMode,
+    pub dir: PathBuf,
+    sequence: Arc<Mutex<HashMap<String, usize>>>
+}
+
+impl ReplayMiddleware {
+    pub fn new(config: &ReplayConfig) -> Self {
+        Self { mode: config.mode, dir: PathBuf::from(&config.dir), sequence: Arc::default() }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for ReplayMiddleware {
+    type Output = ReplayMiddlewareImpl<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ReplayMiddlewareImpl { ep, mode: self.mode, dir: self.dir.clone(), sequence: self.sequence.clone() }
+    }
+}
+
+pub struct ReplayMiddlewareImpl<E> {
+    ep: E,
+    mode: ReplayMode,
+    dir: PathBuf,
+    sequence: Arc<Mutex<HashMap<String, usize>>>
+}
+
+impl<E> ReplayMiddlewareImpl<E> {
+    /// The file of the next exchange with `fingerprint`, counting from 0.
+    fn next_path(&self, fingerprint: String) -> PathBuf {
+        let mut sequence = self.sequence.lock().unwrap();
+        let n = sequence.entry(fingerprint.clone()).or_default();
+        let path = self.dir.join(format!("{}-{}.json", fingerprint, n));
+        *n += 1;
+
+        path
+    }
+
+    async fn replay(&self, path: PathBuf) -> Response {
+        let exchange = tokio::fs::read_to_string(&path)
+            .await
+            .ok()
+            .and_then(|contents| serde_json::from_str::<RecordedExchange>(&contents).ok());
+
+        let Some(exchange) = exchange else {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("No recorded response for this request")
+        };
+
+        let mut response = Response::builder()
+            .status(StatusCode::from_u16(exchange.status_code_u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+
+        for (name, value) in &exchange.headers {
+            response = response.header(name.as_str(), value.as_str());
+        }
+
+        response.body(exchange.response_body)
+    }
+
+    async fn record(&self, path: PathBuf, exchange: &RecordedExchange) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(path, serde_json::to_string_pretty(exchange)?).await
+    }
+}
+
+impl<E: Endpoint> Endpoint for ReplayMiddlewareImpl<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> Result<Self::Output> {
+        if self.mode == ReplayMode::Off {
+            return self.ep.call(req).await.map(IntoResponse::into_response)
+        }
+
+        let method = req.method().to_string();
+        let uri = req.uri().to_string();
+        let request_headers = recorded_headers(req.headers());
+        let request_body = req.take_body().into_bytes().await?;
+        let recorded_request_body = match CREDENTIAL_PATHS.contains(&req.uri().path()) {
+            true => REDACTED.to_string(),
+            false => recorded_body(&request_body)
+        };
+        // Hashed as recorded, so credentials cannot be recovered from file names
+        let path = self.next_path(fingerprint(&method, &uri, recorded_request_body.as_bytes()));
+
+        if self.mode == ReplayMode::Replay {
+            return Ok(self.replay(path).await)
+        }
+
+        req.set_body(request_body.clone());
+        let response = self.ep.call(req).await?.into_response();
+
+        let (parts, body) = response.into_parts();
+        let response_body = body.into_bytes().await?;
+
+        let exchange = RecordedExchange {
+            method,
+            uri,
+            request_headers,
+            request_body: recorded_request_body,
+            status_code_u16: parts.status.as_u16(),
+            headers: recorded_headers(&parts.headers),
+            response_body: recorded_body(&response_body)
+        };
+
+        if let Err(err) = self.record(path, &exchange).await {
+            println!("Failed to record exchange: {}", err);
+        }
+
+        Ok(Response::from_parts(parts, Body::from(response_body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use poem::web::Json;
+    use poem::{handler, post, test::TestClient, web::Data, EndpointExt, Route};
+    use serde_json::json;
+
+    use crate::test::async_run_with_file_create_teardown;
+
+    use super::*;
+
+    #[handler]
+    fn echo(body: String, calls: Data<&Arc<AtomicUsize>>) -> String {
+        let n = calls.fetch_add(1, Ordering::SeqCst);
+        format!("echo {} {}", body, n)
+    }
+
+    #[handler]
+    fn login(calls: Data<&Arc<AtomicUsize>>) -> Json<Value> {
+        let n = calls.fetch_add(1, Ordering::SeqCst);
+        Json(json!({ "data": { "token": format!("secret-{}", n) } }))
+    }
+
+    fn client(mode: ReplayMode, dir: &str, calls: Arc<AtomicUsize>) -> TestClient<impl Endpoint> {
+        TestClient::new(
+            Route::new()
+                .at("/", post(echo))
+                .at("/login", post(login))
+                .data(calls)
+                .with(ReplayMiddleware::new(&ReplayConfig { mode, dir: dir.to_string() }))
+        )
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        async_run_with_file_create_teardown(|file_name| {
+            let dir = format!("{}-recordings", file_name);
+            async move {
+                let calls = Arc::new(AtomicUsize::new(0));
+
+                let recorded = client(ReplayMode::Record, &dir, calls.clone()).post("/").body("hello").send().await;
+                recorded.assert_status_is_ok();
+                recorded.assert_text("echo hello 0").await;
+
+                let replayed = client(ReplayMode::Replay, &dir, calls.clone()).post("/").body("hello").send().await;
+                replayed.assert_status_is_ok();
+                replayed.assert_text("echo hello 0").await;
+
+                assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+                let missing = client(ReplayMode::Replay, &dir, calls.clone()).post("/").body("other").send().await;
+                missing.assert_status(StatusCode::NOT_FOUND);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_repeats_replay_in_order() {
+        async_run_with_file_create_teardown(|file_name| {
+            let dir = format!("{}-recordings", file_name);
+            async move {
+                let calls = Arc::new(AtomicUsize::new(0));
+                let recorder = client(ReplayMode::Record, &dir, calls.clone());
+
+                for n in 0..2 {
+                    recorder.post("/").body("again").send().await.assert_text(format!("echo again {}", n)).await;
+                }
+
+                let replayer = client(ReplayMode::Replay, &dir, calls.clone());
+
+                for n in 0..2 {
+                    replayer.post("/").body("again").send().await.assert_text(format!("echo again {}", n)).await;
+                }
+
+                replayer.post("/").body("again").send().await.assert_status(StatusCode::NOT_FOUND);
+                assert_eq!(calls.load(Ordering::SeqCst), 2);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_redacted() {
+        async_run_with_file_create_teardown(|file_name| {
+            let dir = format!("{}-recordings", file_name);
+            async move {
+                let calls = Arc::new(AtomicUsize::new(0));
+
+                let response = client(ReplayMode::Record, &dir, calls.clone())
+                    .post("/login")
+                    .header(header::AUTHORIZATION, "Bearer secret")
+                    .body_json(&json!({ "username": "jane", "password": "secret" }))
+                    .send()
+                    .await;
+                response.json().await.value().object().get("data").object().get("token").assert_string("secret-0");
+
+                let mut entries = std::fs::read_dir(&dir).unwrap();
+                let recording = std::fs::read_to_string(entries.next().unwrap().unwrap().path()).unwrap();
+                assert!(entries.next().is_none());
+                assert!(!recording.contains("secret"));
+
+                let exchange: RecordedExchange = serde_json::from_str(&recording).unwrap();
+                assert_eq!(exchange.request_body, REDACTED);
+                assert!(exchange.request_headers.contains(&("authorization".to_string(), REDACTED.to_string())));
+                assert_eq!(exchange.response_body, json!({ "data": { "token": REDACTED } }).to_string());
+            }
+        }).await;
+    }
+
+    #[test]
+    fn test_fingerprint_is_stable() {
+        assert_eq!(fingerprint("GET", "/items", b""), fingerprint("GET", "/items", b""));
+        assert_ne!(fingerprint("GET", "/items", b""), fingerprint("POST", "/items", b""));
+    }
+}
\ No newline at end of file
@@ -1,7 +1,15 @@
-use poem::{http::StatusCode, Body, IntoResponse, Response};
+use std::sync::Arc;
+
+use poem::{error::ResponseError, http::StatusCode, Body, IntoResponse, Response};
 use serde::Serialize;
 use serde_json::{Map, Value};
 
+use crate::auth::error::AuthError;
+use crate::db::Page;
+use crate::error::AppError;
+use crate::naming::{FieldNaming, Renamed};
+use crate::scim::model::ScimError;
+
 
 #[derive(Serialize)]
 pub struct GenericResponse<T> {
@@ -10,8 +18,10 @@ pub struct GenericResponse<T> {
     pub data: Option<T>
 }
 
+/// Renders the data with the struct field names of the request's
+/// [`FieldNaming::current`] policy.
 impl<T> IntoResponse for GenericResponse<T> 
-    where T: Serialize + Send + Into<Value>
+    where T: Serialize + Send
 {
     fn into_response(self) -> Response {
         let status_code = StatusCode::from_u16(self.status_code_u16)
@@ -24,7 +34,8 @@ impl<T> IntoResponse for GenericResponse<T>
         let mut map = Map::new();
 
         if let Some(data) = self.data {
-            map.insert("data".to_string(), data.into());
+            let data = Renamed { naming: FieldNaming::current(), value: &data };
+            map.insert("data".to_string(), serde_json::to_value(data).unwrap());
         }
 
         if let Some(message) = self.message {
@@ -40,4 +51,51 @@ impl<T> IntoResponse for GenericResponse<T>
 
         response.finish()
     }
+}
+
+/// Renders a page of records that are already json encoded the way a
+/// [`GenericResponse`] holding the page would be, copying the records into
+/// the body as they are.
+pub fn encoded_page_response(page: Page<Arc<[u8]>>) -> Response {
+    let mut body = Vec::with_capacity(page.items.iter().map(|item| item.len() + 1).sum::<usize>() + 64);
+    body.extend_from_slice(br#"{"data":{"items":["#);
+
+    for (i, item) in page.items.iter().enumerate() {
+        if i > 0 {
+            body.push(b',');
+        }
+
+        body.extend_from_slice(item);
+    }
+
+    let next_offset = page.next_offset.map_or("null".to_string(), |offset| offset.to_string());
+    body.extend_from_slice(format!(r#"],"total":{},"next_offset":{}}}}}"#, page.total, next_offset).as_bytes());
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .content_type("application/json")
+        .body(body)
+}
+
+/// Renders any error as a [`GenericResponse`], keeping the challenge headers
+/// of auth failures and the codes of [`AppError`]s. [`ScimError`]s keep the
+/// body layout SCIM clients expect.
+pub fn error_response(err: poem::Error) -> Response {
+    if let Some(auth_error) = AuthError::from_error(&err) {
+        return auth_error.as_response()
+    }
+
+    if let Some(app_error) = err.downcast_ref::<AppError>() {
+        return app_error.as_response()
+    }
+
+    if let Some(scim_error) = err.downcast_ref::<ScimError>() {
+        return scim_error.as_response()
+    }
+
+    GenericResponse::<Value>{ 
+        message: Some(err.to_string()),
+        status_code_u16: err.status().as_u16(),
+        data: None
+    }.into_response()
 }
\ No newline at end of file
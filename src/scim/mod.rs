@@ -0,0 +1,2 @@
+pub mod model;
+pub mod route;
\ No newline at end of file
@@ -0,0 +1,215 @@
+use poem::error::ResponseError;
+use poem::http::StatusCode;
+use poem::{Body, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::auth::model::User;
+use crate::db::DbError;
+use crate::error::AppError;
+
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const PATCH_OP_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+pub const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+/// A permission of the user, named by `value`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entitlement {
+    pub value: String
+}
+
+/// The SCIM view of a [`User`], with its permissions as entitlements. The
+/// password is never returned.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    pub schemas: Vec<String>,
+    pub id: String,
+    pub user_name: String,
+    pub active: bool,
+    pub entitlements: Vec<Entitlement>,
+    pub meta: Value
+}
+
+impl From<User> for ScimUser {
+    fn from(value: User) -> Self {
+        Self {
+            schemas: vec![USER_SCHEMA.to_string()],
+            id: value.id.to_string(),
+            user_name: value.username,
+            active: true,
+            entitlements: value.permissions.into_iter().map(|value| Entitlement { value }).collect(),
+            meta: json!({ "resourceType": "User", "location": format!("/scim/v2/Users/{}", value.id) })
+        }
+    }
+}
+
+impl ScimUser {
+    pub fn into_response(self, status: StatusCode) -> Response {
+        Response::builder()
+            .status(status)
+            .content_type(SCIM_CONTENT_TYPE)
+            .body(Body::from_json(self).unwrap())
+    }
+}
+
+/// Body of a create request. Users provisioned without a password can only
+/// log in through a provider that does not check it locally, such as LDAP.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateUserBody {
+    pub user_name: String,
+    pub password: Option<String>,
+    #[serde(default)]
+    pub entitlements: Vec<Entitlement>
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatchBody {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>
+}
+
+/// One operation of a PATCH. `userName`, `password` and `entitlements` can be
+/// targeted, by `path` or, without one, as keys of an object `value`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<Value>
+}
+
+impl PatchOperation {
+    pub fn apply(&self, user: &mut User) -> Result<(), ScimError> {
+        let op = self.op.to_lowercase();
+
+        if !["add", "replace", "remove"].contains(&op.as_str()) {
+            return Err(ScimError::bad_request("invalidSyntax", format!("Unknown operation {}", self.op)))
+        }
+
+        match (&self.path, &self.value) {
+            (Some(path), value) => apply_to(user, &op, path, value.as_ref()),
+            (None, Some(Value::Object(fields))) if op != "remove" => {
+                for (path, value) in fields {
+                    apply_to(user, &op, path, Some(value))?;
+                }
+
+                Ok(())
+            },
+            _ => Err(ScimError::bad_request("noTarget", "Operation needs a path or an object value".to_string()))
+        }
+    }
+}
+
+fn string_value(path: &str, value: Option<&Value>) -> Result<String, ScimError> {
+    value
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or(ScimError::bad_request("invalidValue", format!("{} must be a string", path)))
+}
+
+fn entitlement_values(value: Option<&Value>) -> Result<Vec<String>, ScimError> {
+    let entitlements: Vec<Entitlement> = match value {
+        Some(array @ Value::Array(_)) => serde_json::from_value(array.clone()),
+        Some(single) => serde_json::from_value(single.clone()).map(|x| vec![x]),
+        None => Ok(vec![])
+    }
+    .map_err(|err| ScimError::bad_request("invalidValue", format!("Malformed entitlements: {}", err)))?;
+
+    Ok(entitlements.into_iter().map(|x| x.value).collect())
+}
+
+/// Attribute names are case insensitive in SCIM, so `path` is matched
+/// regardless of case.
+fn apply_to(user: &mut User, op: &str, path: &str, value: Option<&Value>) -> Result<(), ScimError> {
+    match (path.to_lowercase().as_str(), op) {
+        ("username", "add" | "replace") => user.username = string_value(path, value)?,
+        ("password", "add" | "replace") => user.password = string_value(path, value)?,
+        ("entitlements", "replace") => user.permissions = entitlement_values(value)?,
+        ("entitlements", "add") => {
+            for permission in entitlement_values(value)? {
+                if !user.permissions.contains(&permission) {
+                    user.permissions.push(permission);
+                }
+            }
+        },
+        ("entitlements", "remove") if value.is_none() => user.permissions.clear(),
+        ("entitlements", "remove") => {
+            let removed = entitlement_values(value)?;
+            user.permissions.retain(|permission| !removed.contains(permission));
+        },
+        _ => return Err(ScimError::bad_request("invalidPath", format!("Cannot {} {}", op, path)))
+    }
+
+    Ok(())
+}
+
+/// An error in the body layout SCIM clients expect, with `scimType` set for
+/// the 400 and 409 cases RFC 7644 names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScimError {
+    pub status: StatusCode,
+    pub scim_type: Option<&'static str>,
+    pub detail: String
+}
+
+impl ScimError {
+    pub fn bad_request(scim_type: &'static str, detail: String) -> Self {
+        Self { status: StatusCode::BAD_REQUEST, scim_type: Some(scim_type), detail }
+    }
+}
+
+impl std::fmt::Display for ScimError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.detail)
+    }
+}
+
+impl std::error::Error for ScimError {}
+
+impl ResponseError for ScimError {
+    fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    fn as_response(&self) -> Response {
+        let detail = match self.status.is_server_error() {
+            true => "Internal server error".to_string(),
+            false => self.detail.clone()
+        };
+
+        let mut body = json!({
+            "schemas": [ERROR_SCHEMA],
+            "status": self.status.as_u16().to_string(),
+            "detail": detail
+        });
+
+        if let Some(scim_type) = self.scim_type {
+            body["scimType"] = json!(scim_type);
+        }
+
+        Response::builder()
+            .status(self.status)
+            .content_type(SCIM_CONTENT_TYPE)
+            .body(Body::from_json(body).unwrap())
+    }
+}
+
+impl From<AppError> for ScimError {
+    fn from(value: AppError) -> Self {
+        let scim_type = match &value {
+            AppError::Db(DbError::UniqueViolation { .. }) => Some("uniqueness"),
+            AppError::Db(DbError::Validation { .. }) => Some("invalidValue"),
+            _ => None
+        };
+
+        let status = match scim_type {
+            Some("invalidValue") => StatusCode::BAD_REQUEST,
+            _ => value.status()
+        };
+
+        Self { status, scim_type, detail: value.to_string() }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,348 @@
+use poem::http::StatusCode;
+use poem::web::{Data, Json, Path};
+use poem::{get, handler, post, Response, Route};
+use uuid::Uuid;
+
+use super::model::{CreateUserBody, PatchBody, ScimError, ScimUser};
+use crate::auth::error::missing_provision;
+use crate::auth::model::User;
+use crate::auth::policy::CredentialPolicy;
+use crate::auth::route::USER_TABLE_NAME;
+use crate::db::DbHandle;
+use crate::error::{AppError, Context};
+
+
+/// Fails with `invalidValue` listing what `policy` finds wrong with the
+/// credentials, limited to `fields`.
+fn check_policy(policy: &CredentialPolicy, user_name: &str, password: &str, fields: &[&str]) -> Result<(), ScimError> {
+    let violations: Vec<String> = policy
+        .check(user_name, password)
+        .into_iter()
+        .filter(|x| fields.contains(&x.field.as_str()))
+        .map(|x| format!("{}: {}", x.field, x.message))
+        .collect();
+
+    if !violations.is_empty() {
+        return Err(ScimError::bad_request("invalidValue", violations.join("; ")))
+    }
+
+    Ok(())
+}
+
+#[poem_grants::protect("PROVISION", error = missing_provision)]
+#[handler]
+async fn create_user(Json(payload): Json<CreateUserBody>, db: Data<&DbHandle>, policy: Data<&CredentialPolicy>) -> Result<Response, ScimError> {
+    // A generated password is not the client's to fix, so only a supplied one is checked
+    let supplied = payload.password.is_some();
+    let password = payload.password.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let fields: &[&str] = match supplied {
+        true => &["username", "password"],
+        false => &["username"]
+    };
+    check_policy(&policy, &payload.user_name, &password, fields)?;
+
+    let permissions = payload.entitlements.into_iter().map(|x| x.value).collect();
+
+    let mut db_ref = db.write().await;
+    let user = db_ref
+        .transaction(|tx| {
+            let id = tx.get_increment_last_id(USER_TABLE_NAME.to_string())?;
+            // Skipping hashing of password, like registration does
+            let to_insert = User::new(id, payload.user_name, password, permissions);
+            tx.insert_or_update(USER_TABLE_NAME.to_string(), id, to_insert)
+        })
+        .await
+        .context("Provisioning user")?;
+
+    Ok(ScimUser::from(user).into_response(StatusCode::CREATED))
+}
+
+#[poem_grants::protect("PROVISION", error = missing_provision)]
+#[handler]
+async fn get_user(Path(id): Path<u32>, db: Data<&DbHandle>) -> Result<Response, ScimError> {
+    let db_ref = db.read().await;
+    let user = db_ref
+        .table::<User>(USER_TABLE_NAME)
+        .get(id)
+        .ok_or(AppError::NotFound)?;
+
+    Ok(ScimUser::from(user).into_response(StatusCode::OK))
+}
+
+#[poem_grants::protect("PROVISION", error = missing_provision)]
+#[handler]
+async fn patch_user(Path(id): Path<u32>, Json(payload): Json<PatchBody>, db: Data<&DbHandle>, policy: Data<&CredentialPolicy>) -> Result<Response, ScimError> {
+    let mut db_ref = db.write().await;
+    let mut users = db_ref.table_mut::<User>(USER_TABLE_NAME);
+    let stored = users.get(id).ok_or(AppError::NotFound)?;
+    let mut user = stored.clone();
+
+    for operation in &payload.operations {
+        operation.apply(&mut user)?;
+    }
+
+    // Like on creation, only the credentials the client changed are checked
+    let mut changed = vec![];
+
+    if user.username != stored.username {
+        changed.push("username");
+    }
+
+    if user.password != stored.password {
+        changed.push("password");
+    }
+
+    check_policy(&policy, &user.username, &user.password, &changed)?;
+
+    let user = users
+        .update(id, user)
+        .await
+        .context("Patching provisioned user")?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(ScimUser::from(user).into_response(StatusCode::OK))
+}
+
+/// Deprovisions the user. Tokens already issued to them stay valid until
+/// they expire.
+#[poem_grants::protect("PROVISION", error = missing_provision)]
+#[handler]
+async fn delete_user(Path(id): Path<u32>, db: Data<&DbHandle>) -> Result<Response, ScimError> {
+    let mut db_ref = db.write().await;
+    db_ref
+        .table_mut::<User>(USER_TABLE_NAME)
+        .delete(id)
+        .await
+        .context("Deprovisioning user")?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(Response::builder().status(StatusCode::NO_CONTENT).finish())
+}
+
+/// The SCIM 2.0 `Users` resource, meant to be nested under `/scim/v2`.
+pub fn scim_routes() -> Route {
+    Route::new()
+        .at("/Users", post(create_user))
+        .at("/Users/:id", get(get_user).patch(patch_user).delete(delete_user))
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::Method;
+    use poem::Endpoint;
+    use serde_json::json;
+
+    use crate::scim::model::{PATCH_OP_SCHEMA, USER_SCHEMA};
+    use crate::test::{async_run_with_file_create_teardown, ApiTestClient, PermissionCase, TEST_PERMISSION};
+
+    use super::*;
+
+    async fn init_client(file_name: String) -> ApiTestClient<impl Endpoint> {
+        let routes = Route::new().nest("/scim/v2", scim_routes());
+        let test_client = ApiTestClient::init(routes, file_name.as_str()).await;
+        {
+            let mut db = test_client.db.write().await;
+            db.add_table(USER_TABLE_NAME.to_string(), false).await.unwrap();
+            db.add_unique_constraint(USER_TABLE_NAME.to_string(), "username".to_string()).unwrap();
+        }
+
+        test_client
+    }
+
+    fn provision_token(test_client: &ApiTestClient<impl Endpoint>) -> String {
+        let jwt_data = test_client.jwt_manager.create_token_data("idp".to_string(), vec!["PROVISION".to_string()]);
+        format!("Bearer {}", test_client.jwt_manager.encode(jwt_data).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_permissions() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+
+                let permission_sets: &[Option<&[&str]>] = &[None, Some(&[TEST_PERMISSION]), Some(&["PROVISION"])];
+                let cases = [
+                    PermissionCase {
+                        method: Method::POST,
+                        uri: "/scim/v2/Users",
+                        body: Some(json!({"schemas": [USER_SCHEMA], "userName": "jane"})),
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::CREATED]
+                    },
+                    PermissionCase {
+                        method: Method::GET,
+                        uri: "/scim/v2/Users/1",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::OK]
+                    },
+                    PermissionCase {
+                        method: Method::DELETE,
+                        uri: "/scim/v2/Users/1",
+                        body: None,
+                        expected: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN, StatusCode::NO_CONTENT]
+                    }
+                ];
+                test_client.assert_permission_matrix(permission_sets, &cases).await;
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_provision_lifecycle() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                let token = provision_token(&test_client);
+
+                let body = json!({"userName": "jane", "password": "correct horse", "entitlements": [{"value": "MUTATE"}]});
+                let response = test_client.client.post("/scim/v2/Users")
+                    .header("Authorization", token.clone())
+                    .body_json(&body)
+                    .send()
+                    .await;
+                response.assert_status(StatusCode::CREATED);
+                response.assert_content_type("application/scim+json");
+                let json = response.json().await;
+                let user = json.value().object();
+                user.get("id").assert_string("1");
+                user.get("userName").assert_string("jane");
+                user.get("active").assert_bool(true);
+                assert!(user.get_opt("password").is_none());
+
+                let response = test_client.client.post("/scim/v2/Users")
+                    .header("Authorization", token.clone())
+                    .body_json(&body)
+                    .send()
+                    .await;
+                response.assert_status(StatusCode::CONFLICT);
+                response.json().await.value().object().get("scimType").assert_string("uniqueness");
+
+                let patch = json!({
+                    "schemas": [PATCH_OP_SCHEMA],
+                    "Operations": [
+                        {"op": "replace", "path": "userName", "value": "jane.doe"},
+                        {"op": "add", "path": "entitlements", "value": [{"value": "ADMIN"}]}
+                    ]
+                });
+                let response = test_client.client.patch("/scim/v2/Users/1")
+                    .header("Authorization", token.clone())
+                    .body_json(&patch)
+                    .send()
+                    .await;
+                response.assert_status_is_ok();
+                let json = response.json().await;
+                let user = json.value().object();
+                user.get("userName").assert_string("jane.doe");
+                user.get("entitlements").array().assert_len(2);
+
+                let stored = test_client.db.read().await.find_by_id::<User>(USER_TABLE_NAME.to_string(), 1).unwrap();
+                assert_eq!(stored.password, "correct horse");
+                assert_eq!(stored.permissions, vec!["MUTATE".to_string(), "ADMIN".to_string()]);
+
+                let unknown = json!({"Operations": [{"op": "replace", "path": "nickName", "value": "jd"}]});
+                let response = test_client.client.patch("/scim/v2/Users/1")
+                    .header("Authorization", token.clone())
+                    .body_json(&unknown)
+                    .send()
+                    .await;
+                response.assert_status(StatusCode::BAD_REQUEST);
+                response.json().await.value().object().get("scimType").assert_string("invalidPath");
+
+                test_client.client.delete("/scim/v2/Users/1")
+                    .header("Authorization", token.clone())
+                    .send()
+                    .await
+                    .assert_status(StatusCode::NO_CONTENT);
+
+                let response = test_client.client.get("/scim/v2/Users/1")
+                    .header("Authorization", token)
+                    .send()
+                    .await;
+                response.assert_status(StatusCode::NOT_FOUND);
+                response.json().await.value().object().get("status").assert_string("404");
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_create_user_checks_policy() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                let token = provision_token(&test_client);
+
+                for body in [
+                    json!({"userName": "jo"}),
+                    json!({"userName": "bad name!"}),
+                    json!({"userName": "jane", "password": "short"}),
+                    json!({"userName": "jane", "password": "letmein1"})
+                ] {
+                    let response = test_client.client.post("/scim/v2/Users")
+                        .header("Authorization", token.clone())
+                        .body_json(&body)
+                        .send()
+                        .await;
+                    response.assert_status(StatusCode::BAD_REQUEST);
+                    response.json().await.value().object().get("scimType").assert_string("invalidValue");
+                }
+
+                assert_eq!(test_client.db.read().await.count(USER_TABLE_NAME.to_string()), Some(0));
+
+                let response = test_client.client.post("/scim/v2/Users")
+                    .header("Authorization", token.clone())
+                    .body_json(&json!({"userName": "jane"}))
+                    .send()
+                    .await;
+                response.assert_status(StatusCode::CREATED);
+            }
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn test_patch_user_checks_policy() {
+        async_run_with_file_create_teardown(|file_name| {
+            let file_name = file_name.to_string();
+            async {
+                let test_client = init_client(file_name).await;
+                let token = provision_token(&test_client);
+
+                test_client.client.post("/scim/v2/Users")
+                    .header("Authorization", token.clone())
+                    .body_json(&json!({"userName": "jane", "password": "correct horse"}))
+                    .send()
+                    .await
+                    .assert_status(StatusCode::CREATED);
+
+                for operations in [
+                    json!([{"op": "replace", "path": "password", "value": "short"}]),
+                    json!([{"op": "replace", "path": "userName", "value": "bad name!"}]),
+                    json!([{"op": "replace", "value": {"userName": "jane", "password": "letmein1"}}]),
+                    json!([{"op": "Replace", "path": "PASSWORD", "value": "jane"}])
+                ] {
+                    let response = test_client.client.patch("/scim/v2/Users/1")
+                        .header("Authorization", token.clone())
+                        .body_json(&json!({"schemas": [PATCH_OP_SCHEMA], "Operations": operations}))
+                        .send()
+                        .await;
+                    response.assert_status(StatusCode::BAD_REQUEST);
+                    response.json().await.value().object().get("scimType").assert_string("invalidValue");
+                }
+
+                let stored = test_client.db.read().await.find_by_id::<User>(USER_TABLE_NAME.to_string(), 1).unwrap();
+                assert_eq!(stored.username, "jane");
+                assert_eq!(stored.password, "correct horse");
+
+                let patch = json!({"Operations": [{"op": "replace", "path": "USERNAME", "value": "jane.doe"}]});
+                let response = test_client.client.patch("/scim/v2/Users/1")
+                    .header("Authorization", token)
+                    .body_json(&patch)
+                    .send()
+                    .await;
+                response.assert_status_is_ok();
+                response.json().await.value().object().get("userName").assert_string("jane.doe");
+            }
+        }).await;
+    }
+}
\ No newline at end of file
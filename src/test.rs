@@ -1,9 +1,9 @@
 use std::future::Future;
 use std::panic::{self, AssertUnwindSafe};
 use std::fs::File;
-use std::sync::{Arc, Mutex};
 
 use futures::FutureExt;
+use poem::http::{Method, StatusCode};
 use poem::middleware::{AddData, Middleware};
 use poem::test::TestClient;
 use poem::{Endpoint, EndpointExt, IntoEndpoint};
@@ -11,16 +11,37 @@ use serde_json::Value;
 use uuid::Uuid;
 
 use crate::auth;
-use crate::db::Db;
-use crate::response::GenericResponse;
+use crate::auth::challenge::{leading_zero_bits, ChallengeGuard};
+use crate::auth::policy::CredentialPolicy;
+use crate::auth::provider::Authenticator;
+use crate::db::{Db, DbHandle};
+use crate::response::error_response;
 
 
 pub static TEST_FILE_NAME: &str = "test-data.json";
 pub const TEST_USERNAME: &str = "username";
-pub const TEST_PASSWORD: &str = "password";
+pub const TEST_PASSWORD: &str = "test-password";
 pub const TEST_PERMISSION: &str = "MUTATE";
 
 
+/// Removes the test file along with any sibling files the db derived from it.
+fn remove_test_files(file_name: &str) {
+    let prefix = file_name.trim_start_matches("./");
+
+    if let Ok(entries) = std::fs::read_dir(".") {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(prefix) {
+                let path = entry.path();
+
+                let _ = match path.is_dir() {
+                    true => std::fs::remove_dir_all(path),
+                    false => std::fs::remove_file(path)
+                };
+            }
+        }
+    }
+}
+
 pub fn run_with_file_create_teardown<T>(test: T)
     where T: FnOnce(&str) + panic::UnwindSafe
 {
@@ -34,12 +55,24 @@ pub fn run_with_file_create_teardown<T>(test: T)
         test(file_name_str)
     });
 
-    let _ = std::fs::remove_file(file_name);
+    remove_test_files(file_name_str);
 
     assert!(result.is_ok())
 }
 
 
+/// Brute forces an answer to a challenge issued by
+/// [`auth::challenge::ProofOfWork`].
+pub fn solve_proof_of_work(issued: &Value) -> String {
+    let nonce = issued["nonce"].as_str().unwrap();
+    let difficulty = issued["difficulty"].as_u64().unwrap() as u32;
+
+    (0u64..)
+        .map(|suffix| format!("{}:{}", nonce, suffix))
+        .find(|answer| leading_zero_bits(answer) >= difficulty)
+        .unwrap()
+}
+
 pub async fn async_run_with_file_create_teardown<T, U>(test: T)
     where T: FnOnce(&str) -> U + panic::UnwindSafe,
         U: Future<Output = ()>
@@ -54,24 +87,33 @@ pub async fn async_run_with_file_create_teardown<T, U>(test: T)
         .catch_unwind()
         .await;
 
-    let _ = std::fs::remove_file(file_name);
+    remove_test_files(file_name_str);
 
     assert!(result.is_ok())
 }
 
+/// A request sent once per permission set of a matrix, with the status
+/// expected for each set in the same order.
+pub struct PermissionCase {
+    pub method: Method,
+    pub uri: &'static str,
+    pub body: Option<Value>,
+    pub expected: Vec<StatusCode>
+}
+
 pub struct ApiTestClient<E> {
-    pub db: Arc<Mutex<Db>>,
+    pub db: DbHandle,
     pub client: TestClient<E>,
     pub jwt_manager: auth::jwt::Manager,
     pub token: String
 }
 
 impl<E: Endpoint + EndpointExt> ApiTestClient<E> {
-    pub fn init<T>(route: T, file_name: &str) -> ApiTestClient<impl Endpoint + EndpointExt> 
+    pub async fn init<T>(route: T, file_name: &str) -> ApiTestClient<impl Endpoint + EndpointExt> 
         where T: IntoEndpoint<Endpoint = E>
     {
-        let db = Db::init(file_name.to_string()).unwrap();
-        let arc_db = Arc::new(Mutex::new(db));
+        let db = Db::init(file_name.to_string()).await.unwrap();
+        let db_handle = DbHandle::new(db);
         
         let jwt_manager = auth::jwt::Manager::init("secret".to_string(), 24);
         let jwt_middleware = auth::middleware::JwtMiddleware{ manager: jwt_manager.clone() };
@@ -82,23 +124,56 @@ impl<E: Endpoint + EndpointExt> ApiTestClient<E> {
         route
             .with(
     jwt_middleware
-                    .combine(AddData::new(arc_db.clone()))
+                    .combine(AddData::new(db_handle.clone()))
                     .combine(AddData::new(jwt_manager.clone()))
+                    .combine(AddData::new(CredentialPolicy::default()))
+                    .combine(AddData::new(ChallengeGuard::default()))
+                    .combine(AddData::new(Authenticator::default()))
             )
-            .catch_all_error(|err| async move {
-                GenericResponse::<Value>{ 
-                    message: Some(err.to_string()),
-                    status_code_u16: err.status().as_u16(),
-                    data: None
-                }
-            })
+            .catch_all_error(|err| async move { error_response(err) })
         );
 
         ApiTestClient {
-            db: arc_db,
+            db: db_handle,
             jwt_manager,
             client,
             token
         }
     }
+}
+
+impl<E: Endpoint> ApiTestClient<E> {
+    /// Sends every case with a token for each permission set, `None` meaning no
+    /// token at all, and fails listing every status that was not expected.
+    /// Cases run in order, so earlier mutations are visible to later cases.
+    pub async fn assert_permission_matrix(&self, permission_sets: &[Option<&[&str]>], cases: &[PermissionCase]) {
+        let mut mismatches = vec![];
+
+        for case in cases {
+            assert_eq!(case.expected.len(), permission_sets.len(), "{} {} needs a status per permission set", case.method, case.uri);
+
+            for (permissions, expected) in permission_sets.iter().zip(&case.expected) {
+                let mut request = self.client.request(case.method.clone(), case.uri);
+
+                if let Some(permissions) = permissions {
+                    let permissions = permissions.iter().map(|permission| permission.to_string()).collect();
+                    let jwt_data = self.jwt_manager.create_token_data(TEST_USERNAME.to_string(), permissions);
+                    let token = self.jwt_manager.encode(jwt_data).unwrap();
+                    request = request.header("Authorization", format!("Bearer {}", token));
+                }
+
+                if let Some(body) = &case.body {
+                    request = request.body_json(body);
+                }
+
+                let status = request.send().await.0.status();
+
+                if status != *expected {
+                    mismatches.push(format!("{} {} with {:?}: expected {}, got {}", case.method, case.uri, permissions, expected, status));
+                }
+            }
+        }
+
+        assert!(mismatches.is_empty(), "Permission matrix mismatches:\n{}", mismatches.join("\n"));
+    }
 }
\ No newline at end of file